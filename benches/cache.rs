@@ -0,0 +1,33 @@
+//! the challenge's self-test up to its first input prompt, with and without the instruction cache and superinstructions
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use synacor::console::Pipe;
+use synacor::debug::Meta;
+use synacor::opcode;
+use synacor::peephole;
+use synacor::vm::State;
+
+fn self_test(program: &[u8], cache: bool, fused: bool) -> usize {
+    let mut state = State::new(program.to_vec());
+    state.cache.enabled = cache;
+    let mut meta = Meta::new();
+    meta.io = Box::new(Pipe::default()); // no input, so the run stops at the first `in`
+    while !meta.halt && !meta.debugging {
+        if !fused || peephole::run(&mut state, &mut meta).is_none() {
+            opcode::step(&mut state, &mut meta);
+        }
+    }
+    meta.op_count
+}
+
+fn bench(c: &mut Criterion) {
+    let program = std::fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/challenge.bin")).unwrap();
+    let mut group = c.benchmark_group("self-test");
+    group.bench_function("decode every step", |b| b.iter(|| self_test(&program, false, false)));
+    group.bench_function("cached", |b| b.iter(|| self_test(&program, true, false)));
+    group.bench_function("superinstructions", |b| b.iter(|| self_test(&program, true, true)));
+    group.finish();
+}
+
+criterion_group!(benches, bench);
+criterion_main!(benches);
@@ -0,0 +1,119 @@
+//! interpreter throughput on small synthetic programs and on the challenge itself, in instructions per second
+
+#[path = "../tests/common/mod.rs"]
+mod common;
+
+use common::{bytes, challenge, FIB, R0, R1, R2, R3};
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use nix::unistd::{close, dup, dup2};
+use std::fs::OpenOptions;
+use std::os::unix::io::AsRawFd;
+use std::time::{Duration, Instant};
+use synacor::console::{Io, Pipe, Terminal};
+use synacor::debug::Meta;
+use synacor::opcode;
+use synacor::vm::State;
+
+const R4: u16 = 32772;
+const R5: u16 = 32773;
+const R6: u16 = 32774;
+
+/// enough instructions of the endless programs to be worth timing
+const LIMIT: usize = 1 << 20;
+
+/// walks from the start to the twisty passages, the guest stops for the debugger once it runs out
+const WALK: &str = "take tablet\nuse tablet\ngo doorway\ngo north\ngo north\ngo bridge\ngo continue\ngo down\ngo east\n\
+                    take empty lantern\ngo west\ngo west\ngo passage\ngo ladder\n";
+
+/// noops and a jump back, nothing but fetch and dispatch
+fn dispatch() -> Vec<u16> {
+    let mut program = vec![21; 255];
+    program.extend([6, 0]);
+    program
+}
+
+/// every arithmetic and logic opcode in a loop that runs until r0 wraps around to zero
+fn arithmetic() -> Vec<u16> {
+    vec![
+        1, R0, 0, 9, R1, R1, R0, 10, R2, R1, 31, 11, R3, R2, 1021, 12, R4, R3, R1, 13, R5, R4, R2, 14, R6, R5, 9, R0, R0,
+        1, 7, R0, 3, 0,
+    ]
+}
+
+/// a thousand lines of forty characters
+fn output() -> Vec<u16> {
+    vec![
+        1, R0, 0, 1, R1, 0, 19, 65, 9, R1, R1, 1, 4, R2, R1, 40, 8, R2, 6, 19, 10, 9, R0, R0, 1, 4, R2, R0, 1000, 8, R2, 3,
+        0,
+    ]
+}
+
+/// how guest output used to reach stdout, one print per byte
+struct Unbuffered;
+
+impl Io for Unbuffered {
+    fn read(&mut self) -> Option<u8> {
+        None
+    }
+
+    fn write(&mut self, byte: u8) {
+        print!("{}", byte as char);
+    }
+}
+
+/// run until halt, until the script runs dry at an `in`, or for LIMIT instructions
+fn run(program: &[u8], script: &str) -> usize {
+    run_with(program, Box::new(Pipe::script(script.as_bytes())))
+}
+
+fn run_with(program: &[u8], io: Box<dyn Io>) -> usize {
+    let mut state = State::new(program.to_vec());
+    let mut meta = Meta::new();
+    meta.io = io;
+    while !meta.halt && !meta.debugging && meta.op_count < LIMIT {
+        opcode::step(&mut state, &mut meta);
+    }
+    meta.op_count
+}
+
+fn bench(c: &mut Criterion) {
+    let challenge = challenge();
+    let cases = [
+        ("dispatch", bytes(&dispatch()), ""),
+        ("arithmetic", bytes(&arithmetic()), ""),
+        ("recursion", bytes(&FIB), ""),
+        ("self-test", challenge.clone(), ""),
+        ("scripted walk", challenge, WALK),
+    ];
+
+    let mut group = c.benchmark_group("interpreter");
+    for (name, program, script) in cases.iter() {
+        group.throughput(Throughput::Elements(run(program, script) as u64));
+        group.bench_function(*name, |b| b.iter(|| run(program, script)));
+    }
+    group.finish();
+
+    // the real stdout, pointed at /dev/null while the clock runs so the report stays readable
+    let program = bytes(&output());
+    let null = OpenOptions::new().write(true).open("/dev/null").unwrap();
+    let timed = |iterations: u64, io: &dyn Fn() -> Box<dyn Io>| -> Duration {
+        let stdout = dup(1).unwrap();
+        dup2(null.as_raw_fd(), 1).unwrap();
+        let start = Instant::now();
+        for _ in 0..iterations {
+            run_with(&program, io());
+        }
+        let elapsed = start.elapsed();
+        dup2(stdout, 1).unwrap();
+        close(stdout).unwrap();
+        elapsed
+    };
+    let mut group = c.benchmark_group("output");
+    group.throughput(Throughput::Bytes(41 * 1000));
+    group.bench_function("print per byte", |b| b.iter_custom(|n| timed(n, &|| Box::new(Unbuffered))));
+    group.bench_function("buffered terminal", |b| b.iter_custom(|n| timed(n, &|| Box::new(Terminal::new()))));
+    group.finish();
+}
+
+criterion_group!(benches, bench);
+criterion_main!(benches);
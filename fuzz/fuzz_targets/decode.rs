@@ -0,0 +1,18 @@
+//! every decoder at every offset of arbitrary bytes, odd ones and ones past the end included
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use synacor::cache::Instruction;
+use synacor::opcode::{disassemble, length, parse};
+
+fuzz_target!(|data: &[u8]| {
+    for ip in 0..data.len() + 8 {
+        let code = parse(data, &ip);
+        let instruction = Instruction::decode(data, ip);
+        assert_eq!(instruction.code(), synacor::opcode::lookup(instruction.op));
+        let (_, words) = disassemble(data, ip);
+        assert_eq!(words, length(data, ip));
+        let _ = code.mnemonic();
+    }
+});
@@ -0,0 +1,37 @@
+//! arbitrary images run for a bounded number of instructions, plain and with superinstructions
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use synacor::console::Pipe;
+use synacor::debug::Meta;
+use synacor::opcode;
+use synacor::peephole;
+use synacor::vm::State;
+
+/// instructions per run, enough for loops to go around a few times
+const LIMIT: usize = 10_000;
+
+fn run(image: &[u8], input: &[u8], fused: bool) {
+    let Ok(mut state) = State::recover(image.to_vec()) else {
+        return;
+    };
+    let mut meta = Meta::new();
+    meta.io = Box::new(Pipe::script(input));
+    while !meta.halt && !meta.debugging && meta.op_count < LIMIT {
+        if !fused || peephole::run(&mut state, &mut meta).is_none() {
+            opcode::step(&mut state, &mut meta);
+        }
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    // the first byte says how much of the tail is guest input
+    let Some((split, rest)) = data.split_first() else {
+        return;
+    };
+    let split = rest.len().saturating_sub(*split as usize);
+    let (image, input) = rest.split_at(split);
+    run(image, input, false);
+    run(image, input, true);
+});
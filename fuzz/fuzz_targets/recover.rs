@@ -0,0 +1,16 @@
+//! any file handed to `State::recover`, a program, a save or a legacy save, has to load or fail cleanly
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use synacor::vm::State;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(state) = State::recover(data.to_vec()) {
+        // what loaded has to save and load again
+        let save = State::save(&state);
+        let again = State::recover(save).expect("a save of a recovered state loads");
+        assert_eq!(again.register, state.register);
+        assert_eq!(again.stack, state.stack);
+    }
+});
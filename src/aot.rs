@@ -0,0 +1,408 @@
+use crate::cache::Instruction;
+use crate::console::Io;
+use crate::debug::coverage::{Coverage, EXECUTED};
+use crate::debug::{Meta, Reason};
+use crate::machine::Frame;
+use crate::opcode::{self, disassemble, step, value, word, Code, Flow, Operand};
+use crate::vm::{BoxResult, State};
+use std::collections::{BTreeSet, HashSet};
+use std::fmt::Write;
+
+/// 32768 words
+const WORDS: usize = 0x8000;
+
+/// image bytes per line of the generated byte string
+const ROW: usize = 24;
+
+/// what a translated module runs against: the machine, the interpreter for everything that was not translated or
+/// was rewritten since, and the guest's I/O
+pub struct Runtime {
+    pub state: State,
+    pub meta: Meta,
+    /// blocks covering each word, by their first word
+    owners: Vec<Vec<usize>>,
+    /// blocks the guest wrote into, interpreted from then on
+    stale: Vec<bool>,
+}
+
+impl Runtime {
+    /// the machine as the image starts it, blocks are the word address and length of every translated block
+    pub fn new(image: &[u8], blocks: &[(usize, usize)]) -> BoxResult<Runtime> {
+        let mut owners = vec![Vec::new(); WORDS];
+        for &(start, len) in blocks {
+            for word in start..start + len {
+                if let Some(owner) = owners.get_mut(word) {
+                    owner.push(start);
+                }
+            }
+        }
+        Ok(Runtime {
+            state: State::recover(image.to_vec())?,
+            meta: Meta::new(),
+            owners,
+            stale: vec![false; WORDS],
+        })
+    }
+
+    /// the block to run at ip, None when there is only the interpreter for it
+    pub fn block(&self) -> Option<usize> {
+        let word = self.state.ip / 2;
+        match self.state.ip.is_multiple_of(2) && !self.stale.get(word).copied().unwrap_or(true) {
+            true => Some(word),
+            false => None,
+        }
+    }
+
+    /// interpret one instruction, noting writes into translated code
+    pub fn step(&mut self) {
+        let instruction = self.state.cache.fetch(&self.state.program, self.state.ip);
+        if instruction.code() == Code::WriteMemory {
+            let address = opcode::address(value(&self.state, instruction.args[0]));
+            self.spoil(address / 2);
+        }
+        step(&mut self.state, &mut self.meta);
+    }
+
+    fn spoil(&mut self, word: usize) {
+        if let Some(owners) = self.owners.get(word) {
+            for start in owners {
+                self.stale[*start] = true;
+            }
+        }
+    }
+
+    /// hand the instruction at the word address to the interpreter, for what a block cannot do itself
+    pub fn bail(&mut self, at: usize) {
+        self.state.ip = at * 2;
+        self.step();
+    }
+
+    /// rmem, memory past the end of the image reads as zero
+    pub fn read(&self, address: u16) -> u16 {
+        word(&self.state.program, opcode::address(address))
+    }
+
+    /// wmem, the blocks under the word are not run again
+    pub fn write(&mut self, address: u16, b: u16) {
+        let a = opcode::address(address);
+        if self.state.program.len() < a + 2 {
+            self.state.program.resize(a + 2, 0);
+        }
+        self.state.program[a + 1] = (b >> 8) as u8;
+        self.state.program[a] = b as u8;
+        self.state.cache.invalidate(a);
+        self.spoil(a / 2);
+    }
+
+    /// call, at and next are the word addresses of the call and of the instruction after it, to is a byte address
+    pub fn call(&mut self, at: usize, next: u16, to: usize) {
+        self.state.stack.push(next);
+        self.state.frames.push(Frame {
+            caller: at * 2,
+            callee: to,
+            depth: self.state.stack.len(),
+        });
+        self.state.ip = to;
+    }
+
+    /// ret, the interpreter takes over on an empty stack, at is the ret's own word address
+    pub fn ret(&mut self, at: usize) {
+        match self.state.stack.pop() {
+            Some(address) => {
+                self.state.ip = address as usize * 2;
+                self.state.unwind();
+            }
+            None => {
+                self.meta.op_count -= 1;
+                self.bail(at);
+            }
+        }
+    }
+
+    pub fn out(&mut self, a: u16) {
+        self.meta.io.write(a as u8);
+    }
+
+    /// in, false when there is no input and the guest stops for the debugger like it would interpreted
+    pub fn input(&mut self, register: usize) -> bool {
+        self.meta.io.flush();
+        match self.meta.io.read() {
+            Some(byte) => {
+                self.state.register[register] = byte as u16;
+                true
+            }
+            None => {
+                self.meta.debugging = true;
+                self.meta.reason = Some(Reason::Input);
+                false
+            }
+        }
+    }
+
+    /// swap the guest's I/O, the terminal by default
+    pub fn io(&mut self, io: Box<dyn Io>) {
+        self.meta.io = io;
+    }
+}
+
+/// an operand as a Rust expression
+fn operand(raw: u16) -> String {
+    match raw {
+        0..=32767 => raw.to_string(),
+        32768..=32775 => format!("rt.state.register[{}]", raw - 32768),
+        _ => (raw % 32768).to_string(),
+    }
+}
+
+/// the word a literal operand names, None for registers
+fn literal(raw: u16) -> Option<usize> {
+    match raw {
+        32768..=32775 => None,
+        _ => Some(raw as usize % 32768),
+    }
+}
+
+/// the register a destination operand names, None for the invalid ones left to the interpreter's warning
+fn register(raw: u16) -> Option<usize> {
+    match raw {
+        32768..=32775 => Some(raw as usize - 32768),
+        _ => None,
+    }
+}
+
+/// a jump target as a byte address expression, folded for literals
+fn target(raw: u16) -> String {
+    match literal(raw) {
+        Some(word) => (word * 2).to_string(),
+        None => format!("{} as usize * 2", operand(raw)),
+    }
+}
+
+/// the statement for an instruction that leaves ip alone, None for control flow, input and anything left to the
+/// interpreter
+fn statement(instruction: &Instruction) -> Option<String> {
+    let [x, y, z] = instruction.args;
+    let (b, c) = (operand(y), operand(z));
+    let a = || register(x).map(|a| format!("rt.state.register[{}]", a));
+    Some(match instruction.code() {
+        Code::Set => format!("{} = {};", a()?, b),
+        Code::Push => format!("rt.state.stack.push({});", operand(x)),
+        Code::Pop => format!(
+            "if let Some(value) = rt.state.stack.pop() {{\n{0}    {1} = value;\n{0}    rt.state.unwind();\n{0}}}",
+            INDENT,
+            a()?
+        ),
+        Code::Equals => format!("{} = ({} == {}) as u16;", a()?, b, c),
+        Code::GreaterThan => format!("{} = ({} > {}) as u16;", a()?, b, c),
+        Code::Add => format!("{} = (({} as u32 + {} as u32) % 32768) as u16;", a()?, b, c),
+        Code::Multiply => format!("{} = (({} as u32 * {} as u32) % 32768) as u16;", a()?, b, c),
+        Code::Modulo if literal(z) == Some(0) => return None, // the interpreter stops for the debugger
+        Code::Modulo => format!("{} = {} % {} % 32768;", a()?, b, c),
+        Code::And => format!("{} = ({} & {}) % 32768;", a()?, b, c),
+        Code::Or => format!("{} = ({} | {}) % 32768;", a()?, b, c),
+        Code::Not => format!("{} = !{} % 32768;", a()?, b),
+        Code::ReadMemory => format!("{} = rt.read({});", a()?, b),
+        Code::WriteMemory => format!("rt.write({}, {});", operand(x), b),
+        Code::Out => format!("rt.out({});", operand(x)),
+        Code::Noop => String::from("// noop"),
+        // control flow and input are translated by block, halt and data stop the interpreter
+        Code::Halt
+        | Code::Jump
+        | Code::JumpIfTrue
+        | Code::JumpIfFalse
+        | Code::Call
+        | Code::Return
+        | Code::In
+        | Code::Data => return None,
+    })
+}
+
+/// statements inside a match arm
+const INDENT: &str = "                ";
+
+/// the words that start a block, walking from the entry point and from the roots, instructions a recorded run
+/// executed; a root nothing falls through to was reached through a register and starts a block too
+fn discover(program: &[u8], entry: usize, roots: &[usize]) -> BTreeSet<usize> {
+    let words = program.len() / 2;
+    let mut seen = HashSet::new();
+    let mut fallen = HashSet::new();
+    let mut leaders = BTreeSet::from([entry]);
+    let mut work = vec![entry];
+    work.extend(roots);
+    while let Some(word) = work.pop() {
+        if word >= words || !seen.insert(word) {
+            continue;
+        }
+        let instruction = Instruction::decode(program, word * 2);
+        let next = word + instruction.len as usize;
+        let code = instruction.code();
+        let address = code.operands().iter().position(|operand| *operand == Operand::Address);
+        let jump = address.and_then(|i| literal(instruction.args[i]));
+        let (follow, jump) = match code.flow() {
+            Flow::Next => (true, None),
+            Flow::Branch => (true, jump),
+            Flow::Jump => (false, jump),
+            Flow::Stop => (false, None),
+        };
+        if let Some(jump) = jump {
+            leaders.insert(jump);
+            work.push(jump);
+        }
+        if follow {
+            if code.flow() == Flow::Branch || code == Code::WriteMemory {
+                leaders.insert(next);
+            }
+            fallen.insert(next);
+            work.push(next);
+        }
+    }
+    leaders.extend(roots.iter().filter(|root| !fallen.contains(root)));
+    leaders.retain(|word| seen.contains(word));
+    leaders
+}
+
+/// account for the instructions run so far and go on at ip
+fn exit(body: &mut String, count: usize, ip: String) {
+    let _ = writeln!(body, "{}rt.meta.op_count += {};", INDENT, count);
+    let _ = writeln!(body, "{}rt.state.ip = {};", INDENT, ip);
+}
+
+/// one block as a match arm, returns its length in words, 0 when its first instruction is left to the interpreter
+fn block(out: &mut String, program: &[u8], start: usize, leaders: &BTreeSet<usize>) -> usize {
+    let mut body = String::new();
+    let mut word = start;
+    let mut count = 0;
+    let end = loop {
+        let instruction = Instruction::decode(program, word * 2);
+        let next = word + instruction.len as usize;
+        let [x, y, z] = instruction.args;
+        let code = instruction.code();
+        let translated = match code {
+            Code::Jump | Code::JumpIfTrue | Code::JumpIfFalse | Code::Call | Code::Return => true,
+            Code::In => register(x).is_some(),
+            _ => statement(&instruction).is_some(),
+        };
+        if !translated {
+            if count == 0 {
+                return 0;
+            }
+            exit(&mut body, count, (word * 2).to_string());
+            break word;
+        }
+        let _ = writeln!(body, "{}// {:#06X}: {}", INDENT, word * 2, disassemble(program, word * 2).0);
+        count += 1;
+        match code {
+            Code::Jump => {
+                exit(&mut body, count, target(x));
+                break next;
+            }
+            Code::JumpIfTrue | Code::JumpIfFalse => {
+                let test = if code == Code::JumpIfTrue { "!=" } else { "==" };
+                let ip = format!("if {} {} 0 {{ {} }} else {{ {} }}", operand(x), test, target(y), next * 2);
+                exit(&mut body, count, ip);
+                break next;
+            }
+            Code::Call => {
+                let _ = writeln!(body, "{}rt.meta.op_count += {};", INDENT, count);
+                let _ = writeln!(body, "{}rt.call({}, {}, {});", INDENT, word, next, target(x));
+                break next;
+            }
+            Code::Return => {
+                let _ = writeln!(body, "{}rt.meta.op_count += {};", INDENT, count);
+                let _ = writeln!(body, "{}rt.ret({});", INDENT, word);
+                break next;
+            }
+            Code::In => {
+                let _ = writeln!(body, "{}if !rt.input({}) {{", INDENT, register(x).unwrap_or(0));
+                let _ = writeln!(body, "{}    rt.meta.op_count += {};", INDENT, count);
+                let _ = writeln!(body, "{}    rt.state.ip = {};", INDENT, word * 2);
+                let _ = writeln!(body, "{}    continue;", INDENT);
+                let _ = writeln!(body, "{}}}", INDENT);
+            }
+            Code::Modulo if literal(z).is_none() => {
+                // the interpreter stops for the debugger on a zero divisor, let it
+                let _ = writeln!(body, "{}if {} == 0 {{", INDENT, operand(z));
+                if count > 1 {
+                    let _ = writeln!(body, "{}    rt.meta.op_count += {};", INDENT, count - 1);
+                }
+                let _ = writeln!(body, "{}    rt.bail({});", INDENT, word);
+                let _ = writeln!(body, "{}    continue;", INDENT);
+                let _ = writeln!(body, "{}}}", INDENT);
+                let _ = writeln!(body, "{}{}", INDENT, statement(&instruction).unwrap_or_default());
+            }
+            _ => {
+                let _ = writeln!(body, "{}{}", INDENT, statement(&instruction).unwrap_or_default());
+            }
+        }
+        // a write may have landed on the rest of the block, so the block ends after it
+        if code == Code::WriteMemory || leaders.contains(&next) || next * 2 >= program.len() {
+            exit(&mut body, count, (next * 2).to_string());
+            break next;
+        }
+        word = next;
+    };
+    let _ = writeln!(out, "            Some({}) => {{", start);
+    out.push_str(&body);
+    let _ = writeln!(out, "            }}");
+    end - start
+}
+
+/// a Rust module running the image, every reachable basic block becomes an arm of a match on ip; anything not
+/// reached from the entry point or the coverage, or rewritten by the guest, goes to the interpreter
+pub fn translate(image: &[u8], name: &str, coverage: Option<&Coverage>) -> BoxResult<String> {
+    let state = State::recover(image.to_vec())?;
+    let program = &state.program;
+    let roots: Vec<usize> = match coverage {
+        Some(coverage) => (0..program.len() / 2).filter(|word| coverage.flags(word * 2) & EXECUTED != 0).collect(),
+        None => Vec::new(),
+    };
+    let leaders = discover(program, state.ip / 2, &roots);
+
+    let mut arms = String::new();
+    let mut blocks = Vec::new();
+    for start in &leaders {
+        let len = block(&mut arms, program, *start, &leaders);
+        if len > 0 {
+            blocks.push((*start, len));
+        }
+    }
+
+    let mut out = String::new();
+    let _ = writeln!(out, "//! translated by `synacor aot` from {}, do not edit", name);
+    let _ = writeln!(out);
+    let _ = writeln!(out, "#![allow(dead_code, clippy::all)]");
+    let _ = writeln!(out);
+    let _ = writeln!(out, "use synacor::aot::Runtime;");
+    let _ = writeln!(out, "use synacor::vm::BoxResult;");
+    let _ = writeln!(out);
+    let _ = writeln!(out, "/// the image the blocks were translated from");
+    let _ = writeln!(out, "pub const IMAGE: &[u8] = b\"\\");
+    for row in image.chunks(ROW) {
+        let bytes: String = row.iter().map(|byte| format!("\\x{:02x}", byte)).collect();
+        let _ = writeln!(out, "{}\\", bytes);
+    }
+    let _ = writeln!(out, "\";");
+    let _ = writeln!(out);
+    let _ = writeln!(out, "/// word address and length in words of every translated block");
+    let _ = writeln!(out, "pub const BLOCKS: &[(usize, usize)] = &[");
+    for (start, len) in &blocks {
+        let _ = writeln!(out, "    ({}, {}),", start, len);
+    }
+    let _ = writeln!(out, "];");
+    let _ = writeln!(out);
+    let _ = writeln!(out, "/// the machine as the image starts it, on the terminal");
+    let _ = writeln!(out, "pub fn runtime() -> BoxResult<Runtime> {{");
+    let _ = writeln!(out, "    Runtime::new(IMAGE, BLOCKS)");
+    let _ = writeln!(out, "}}");
+    let _ = writeln!(out);
+    let _ = writeln!(out, "/// run until the guest halts or stops for the debugger");
+    let _ = writeln!(out, "pub fn run(rt: &mut Runtime) {{");
+    let _ = writeln!(out, "    while !rt.meta.halt && !rt.meta.debugging {{");
+    let _ = writeln!(out, "        match rt.block() {{");
+    out.push_str(&arms);
+    let _ = writeln!(out, "            _ => rt.step(),");
+    let _ = writeln!(out, "        }}");
+    let _ = writeln!(out, "    }}");
+    let _ = writeln!(out, "}}");
+    Ok(out)
+}
@@ -0,0 +1,148 @@
+use crate::opcode::lookup;
+use crate::opcode::Code;
+use crate::peephole::{Super, SPAN};
+
+/// 32768 words
+const WORDS: usize = 0x8000;
+
+/// longest instruction, the opcode and three operands
+const LONGEST: usize = 4;
+
+/// an instruction decoded once, operands stay raw words since registers are read when it runs
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Instruction {
+    /// low byte of the opcode word, which is what the executor dispatches on
+    pub op: u8,
+    pub args: [u16; 3],
+    /// words, the opcode included
+    pub len: u8,
+}
+
+impl Instruction {
+    /// decode the instruction at ip, words past the end of the program read as zero
+    pub fn decode(program: &[u8], ip: usize) -> Instruction {
+        let word = |i: usize| {
+            let byte = |i: usize| program.get(i).copied().unwrap_or(0) as u16;
+            byte(i + 1) << 8 | byte(i)
+        };
+        // opcodes are whole words, one with its high byte set is data however its low byte reads
+        let op = match word(ip) {
+            op @ 0..=255 => op as u8,
+            _ => u8::MAX,
+        };
+        let operands = lookup(op).len();
+        let mut args = [0; 3];
+        for (n, arg) in args.iter_mut().enumerate().take(operands) {
+            *arg = word(ip + 2 + n * 2);
+        }
+        Instruction {
+            op,
+            args,
+            len: operands as u8 + 1,
+        }
+    }
+
+    pub fn code(&self) -> Code {
+        lookup(self.op)
+    }
+}
+
+/// what the peephole optimizer made of the instructions starting at an address
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Slot {
+    Unseen,
+    /// nothing to fuse here
+    Plain,
+    Fused(Super),
+}
+
+/// decoded instructions by word address, dropped when a write lands on any of their words
+pub struct Cache {
+    entries: Vec<Option<Instruction>>,
+    /// superinstructions by the word address of their first instruction
+    slots: Vec<Slot>,
+    /// superinstructions dropped because a write landed under them
+    pub deoptimized: usize,
+    /// decode every time instead, for comparing against the cache
+    pub enabled: bool,
+}
+
+impl Cache {
+    pub fn new() -> Cache {
+        Cache {
+            entries: vec![None; WORDS],
+            slots: vec![Slot::Unseen; WORDS],
+            deoptimized: 0,
+            enabled: true,
+        }
+    }
+
+    /// the instruction at ip, decoded on the first visit
+    pub fn fetch(&mut self, program: &[u8], ip: usize) -> Instruction {
+        if !self.enabled || !ip.is_multiple_of(2) {
+            return Instruction::decode(program, ip);
+        }
+        match self.entries.get_mut(ip / 2) {
+            Some(Some(instruction)) => *instruction,
+            Some(entry) => *entry.insert(Instruction::decode(program, ip)),
+            None => Instruction::decode(program, ip),
+        }
+    }
+
+    /// the superinstruction starting at ip, fused on the first visit
+    pub fn superinstruction(&mut self, program: &[u8], ip: usize) -> Option<Super> {
+        if !self.enabled || !ip.is_multiple_of(2) {
+            return None;
+        }
+        let slot = self.slots.get_mut(ip / 2)?;
+        if *slot == Slot::Unseen {
+            *slot = Super::fuse(program, ip).map_or(Slot::Plain, Slot::Fused);
+        }
+        match slot {
+            Slot::Fused(fused) => Some(*fused),
+            _ => None,
+        }
+    }
+
+    /// start of the nearest decoded instruction covering the word at address, as a byte address
+    pub fn covering(&self, address: usize) -> Option<usize> {
+        let word = address / 2;
+        (word.saturating_sub(LONGEST - 1)..=word)
+            .rev()
+            .find(|start| matches!(self.entries.get(*start), Some(Some(instruction)) if start + instruction.len as usize > word))
+            .map(|start| start * 2)
+    }
+
+    /// forget every instruction covering the word at address, call after writing it
+    pub fn invalidate(&mut self, address: usize) {
+        let word = address / 2;
+        for start in word.saturating_sub(LONGEST - 1)..=word {
+            if let Some(entry) = self.entries.get_mut(start) {
+                if matches!(entry, Some(instruction) if start + instruction.len as usize > word) {
+                    *entry = None;
+                }
+            }
+        }
+        // a plain slot looked ahead too, the write might make it fusable
+        for start in word.saturating_sub(SPAN - 1)..=word {
+            match self.slots.get(start) {
+                Some(Slot::Fused(fused)) if start + fused.len as usize > word => {
+                    self.slots[start] = Slot::Unseen;
+                    self.deoptimized += 1;
+                }
+                Some(Slot::Plain) => self.slots[start] = Slot::Unseen,
+                _ => {}
+            }
+        }
+    }
+
+    /// forget every instruction covering a run of bytes, for front ends that write memory in bulk
+    pub fn invalidate_range(&mut self, address: usize, bytes: usize) {
+        if bytes == 0 {
+            return;
+        }
+        for word in address / 2..=(address + bytes - 1) / 2 {
+            self.invalidate(word * 2);
+        }
+    }
+}
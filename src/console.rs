@@ -0,0 +1,108 @@
+use crate::util::discard_line;
+use crate::util::read;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io;
+use std::io::Write;
+use std::rc::Rc;
+
+/// where guest input comes from and guest output goes to
+pub trait Io {
+    /// one byte of guest input, None when the debugger should take over instead
+    fn read(&mut self) -> Option<u8>;
+    /// one byte of guest output
+    fn write(&mut self, byte: u8);
+    /// push out anything written but not yet shown
+    fn flush(&mut self) {}
+}
+
+/// guest output held back before stdout gets it in one write
+const CAPACITY: usize = 1 << 13;
+
+/// stdin and stdout, typing ~ at an input prompt opens the debugger; output is buffered until
+/// the guest reads input, the debugger opens, the machine halts or the buffer fills up
+pub struct Terminal {
+    buffer: Vec<u8>,
+}
+
+impl Terminal {
+    pub fn new() -> Terminal {
+        Terminal {
+            buffer: Vec::with_capacity(CAPACITY),
+        }
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+impl Io for Terminal {
+    fn read(&mut self) -> Option<u8> {
+        match read() {
+            Some(b'~') => {
+                discard_line(); // the debugger prompt does not read through our stdin buffer
+                None
+            }
+            res => res,
+        }
+    }
+
+    fn write(&mut self, byte: u8) {
+        self.buffer.push(byte);
+        if self.buffer.len() >= CAPACITY {
+            self.flush();
+        }
+    }
+
+    fn flush(&mut self) {
+        let mut stdout = io::stdout().lock();
+        // every byte as the char it always was printed as
+        let text: String = self.buffer.drain(..).map(|byte| byte as char).collect();
+        let _ = stdout.write_all(text.as_bytes());
+        let _ = stdout.flush();
+    }
+}
+
+/// guest io kept in memory, for front ends that show the console themselves
+#[derive(Default)]
+pub struct Pipe {
+    pub input: VecDeque<u8>,
+    pub output: Vec<u8>,
+}
+
+impl Pipe {
+    /// a pipe with the guest's input already queued up
+    pub fn script(input: &[u8]) -> Pipe {
+        Pipe {
+            input: input.iter().copied().collect(),
+            output: Vec::new(),
+        }
+    }
+}
+
+/// the pipe on its own, for driving the guest from a fixed script when nobody reads the output back
+impl Io for Pipe {
+    fn read(&mut self) -> Option<u8> {
+        self.input.pop_front()
+    }
+
+    fn write(&mut self, byte: u8) {
+        self.output.push(byte);
+    }
+}
+
+/// reads from and writes to a pipe the front end holds the other end of
+pub struct Piped(pub Rc<RefCell<Pipe>>);
+
+impl Io for Piped {
+    fn read(&mut self) -> Option<u8> {
+        self.0.borrow_mut().input.pop_front()
+    }
+
+    fn write(&mut self, byte: u8) {
+        self.0.borrow_mut().output.push(byte);
+    }
+}
@@ -0,0 +1,750 @@
+use crate::console::Pipe;
+use crate::console::Piped;
+use crate::debug::backtrace;
+use crate::debug::symbol::parse_address;
+use crate::debug::Meta;
+use crate::debug::Reason;
+use crate::debug::Resume;
+use crate::machine::Machine;
+use crate::opcode::disassemble;
+use crate::opcode::length;
+use crate::opcode::parse;
+use crate::opcode::step;
+use crate::opcode::Code;
+use crate::signal;
+use crate::vm::BoxResult;
+use crate::vm::State;
+
+use serde_json::{json, Value};
+use std::cell::RefCell;
+use std::fs;
+use std::io::{self, BufRead, BufReader, ErrorKind, Read, Write};
+use std::mem;
+use std::net::{TcpListener, TcpStream};
+use std::path::Path;
+use std::rc::Rc;
+
+/// instructions executed between checks for a pause request
+const POLL_INTERVAL: usize = 1 << 14;
+
+/// the VM only ever has the one thread
+const THREAD: u64 = 1;
+
+/// the disassembly listing handed out through the source request
+const SOURCE: u64 = 1;
+
+/// variable references, the stack and memory scopes get one per frame
+const REGISTERS: u64 = 1;
+const STACK: u64 = 1000;
+const MEMORY: u64 = 2000;
+
+/// words of memory shown from the pc of a frame
+const WINDOW: usize = 16;
+
+/// bytes in a message body at most, anything bigger is skipped and refused
+const LARGEST: usize = 1 << 20;
+
+/// instructions one disassemble request lists at most, and how far its offset reaches
+const LISTING: usize = 1 << 12;
+
+enum Action {
+    Reply,
+    Resume(Resume),
+    Pause,
+    Disconnect,
+}
+
+struct Client {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    seq: u64,
+}
+
+impl Client {
+    fn new(stream: TcpStream) -> BoxResult<Client> {
+        stream.set_nodelay(true)?;
+        Ok(Client {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+            seq: 0,
+        })
+    }
+
+    /// next message from the editor, None once it hung up; one too big or not JSON gets a failed response instead
+    fn receive(&mut self) -> BoxResult<Option<Value>> {
+        loop {
+            let mut length = None;
+            loop {
+                let mut line = String::new();
+                if self.reader.read_line(&mut line)? == 0 {
+                    return Ok(None);
+                }
+                let line = line.trim_end();
+                if line.is_empty() {
+                    if length.is_some() {
+                        break;
+                    }
+                    continue;
+                }
+                if let Some((name, value)) = line.split_once(':') {
+                    if name.trim().eq_ignore_ascii_case("content-length") {
+                        length = Some(value.trim().parse::<usize>()?);
+                    }
+                }
+            }
+            // there is no request to tie the answer to, so it goes to seq 0 and the session carries on
+            let unknown = json!({ "seq": 0, "command": "" });
+            let length = length.unwrap_or(0);
+            if length > LARGEST {
+                io::copy(&mut (&mut self.reader).take(length as u64), &mut io::sink())?;
+                self.fail(&unknown, &format!("a message of {} bytes is over the {} byte limit", length, LARGEST))?;
+                continue;
+            }
+            let mut body = vec![0u8; length];
+            self.reader.read_exact(&mut body)?;
+            match serde_json::from_slice(&body) {
+                Ok(message) => return Ok(Some(message)),
+                Err(error) => self.fail(&unknown, &format!("not a JSON message: {}", error))?,
+            }
+        }
+    }
+
+    fn send(&mut self, mut message: Value) -> BoxResult<()> {
+        self.seq += 1;
+        message["seq"] = json!(self.seq);
+        let body = message.to_string();
+        write!(self.writer, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    fn respond(&mut self, request: &Value, body: Value) -> BoxResult<()> {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": true,
+            "body": body,
+        }))
+    }
+
+    fn fail(&mut self, request: &Value, message: &str) -> BoxResult<()> {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": false,
+            "message": message,
+        }))
+    }
+
+    fn event(&mut self, event: &str, body: Value) -> BoxResult<()> {
+        self.send(json!({ "type": "event", "event": event, "body": body }))
+    }
+
+    /// check without blocking whether the editor sent something while the guest runs
+    fn ready(&mut self) -> BoxResult<bool> {
+        if !self.reader.buffer().is_empty() {
+            return Ok(true);
+        }
+        self.reader.get_ref().set_nonblocking(true)?;
+        let filled = self.reader.fill_buf().map(|buffer| !buffer.is_empty());
+        self.reader.get_ref().set_nonblocking(false)?;
+        match filled {
+            Ok(filled) => Ok(filled),
+            Err(error) if error.kind() == ErrorKind::WouldBlock => Ok(false),
+            Err(error) => Err(Box::new(error)),
+        }
+    }
+}
+
+fn word(state: &State, address: usize) -> u16 {
+    let byte = |i: usize| state.program.get(i).copied().unwrap_or(0) as u16;
+    byte(address + 1) << 8 | byte(address)
+}
+
+fn number(value: &Value) -> Option<usize> {
+    match value {
+        Value::Number(number) => number.as_u64().map(|number| number as usize),
+        Value::String(text) => parse_address(text.trim()).ok(),
+        _ => None,
+    }
+}
+
+/// addresses where an instruction starts when decoding straight through the range, the first most of them
+fn boundaries(state: &State, start: usize, end: usize, most: usize) -> Vec<usize> {
+    let mut addresses = Vec::new();
+    let mut i = start;
+    while i < end && addresses.len() < most {
+        addresses.push(i);
+        i += length(&state.program, i) * 2;
+    }
+    addresses
+}
+
+struct Session {
+    client: Client,
+    pipe: Rc<RefCell<Pipe>>,
+    /// what the listing is called in the editor
+    name: String,
+    /// lines count from 1 unless the editor said otherwise
+    line_base: usize,
+    launched: bool,
+    stop_on_entry: bool,
+    source_breakpoints: Vec<usize>,
+    instruction_breakpoints: Vec<usize>,
+    function_breakpoints: Vec<usize>,
+}
+
+impl Session {
+    fn source(&self) -> Value {
+        json!({ "name": self.name, "sourceReference": SOURCE })
+    }
+
+    fn line(&self, address: usize) -> usize {
+        address / 2 + self.line_base
+    }
+
+    /// the whole program with one line per word, so a line number is always a word address
+    fn listing(&self, state: &State) -> String {
+        let mut text = String::new();
+        let mut i = 0;
+        while i < state.program.len() {
+            let (instruction, length) = disassemble(&state.program, i);
+            text.push_str(&format!("{:#06x}  {}\n", i, instruction));
+            for n in 1..length {
+                let address = i + n * 2;
+                if address < state.program.len() {
+                    text.push_str(&format!("{:#06x}      {}\n", address, word(state, address)));
+                }
+            }
+            i += length * 2;
+        }
+        text
+    }
+
+    fn breakpoints(&self, meta: &mut Meta) {
+        meta.breakpoints.clear();
+        for address in self
+            .source_breakpoints
+            .iter()
+            .chain(&self.instruction_breakpoints)
+            .chain(&self.function_breakpoints)
+        {
+            if !meta.breakpoints.contains(address) {
+                meta.breakpoints.push(*address);
+            }
+        }
+    }
+
+    fn verified(&self, state: &State, address: usize) -> Value {
+        json!({
+            "verified": address < state.program.len(),
+            "line": self.line(address),
+            "source": self.source(),
+            "instructionReference": format!("{:#06x}", address),
+        })
+    }
+
+    /// send out whatever the guest printed since last time
+    fn output(&mut self) -> BoxResult<()> {
+        let output = mem::take(&mut self.pipe.borrow_mut().output);
+        if !output.is_empty() {
+            let text = String::from_utf8_lossy(&output).into_owned();
+            self.client.event("output", json!({ "category": "stdout", "output": text }))?;
+        }
+        Ok(())
+    }
+
+    fn stopped(&mut self, state: &State, reason: &str, description: Option<&str>) -> BoxResult<()> {
+        self.output()?;
+        let mut body = json!({
+            "reason": reason,
+            "threadId": THREAD,
+            "allThreadsStopped": true,
+            "instructionPointerReference": format!("{:#06x}", state.ip),
+        });
+        if let Some(description) = description {
+            body["description"] = json!(description);
+        }
+        self.client.event("stopped", body)
+    }
+
+    fn exited(&mut self) -> BoxResult<()> {
+        self.output()?;
+        self.client.event("exited", json!({ "exitCode": 0 }))?;
+        self.client.event("terminated", json!({}))
+    }
+
+    fn variables(&self, state: &State, meta: &Meta, reference: u64) -> Option<Vec<Value>> {
+        let traces = backtrace(state);
+        let variable = |name: String, value: u16| {
+            json!({ "name": name, "value": value.to_string(), "type": "u16", "variablesReference": 0 })
+        };
+        match reference {
+            REGISTERS => {
+                let mut variables: Vec<Value> = (0..8).map(|i| variable(format!("r{}", i), state.register[i])).collect();
+                variables.push(json!({
+                    "name": "ip",
+                    "value": format!("{:#06x}", state.ip),
+                    "type": "address",
+                    "variablesReference": 0,
+                    "memoryReference": format!("{:#06x}", state.ip),
+                }));
+                Some(variables)
+            }
+            _ if (STACK..MEMORY).contains(&reference) => {
+                let trace = traces.get((reference - STACK) as usize)?;
+                Some(
+                    trace
+                        .slots
+                        .clone()
+                        .map(|i| {
+                            if i == trace.slots.start && trace.function.is_some() {
+                                let target = state.stack[i] as usize * 2;
+                                json!({
+                                    "name": format!("<{}>", i),
+                                    "value": format!("{} (return to {})", state.stack[i], meta.symbols.describe(target)),
+                                    "type": "u16",
+                                    "variablesReference": 0,
+                                })
+                            } else {
+                                variable(format!("<{}>", i), state.stack[i])
+                            }
+                        })
+                        .collect(),
+                )
+            }
+            _ if reference >= MEMORY => {
+                let trace = traces.get((reference - MEMORY) as usize)?;
+                Some(
+                    (0..WINDOW)
+                        .map(|n| trace.pc + n * 2)
+                        .filter(|address| *address < state.program.len())
+                        .map(|address| {
+                            let mut variable = variable(format!("{:#06x}", address), word(state, address));
+                            variable["memoryReference"] = json!(format!("{:#06x}", address));
+                            variable
+                        })
+                        .collect(),
+                )
+            }
+            _ => None,
+        }
+    }
+
+    fn set_variable(&self, state: &mut State, meta: &mut Meta, reference: u64, name: &str, value: usize) -> Option<String> {
+        if reference == REGISTERS {
+            if name == "ip" {
+                state.ip = value;
+                return Some(format!("{:#06x}", value));
+            }
+            let n = name.strip_prefix('r')?.parse::<usize>().ok().filter(|n| *n < 8)?;
+            state.register[n] = value as u16;
+        } else if (STACK..MEMORY).contains(&reference) {
+            let i = name.trim_matches(['<', '>']).parse::<usize>().ok()?;
+            *state.stack.get_mut(i)? = value as u16;
+        } else {
+            let address = parse_address(name).ok()?;
+            if address + 1 >= state.program.len() {
+                return None;
+            }
+            state.write_memory(&mut meta.hooks, address, &(value as u16).to_le_bytes());
+        }
+        Some((value as u16).to_string())
+    }
+
+    fn disassembly(&self, state: &State, meta: &Meta, arguments: &Value) -> Option<Vec<Value>> {
+        let address = number(&arguments["memoryReference"])? as i64 + arguments["offset"].as_i64().unwrap_or(0);
+        let address = address.max(0) as usize;
+        let offset = arguments["instructionOffset"].as_i64().unwrap_or(0).clamp(-(LISTING as i64), LISTING as i64);
+        let count = (arguments["instructionCount"].as_u64()? as usize).min(LISTING);
+
+        // decode up to the address and from it separately, so the address itself is always an instruction; an
+        // instruction is four words at most, so starting that far back per instruction before it finds enough of them
+        let end = address.min(state.program.len());
+        let back = (-offset).max(0) as usize * 8;
+        let before = boundaries(state, address.saturating_sub(back), end, usize::MAX);
+        let first = before.len() as i64 + offset;
+        let mut addresses = before;
+        let wanted = (first + count as i64).max(0) as usize;
+        addresses.extend(boundaries(state, address, state.program.len(), wanted.saturating_sub(addresses.len())));
+
+        let mut instructions = Vec::with_capacity(count);
+        for n in first..first + count as i64 {
+            let address = if n < 0 || n as usize >= addresses.len() { None } else { Some(addresses[n as usize]) };
+            instructions.push(match address {
+                Some(address) => {
+                    let (text, length) = disassemble(&state.program, address);
+                    let bytes: String = (address..address + length * 2)
+                        .map(|i| format!("{:02x}", state.program.get(i).copied().unwrap_or(0)))
+                        .collect();
+                    let mut instruction = json!({
+                        "address": format!("{:#06x}", address),
+                        "instructionBytes": bytes,
+                        "instruction": text,
+                        "location": self.source(),
+                        "line": self.line(address),
+                    });
+                    if let Some(name) = meta.symbols.get(address) {
+                        instruction["symbol"] = json!(name);
+                    }
+                    instruction
+                }
+                // outside the program, still one entry per requested slot
+                None => json!({ "address": format!("{:#06x}", (n.max(0) as usize) * 2), "instruction": "", "presentationHint": "invalid" }),
+            });
+        }
+        Some(instructions)
+    }
+
+    fn evaluate(&self, state: &State, meta: &Meta, expression: &str) -> Option<String> {
+        let expression = expression.trim();
+        if expression == "ip" {
+            return Some(format!("{:#06x}", state.ip));
+        }
+        if let Some(n) = expression.strip_prefix('r').and_then(|n| n.parse::<usize>().ok()) {
+            return state.register.get(n).map(|value| value.to_string());
+        }
+        let address = meta.symbols.resolve(expression).ok()?;
+        Some(word(state, address).to_string())
+    }
+
+    fn handle(&mut self, state: &mut State, meta: &mut Meta, request: &Value) -> BoxResult<Action> {
+        let arguments = &request["arguments"];
+        let command = request["command"].as_str().unwrap_or("");
+        match command {
+            "initialize" => {
+                if arguments["linesStartAt1"] == json!(false) {
+                    self.line_base = 0;
+                }
+                self.client.respond(request, json!({
+                    "supportsConfigurationDoneRequest": true,
+                    "supportsFunctionBreakpoints": true,
+                    "supportsInstructionBreakpoints": true,
+                    "supportsDisassembleRequest": true,
+                    "supportsSteppingGranularity": true,
+                    "supportsSetVariable": true,
+                    "supportsTerminateRequest": true,
+                }))?;
+                self.client.event("initialized", json!({}))?;
+            }
+            "launch" | "attach" => {
+                self.launched = command == "launch";
+                if let Some(path) = arguments["program"].as_str() {
+                    match fs::read(path).map_err(|error| error.to_string()).and_then(|program| State::recover(program).map_err(|error| error.to_string())) {
+                        Ok(recovered) => {
+                            *state = recovered;
+                            meta.op_count = 0;
+                            meta.halt = false;
+                            self.name = Path::new(path)
+                                .file_name()
+                                .map(|name| name.to_string_lossy().into_owned())
+                                .unwrap_or_else(|| String::from(path));
+                        }
+                        Err(error) => {
+                            self.client.fail(request, &format!("could not load {}: {}", path, error))?;
+                            return Ok(Action::Reply);
+                        }
+                    }
+                }
+                if state.program.is_empty() {
+                    self.client.fail(request, "no program given to launch")?;
+                    return Ok(Action::Reply);
+                }
+                if let Some(input) = arguments["input"].as_str() {
+                    self.pipe.borrow_mut().input.extend(input.bytes());
+                }
+                // attaching leaves the VM where it is, so show where that is
+                self.stop_on_entry = !self.launched || arguments["stopOnEntry"] == json!(true);
+                self.client.respond(request, json!({}))?;
+            }
+            "setBreakpoints" => {
+                let lines: Vec<usize> = arguments["breakpoints"]
+                    .as_array()
+                    .map(|breakpoints| breakpoints.iter().filter_map(|breakpoint| number(&breakpoint["line"])).collect())
+                    .unwrap_or_default();
+                self.source_breakpoints = lines.iter().map(|line| line.saturating_sub(self.line_base) * 2).collect();
+                self.breakpoints(meta);
+                let breakpoints: Vec<Value> = self.source_breakpoints.iter().map(|address| self.verified(state, *address)).collect();
+                self.client.respond(request, json!({ "breakpoints": breakpoints }))?;
+            }
+            "setInstructionBreakpoints" => {
+                let mut breakpoints = Vec::new();
+                self.instruction_breakpoints.clear();
+                for breakpoint in arguments["breakpoints"].as_array().into_iter().flatten() {
+                    match number(&breakpoint["instructionReference"]) {
+                        Some(address) => {
+                            let address = (address as i64 + breakpoint["offset"].as_i64().unwrap_or(0)).max(0) as usize;
+                            self.instruction_breakpoints.push(address);
+                            breakpoints.push(self.verified(state, address));
+                        }
+                        None => breakpoints.push(json!({ "verified": false, "message": "not an address" })),
+                    }
+                }
+                self.breakpoints(meta);
+                self.client.respond(request, json!({ "breakpoints": breakpoints }))?;
+            }
+            "setFunctionBreakpoints" => {
+                let mut breakpoints = Vec::new();
+                self.function_breakpoints.clear();
+                for breakpoint in arguments["breakpoints"].as_array().into_iter().flatten() {
+                    let name = breakpoint["name"].as_str().unwrap_or("");
+                    match meta.symbols.resolve(name) {
+                        Ok(address) => {
+                            self.function_breakpoints.push(address);
+                            breakpoints.push(self.verified(state, address));
+                        }
+                        Err(_) => breakpoints.push(json!({ "verified": false, "message": format!("no symbol {}", name) })),
+                    }
+                }
+                self.breakpoints(meta);
+                self.client.respond(request, json!({ "breakpoints": breakpoints }))?;
+            }
+            "setExceptionBreakpoints" => {
+                self.client.respond(request, json!({}))?;
+            }
+            "configurationDone" => {
+                self.client.respond(request, json!({}))?;
+                if self.stop_on_entry {
+                    let reason = if self.launched { "entry" } else { "pause" };
+                    self.stopped(state, reason, None)?;
+                } else {
+                    return Ok(Action::Resume(Resume::Continue));
+                }
+            }
+            "threads" => {
+                self.client.respond(request, json!({ "threads": [{ "id": THREAD, "name": "synacor" }] }))?;
+            }
+            "stackTrace" => {
+                let traces = backtrace(state);
+                let start = number(&arguments["startFrame"]).unwrap_or(0);
+                let levels = number(&arguments["levels"]).filter(|levels| *levels > 0).unwrap_or(traces.len());
+                let frames: Vec<Value> = traces
+                    .iter()
+                    .enumerate()
+                    .skip(start)
+                    .take(levels)
+                    .map(|(n, trace)| {
+                        let name = match trace.function {
+                            Some(address) => meta.symbols.describe(address),
+                            None => String::from("<entry>"),
+                        };
+                        json!({
+                            "id": n,
+                            "name": name,
+                            "source": self.source(),
+                            "line": self.line(trace.pc),
+                            "column": self.line_base,
+                            "instructionPointerReference": format!("{:#06x}", trace.pc),
+                        })
+                    })
+                    .collect();
+                self.client.respond(request, json!({ "stackFrames": frames, "totalFrames": traces.len() }))?;
+            }
+            "scopes" => {
+                let n = number(&arguments["frameId"]).unwrap_or(0);
+                let traces = backtrace(state);
+                match traces.get(n) {
+                    Some(trace) => {
+                        let scopes = json!([
+                            { "name": "Registers", "presentationHint": "registers", "variablesReference": REGISTERS, "namedVariables": 9, "expensive": false },
+                            { "name": "Stack", "presentationHint": "locals", "variablesReference": STACK + n as u64, "namedVariables": trace.slots.len(), "expensive": false },
+                            { "name": "Memory", "variablesReference": MEMORY + n as u64, "namedVariables": WINDOW, "expensive": false },
+                        ]);
+                        self.client.respond(request, json!({ "scopes": scopes }))?;
+                    }
+                    None => self.client.fail(request, "no such frame")?,
+                }
+            }
+            "variables" => {
+                let reference = arguments["variablesReference"].as_u64().unwrap_or(0);
+                match self.variables(state, meta, reference) {
+                    Some(variables) => self.client.respond(request, json!({ "variables": variables }))?,
+                    None => self.client.fail(request, "no such variables")?,
+                }
+            }
+            "setVariable" => {
+                let reference = arguments["variablesReference"].as_u64().unwrap_or(0);
+                let name = arguments["name"].as_str().unwrap_or("");
+                let value = number(&arguments["value"]);
+                match value.and_then(|value| self.set_variable(state, meta, reference, name, value)) {
+                    Some(value) => self.client.respond(request, json!({ "value": value }))?,
+                    None => self.client.fail(request, "can not set that")?,
+                }
+            }
+            "source" => {
+                let listing = self.listing(state);
+                self.client.respond(request, json!({ "content": listing, "mimeType": "text/x-synacor-asm" }))?;
+            }
+            "disassemble" => match self.disassembly(state, meta, arguments) {
+                Some(instructions) => self.client.respond(request, json!({ "instructions": instructions }))?,
+                None => self.client.fail(request, "bad disassemble arguments")?,
+            },
+            "evaluate" => {
+                let expression = arguments["expression"].as_str().unwrap_or("");
+                if arguments["context"] == json!("repl") {
+                    // the debug console types into the game
+                    let mut pipe = self.pipe.borrow_mut();
+                    pipe.input.extend(expression.bytes());
+                    pipe.input.push_back(b'\n');
+                    drop(pipe);
+                    self.client.respond(request, json!({ "result": "", "variablesReference": 0 }))?;
+                } else {
+                    match self.evaluate(state, meta, expression) {
+                        Some(result) => self.client.respond(request, json!({ "result": result, "variablesReference": 0 }))?,
+                        None => self.client.fail(request, "not a register, symbol or address")?,
+                    }
+                }
+            }
+            "continue" => {
+                self.client.respond(request, json!({ "allThreadsContinued": true }))?;
+                return Ok(Action::Resume(Resume::Continue));
+            }
+            "next" => {
+                self.client.respond(request, json!({}))?;
+                if let Code::Call = parse(&state.program, &state.ip) {
+                    return Ok(Action::Resume(Resume::Return(state.frames.len())));
+                }
+                return Ok(Action::Resume(Resume::Step(1)));
+            }
+            "stepIn" => {
+                self.client.respond(request, json!({}))?;
+                return Ok(Action::Resume(Resume::Step(1)));
+            }
+            "stepOut" => {
+                self.client.respond(request, json!({}))?;
+                return Ok(Action::Resume(match state.frames.len() {
+                    0 => Resume::Continue,
+                    depth => Resume::Return(depth - 1),
+                }));
+            }
+            "pause" => {
+                self.client.respond(request, json!({}))?;
+                return Ok(Action::Pause);
+            }
+            "terminate" => {
+                self.client.respond(request, json!({}))?;
+                meta.halt = true;
+                self.exited()?;
+            }
+            "disconnect" => {
+                if self.launched && arguments["terminateDebuggee"] != json!(false) {
+                    meta.halt = true;
+                }
+                self.client.respond(request, json!({}))?;
+                return Ok(Action::Disconnect);
+            }
+            _ => self.client.fail(request, &format!("{} is not supported", command))?,
+        }
+        Ok(Action::Reply)
+    }
+
+    /// run the guest until it stops for one of the reasons the editor cares about
+    fn resume(&mut self, state: &mut State, meta: &mut Meta, resume: Resume) -> BoxResult<Action> {
+        if meta.halt {
+            self.exited()?;
+            return Ok(Action::Reply);
+        }
+        meta.resume = resume;
+        let mut count = 0usize;
+        loop {
+            step(state, meta);
+            if meta.halt {
+                self.exited()?;
+                return Ok(Action::Reply);
+            }
+            if meta.debugging {
+                meta.debugging = false;
+                meta.resume = Resume::Continue;
+                match meta.reason.take() {
+                    Some(Reason::UnknownOpcode) => self.stopped(state, "exception", Some("unknown opcode"))?,
+                    Some(Reason::DivideByZero) => self.stopped(state, "exception", Some("mod by zero"))?,
+                    Some(Reason::Input) => self.stopped(state, "pause", Some("waiting for input, type it in the debug console"))?,
+                    Some(Reason::CodeWrite) => self.stopped(state, "data breakpoint", Some("write into code"))?,
+                    None => self.stopped(state, "pause", None)?,
+                }
+                return Ok(Action::Reply);
+            }
+            if meta.stop(state) {
+                self.stopped(state, "step", None)?;
+                return Ok(Action::Reply);
+            }
+            if meta.breakpoints.contains(&state.ip) {
+                meta.resume = Resume::Continue;
+                self.stopped(state, "breakpoint", None)?;
+                return Ok(Action::Reply);
+            }
+            count += 1;
+            if signal::interrupted() {
+                meta.resume = Resume::Continue;
+                self.stopped(state, "pause", None)?;
+                return Ok(Action::Reply);
+            }
+            if count.is_multiple_of(POLL_INTERVAL) {
+                self.output()?;
+                while self.client.ready()? {
+                    let request = match self.client.receive()? {
+                        Some(request) => request,
+                        None => return Ok(Action::Disconnect),
+                    };
+                    match self.handle(state, meta, &request)? {
+                        Action::Pause => {
+                            meta.resume = Resume::Continue;
+                            self.stopped(state, "pause", None)?;
+                            return Ok(Action::Reply);
+                        }
+                        Action::Disconnect => return Ok(Action::Disconnect),
+                        Action::Resume(resume) => meta.resume = resume,
+                        Action::Reply => {}
+                    }
+                }
+            }
+        }
+    }
+
+    fn serve(&mut self, state: &mut State, meta: &mut Meta) -> BoxResult<()> {
+        while let Some(request) = self.client.receive()? {
+            let mut action = self.handle(state, meta, &request)?;
+            if let Action::Resume(resume) = action {
+                action = self.resume(state, meta, resume)?;
+            }
+            match action {
+                Action::Pause => self.stopped(state, "pause", None)?,
+                Action::Disconnect => {
+                    println!("dap: disconnected");
+                    return Ok(());
+                }
+                _ => {}
+            }
+        }
+        println!("dap: connection closed");
+        Ok(())
+    }
+}
+
+/// wait for an editor on the port and let it drive the VM over the debug adapter protocol
+pub fn serve(state: &mut State, meta: &mut Meta, port: u16, name: &str) -> BoxResult<()> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    println!("dap: listening on {}", listener.local_addr()?);
+    let (stream, address) = listener.accept()?;
+    println!("dap: {} connected", address);
+
+    let pipe = Rc::new(RefCell::new(Pipe::default()));
+    let terminal = mem::replace(&mut meta.io, Box::new(Piped(pipe.clone())));
+    let mut session = Session {
+        client: Client::new(stream)?,
+        pipe,
+        name: String::from(name),
+        line_base: 1,
+        launched: false,
+        stop_on_entry: false,
+        source_breakpoints: Vec::new(),
+        instruction_breakpoints: Vec::new(),
+        function_breakpoints: Vec::new(),
+    };
+    let result = session.serve(state, meta);
+    meta.io = terminal;
+    meta.resume = Resume::Continue;
+    result
+}
@@ -0,0 +1,224 @@
+use crate::debug::symbol::parse_address;
+use crate::debug::symbol::Symbols;
+use crate::opcode::disassemble;
+use crate::opcode::length;
+use crate::vm::BoxResult;
+
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{Error, ErrorKind};
+
+pub const EXECUTED: u8 = 1;
+pub const READ: u8 = 2;
+pub const WRITTEN: u8 = 4;
+
+/// 32768 words
+const WORDS: usize = 0x8000;
+
+/// what happened to each word of memory over one or more runs
+pub struct Coverage {
+    /// flags per word, indexed by byte address / 2
+    flags: Vec<u8>,
+    pub runs: u64,
+    /// fingerprint of the memory the runs started from, None until one is recorded or loaded
+    pub program: Option<u64>,
+}
+
+fn word(program: &[u8], address: usize) -> u16 {
+    let byte = |i: usize| program.get(i).copied().unwrap_or(0) as u16;
+    byte(address + 1) << 8 | byte(address)
+}
+
+/// FNV-1a over the bytes, stable across builds so files from different versions still compare
+pub fn fingerprint(program: &[u8]) -> u64 {
+    program.iter().fold(0xcbf29ce484222325, |hash, byte| (hash ^ *byte as u64).wrapping_mul(0x100000001b3))
+}
+
+impl Coverage {
+    /// nothing covered and no runs, to merge files into
+    pub fn empty() -> Coverage {
+        Coverage {
+            flags: vec![0; WORDS],
+            runs: 0,
+            program: None,
+        }
+    }
+
+    /// a fresh recording of a run starting from program counts as one run
+    pub fn new(program: &[u8]) -> Coverage {
+        Coverage {
+            runs: 1,
+            program: Some(fingerprint(program)),
+            ..Coverage::empty()
+        }
+    }
+
+    fn mark(&mut self, address: usize, flag: u8) {
+        if let Some(flags) = self.flags.get_mut(address / 2) {
+            *flags |= flag;
+        }
+    }
+
+    pub fn execute(&mut self, address: usize) {
+        self.mark(address, EXECUTED);
+    }
+
+    pub fn read(&mut self, address: usize) {
+        self.mark(address, READ);
+    }
+
+    pub fn write(&mut self, address: usize) {
+        self.mark(address, WRITTEN);
+    }
+
+    pub fn flags(&self, address: usize) -> u8 {
+        self.flags.get(address / 2).copied().unwrap_or(0)
+    }
+
+    fn executed(&self, address: usize) -> bool {
+        self.flags(address) & EXECUTED != 0
+    }
+
+    /// add the other runs, refused when they started from a different program
+    pub fn merge(&mut self, other: &Coverage) -> BoxResult<()> {
+        match (self.program, other.program) {
+            (Some(program), Some(other)) if program != other => {
+                return Err(Box::new(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("coverage of program {:016x} cannot be merged into coverage of {:016x}", other, program),
+                )));
+            }
+            (None, program) => self.program = program,
+            _ => {}
+        }
+        for (flags, other) in self.flags.iter_mut().zip(&other.flags) {
+            *flags |= other;
+        }
+        self.runs += other.runs;
+        Ok(())
+    }
+
+    /// read a coverage file, `runs <n>`, `program <fingerprint>` and then one `<address> <flags>` line per touched
+    /// word; files without a program line merge with anything
+    pub fn load(path: &str) -> BoxResult<Coverage> {
+        let mut coverage = Coverage::empty();
+        for line in fs::read_to_string(path)?.lines() {
+            let line = line.split('#').next().unwrap_or("");
+            let mut words = line.split_whitespace();
+            match (words.next(), words.next()) {
+                (Some("runs"), Some(runs)) => coverage.runs = runs.parse()?,
+                (Some("program"), Some(program)) => coverage.program = Some(u64::from_str_radix(program, 16)?),
+                (Some(address), Some(flags)) => {
+                    let address = parse_address(address)?;
+                    for flag in flags.chars() {
+                        match flag {
+                            'x' => coverage.execute(address),
+                            'r' => coverage.read(address),
+                            'w' => coverage.write(address),
+                            _ => {}
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(coverage)
+    }
+
+    pub fn save(&self, path: &str) -> BoxResult<()> {
+        let mut text = String::from("# synacor coverage, x executed, r read as data, w written\n");
+        let _ = writeln!(text, "runs {}", self.runs);
+        if let Some(program) = self.program {
+            let _ = writeln!(text, "program {:016x}", program);
+        }
+        for (i, flags) in self.flags.iter().enumerate().filter(|(_, flags)| **flags != 0) {
+            let _ = writeln!(text, "{:#06x} {}", i * 2, letters(*flags).replace('-', ""));
+        }
+        fs::write(path, text)?;
+        Ok(())
+    }
+
+    /// add this run to whatever the file already holds
+    pub fn accumulate(&self, path: &str) -> BoxResult<()> {
+        let mut total = if fs::metadata(path).is_ok() { Coverage::load(path)? } else { Coverage::empty() };
+        total.merge(self)?;
+        total.save(path)
+    }
+
+    /// instruction starts found by decoding straight through the program
+    fn instructions(program: &[u8]) -> Vec<usize> {
+        let mut starts = Vec::new();
+        let mut i = 0;
+        while i < program.len() {
+            starts.push(i);
+            i += length(program, i) * 2;
+        }
+        starts
+    }
+
+    /// totals and every conditional jump that only ever went one way
+    pub fn report(&self, program: &[u8], symbols: &Symbols) -> String {
+        let mut text = String::new();
+        let words = program.len() / 2;
+        let count = |flag: u8| self.flags.iter().take(words).filter(|flags| **flags & flag != 0).count();
+        let starts: Vec<usize> = Coverage::instructions(program)
+            .into_iter()
+            .filter(|address| !disassemble(program, *address).0.starts_with("data"))
+            .collect();
+        let executed = starts.iter().filter(|address| self.executed(**address)).count();
+
+        let _ = writeln!(text, "{} run(s)", self.runs);
+        let _ = writeln!(
+            text,
+            "instructions executed: {} of {} decoded ({:.2}%)",
+            executed,
+            starts.len(),
+            if starts.is_empty() { 0.0 } else { executed as f64 * 100.0 / starts.len() as f64 }
+        );
+        let _ = writeln!(text, "words executed: {} of {}", count(EXECUTED), words);
+        let _ = writeln!(text, "words read as data: {}", count(READ));
+        let _ = writeln!(text, "words written: {}", count(WRITTEN));
+
+        let _ = writeln!(text, "\nbranches only ever taken one way");
+        for address in (0..program.len()).step_by(2).filter(|address| self.executed(*address)) {
+            let op = word(program, address);
+            if op != 7 && op != 8 {
+                continue; // jt and jf
+            }
+            let target = word(program, address + 4);
+            let fall = address + 6;
+            let (instruction, _) = disassemble(program, address);
+            if target < 32768 && !self.executed(target as usize * 2) {
+                let target = target as usize * 2;
+                let _ = writeln!(text, "  {:<32} {:<20} never jumped to {}", symbols.label(address), instruction, symbols.label(target));
+            }
+            if !self.executed(fall) {
+                let _ = writeln!(text, "  {:<32} {:<20} never fell through to {}", symbols.label(address), instruction, symbols.label(fall));
+            }
+        }
+        text
+    }
+
+    /// the program disassembled with x, r and w columns for executed, read and written
+    pub fn annotate(&self, program: &[u8], symbols: &Symbols) -> String {
+        let mut text = String::from("# x executed, r read as data, w written, for any word of the instruction\n");
+        for address in Coverage::instructions(program) {
+            if let Some(name) = symbols.get(address) {
+                let _ = writeln!(text, "{}:", name);
+            }
+            let (instruction, length) = disassemble(program, address);
+            let flags = (0..length).fold(0, |flags, n| flags | self.flags(address + n * 2));
+            // execution only counts for the first word, that is where the ip was
+            let flags = (flags & !EXECUTED) | (self.flags(address) & EXECUTED);
+            let _ = writeln!(text, "{} {:#06x}  {}", letters(flags), address, instruction);
+        }
+        text
+    }
+}
+
+fn letters(flags: u8) -> String {
+    [(EXECUTED, 'x'), (READ, 'r'), (WRITTEN, 'w')]
+        .iter()
+        .map(|(flag, letter)| if flags & flag != 0 { *letter } else { '-' })
+        .collect()
+}
@@ -1,14 +1,20 @@
-use crate::opcode::parse;
-use crate::opcode::lookup;
-use crate::opcode::Code;
-use crate::vm::State;
+use crate::observer::Verbose;
+use crate::machine::Machine;
 use crate::vm::BoxResult;
 use crate::debug::Meta;
+use crate::debug::Resume;
+use crate::debug::Trace;
+use crate::debug::backtrace;
+use crate::debug::symbol::Symbols;
+use crate::debug::prompt::Prompt;
 
 use std::fs;
 use std::io;
 use std::io::Write;
 
+/// how deep macros may expand inside each other, a macro that uses itself stops here
+const MACRO_DEPTH: usize = 16;
+
 // !macro_rules command {
 
 // }
@@ -17,9 +23,12 @@ use std::io::Write;
 pub enum Command {
     Save(String),
     // Load(String),
-    Help,
+    Help(Option<String>),
     Run,
     Step(usize),
+    Next,
+    Finish,
+    Until(usize),
     DebugSet(bool),
     DebugGet,
     RegisterSet(usize, u16),
@@ -39,186 +48,444 @@ pub enum Command {
     PrintMemory,
     PrintMemoryRange(usize,usize),
     PrintMemoryX(usize),
+    Backtrace,
+    FrameSelect(usize),
+    FrameGet,
+    SymbolSet(usize, String),
+    SymbolGet,
+    Source(String),
+    Alias(String, String),
+    AliasGet,
+    Define(String),
     Halt,
 }
 
-pub fn print_memory(state: &mut State, start: usize, limit: usize) {
+/// disassemble from start up to limit, one instruction per line
+pub fn print_memory<M: Machine>(machine: &M, start: usize, limit: usize) {
     let mut i = start;
-    loop {
-        if i >= limit {
-            break;
-        }
-        let code = parse(&state.program, &i);
-        let curr = state.program[i];
-        if code == Code::Data {
-            if curr == 0x9B  || curr == 0x1B {
-                println!("{:#06X}: {:#04X} {} _", i, curr, code);
-            } else {
-                println!("{:#06X}: {:#04X} {} {}", i, curr, code, curr as u8 as char);
-            }
+    while i < limit {
+        let (text, length) = machine.decode(i);
+        println!("{:#06X}: {}", i, text);
+        i += length.max(1);
+    }
+}
+
+fn print_trace<M: Machine>(meta: &Meta<M>, n: usize, trace: &Trace) {
+    let function = match trace.function {
+        Some(address) => meta.symbols.describe(address),
+        None => String::from("<entry>"),
+    };
+    let marker = if n == meta.frame { '>' } else { ' ' };
+    println!(
+        "{}#{} {} at {:#06X} ({}) stack <{}..{}>",
+        marker,
+        n,
+        function,
+        trace.pc,
+        meta.symbols.describe(trace.pc),
+        trace.slots.start,
+        trace.slots.end
+    );
+}
+
+fn print_frame<M: Machine>(machine: &M, meta: &Meta<M>, n: usize, trace: &Trace) {
+    print_trace(meta, n, trace);
+    let stack = machine.stack();
+    for i in trace.slots.clone() {
+        if i == trace.slots.start && trace.function.is_some() {
+            println!("<{}> = {} (return to {:#06X})", i, stack[i], machine.return_address(stack[i]));
         } else {
-            println!("{:#06X}: {:#04X} {}", i, curr, code);
+            println!("<{}> = {}", i, stack[i]);
         }
-        i = i + code.len() * 2 + 2;
     }
 }
 
-pub fn debugger(state: &mut State, meta: &mut Meta) -> BoxResult<()>  {
-    println!("[IP] at {}", state.ip);
+pub fn debugger<M: Machine>(machine: &mut M, meta: &mut Meta<M>) -> BoxResult<()>  {
+    meta.io.flush();
+    println!("[IP] at {}", machine.ip());
+    print_memory(machine, machine.ip(), machine.ip() + 1);
+    meta.frame = 0;
     for counter in meta.counters.clone() {
         println!(" {}", counter);
     }
     loop {
-        print!("DEBUG> ");
-        io::stdout().flush()?; // flushing to ensure that DEBUG> gets printed before the read_line
-        let mut input = String::new();
-        io::stdin().read_line(&mut input)?;
-        let mut command = match lex(input) {
-            Ok(command) => command,
-            Err(error) => {
-                eprintln!("{}", error);
-                Command::Noop
+        let line = match next_line(meta, "DEBUG> ")? {
+            Some(line) => line,
+            None => {
+                // stdin is gone, nobody is left to resume us
+                meta.halt = true;
+                return Ok(());
             }
         };
+        if run_line(machine, meta, line)? {
+            return Ok(());
+        }
+    }
+}
 
-        match command {
-            Command::Null => {
-                command = meta.last.clone();
-            },
-            Command::Save(_) => {
-            },
-            _ => {
-                meta.last = command.clone();
-            }
+/// run queued command lines without prompting, stops early when one of them resumes the VM
+pub fn script<M: Machine>(machine: &mut M, meta: &mut Meta<M>) -> BoxResult<()> {
+    while let Some(line) = meta.pending.pop_front() {
+        if run_line(machine, meta, line)? {
+            break;
         }
+    }
+    Ok(())
+}
+
+/// queue the commands in a file so they run before anything typed at the prompt
+pub fn source<M: Machine>(meta: &mut Meta<M>, path: &str) -> BoxResult<()> {
+    let file = fs::read_to_string(path)?;
+    for line in script_lines(&file).into_iter().rev() {
+        meta.pending.push_front(line);
+    }
+    Ok(())
+}
+
+/// the non empty, non comment lines of a command file
+fn script_lines(file: &str) -> Vec<String> {
+    file.lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(String::from)
+        .collect()
+}
 
-        match command {
-            Command::Run => {
-                break;
-            }
-            Command::Noop => {
-            }
-            Command::PrintInfo => {
-                println!("[IP] at {}", state.ip);
-            }
-            Command::PrintMemory => {
-                print_memory(state, 0, state.program.len());
-                // let mut i = 0;
-                // loop {
-                //     if i >= state.program.len() {
-                //         break;
-                //     }
-                //     let code = parse(&state.program, &i);
-                //     let curr = state.program[i];
-                //     if code == Code::Data {
-                //         if curr == 0x9B  || curr == 0x1B {
-                //             println!("{:#06X}: {:#04X} {} _", i, curr, code);
-                //         } else {
-                //             println!("{:#06X}: {:#04X} {} {}", i, curr, code, curr as u8 as char);
-                //         }
-                //     } else {
-                //         println!("{:#06X}: {:#04X} {}", i, curr, code);
-                //     }
-                //     i = i + code.len() * 2 + 2;
-                // }
-            }
-            Command::PrintMemoryRange(mut n, m) => {
-                print_memory(state, n, m);
-                // loop {
-                //     if n > m {
-                //         break;
-                //     }
-                //     println!("{}: {}", n, state.program[n]);
-                //     n = n + 1;
-                // }
-            }
-            Command::PrintMemoryX(mut m) => {
-                let mut i = state.ip;
-                m = m + i;
-                print_memory(state, i, m);
-                // let mut i = state.ip;
-                // m = m + i;
-                // loop {
-                //     if i >= m {
-                //         break;
-                //     }
-                //     let code = parse(&state.program, &i);
-                //     println!("{}: {} {:?}", i, state.program[i], code);
-                //     i = i + code.len() * 2 + 2;
-                // }
-            }
-            Command::BreakPointOpSet(op) => {
-                meta.break_op = lookup(op);
-                println!("DEBUG: {}", meta.break_op);
-            }
-            Command::BreakPointOpGet => {
-                println!("DEBUG: {}", meta.break_op);
-            }
-            Command::DebugSet(value) => {
-                meta.debug = value;
-                println!("DEBUG: {}", meta.debug);
-            }
-            Command::DebugGet => {
-                println!("DEBUG: {}", meta.debug);
-            }
-            Command::RegisterSet(register, value) => {
-                state.register[register] = value;
+/// next command line, queued lines first, then stdin; None once stdin is closed
+fn next_line<M: Machine>(meta: &mut Meta<M>, prompt: &str) -> BoxResult<Option<String>> {
+    if let Some(line) = meta.pending.pop_front() {
+        return Ok(Some(line));
+    }
+    meta.expansions.clear(); // typed lines are never part of a macro
+    io::stdout().flush()?; // guest output might still be sitting in the buffer
+    if meta.prompt.is_none() {
+        meta.prompt = Some(Prompt::new()?);
+    }
+    let commands = meta.aliases.keys().chain(meta.macros.keys()).cloned().collect();
+    let symbols = meta.symbols.iter().map(|(_, name)| name.clone()).collect();
+
+    let editor = meta.prompt.as_mut().expect("created above");
+    let completion = editor.completion();
+    completion.commands = commands;
+    completion.symbols = symbols;
+    editor.read(prompt)
+}
+
+/// expand aliases and macros, then lex and execute a line, returns true when the VM should resume
+fn run_line<M: Machine>(machine: &mut M, meta: &mut Meta<M>, mut line: String) -> BoxResult<bool> {
+    let first = line.split_whitespace().next().unwrap_or("").to_owned();
+    if let Some(expansion) = meta.aliases.get(&first) {
+        let rest = line.trim_start()[first.len()..].to_owned();
+        line = format!("{}{}", expansion, rest);
+    }
+
+    let first = line.split_whitespace().next().unwrap_or("");
+    if let Some(body) = meta.macros.get(first) {
+        // an expansion is over once the queue is back to what was behind it
+        let left = meta.pending.len();
+        meta.expansions.retain(|behind| *behind <= left);
+        if meta.expansions.len() >= MACRO_DEPTH {
+            eprintln!("macro {} nests deeper than {}, not expanding it", first, MACRO_DEPTH);
+            return Ok(false);
+        }
+        meta.expansions.push(left);
+        for line in body.iter().rev() {
+            meta.pending.push_front(line.clone());
+        }
+        return Ok(false);
+    }
+
+    let mut command = match lex(line, &meta.symbols) {
+        Ok(command) => command,
+        Err(error) => {
+            eprintln!("{}", error);
+            Command::Noop
+        }
+    };
+
+    match command {
+        Command::Null => {
+            command = meta.last.clone();
+        },
+        Command::Save(_) => {
+        },
+        _ => {
+            meta.last = command.clone();
+        }
+    }
+
+    execute(machine, meta, command)
+}
+
+/// execute a single command, returns true when the VM should resume
+fn execute<M: Machine>(machine: &mut M, meta: &mut Meta<M>, command: Command) -> BoxResult<bool> {
+    match command {
+        Command::Run => {
+            return Ok(true);
+        }
+        Command::Noop => {
+        }
+        Command::PrintInfo => {
+            println!("[IP] at {}", machine.ip());
+        }
+        Command::PrintMemory => {
+            print_memory(machine, 0, machine.memory().len());
+            // let mut i = 0;
+            // loop {
+            //     if i >= state.program.len() {
+            //         break;
+            //     }
+            //     let code = parse(&state.program, &i);
+            //     let curr = state.program[i];
+            //     if code == Code::Data {
+            //         if curr == 0x9B  || curr == 0x1B {
+            //             println!("{:#06X}: {:#04X} {} _", i, curr, code);
+            //         } else {
+            //             println!("{:#06X}: {:#04X} {} {}", i, curr, code, curr as u8 as char);
+            //         }
+            //     } else {
+            //         println!("{:#06X}: {:#04X} {}", i, curr, code);
+            //     }
+            //     i = i + code.len() * 2 + 2;
+            // }
+        }
+        Command::PrintMemoryRange(n, m) => {
+            print_memory(machine, n, m);
+            // loop {
+            //     if n > m {
+            //         break;
+            //     }
+            //     println!("{}: {}", n, state.program[n]);
+            //     n = n + 1;
+            // }
+        }
+        Command::PrintMemoryX(mut m) => {
+            let i = machine.ip();
+            m += i;
+            print_memory(machine, i, m);
+            // let mut i = state.ip;
+            // m = m + i;
+            // loop {
+            //     if i >= m {
+            //         break;
+            //     }
+            //     let code = parse(&state.program, &i);
+            //     println!("{}: {} {:?}", i, state.program[i], code);
+            //     i = i + code.len() * 2 + 2;
+            // }
+        }
+        Command::BreakPointOpSet(op) => {
+            M::break_on(&mut meta.hooks, op);
+            println!("DEBUG: {}", M::break_op(&meta.hooks));
+        }
+        Command::BreakPointOpGet => {
+            println!("DEBUG: {}", M::break_op(&meta.hooks));
+        }
+        Command::DebugSet(value) => {
+            meta.observer = if value { Some(Box::new(Verbose)) } else { None };
+            println!("DEBUG: {}", value);
+        }
+        Command::DebugGet => {
+            println!("DEBUG: {}", meta.observer.is_some());
+        }
+        Command::RegisterSet(register, value) => {
+            if machine.set_register(register, value as u64) {
                 println!("DEBUG: [{}] = {}", register, value);
+            } else {
+                println!("DEBUG: no register {}", register);
+            }
+        }
+        Command::RegisterGet => {
+            println!("DEBUG: {:?}", machine.registers());
+        }
+        Command::RegisterGetN(register) => {
+            match machine.registers().get(register) {
+                Some(value) => println!("DEBUG: [{}]: {}", register, value),
+                None => println!("DEBUG: no register {}", register),
             }
-            Command::RegisterGet => {
-                println!("DEBUG: {:?}", state.register);
+        }
+        Command::Help(None) => {
+            for help in COMMANDS {
+                println!("{:<24} {}", help.usage, help.about);
             }
-            Command::RegisterGetN(register) => {
-                println!("DEBUG: [{}]: {}", register, state.register[register]);
+        }
+        Command::Help(Some(name)) => {
+            if let Some(help) = help_for(&name) {
+                print_help(help);
+            } else if let Some(expansion) = meta.aliases.get(&name) {
+                println!("{} is an alias for {}", name, expansion);
+            } else if meta.macros.contains_key(&name) {
+                println!("{} is a macro", name);
+            } else {
+                println!("DEBUG: no command {}", name);
             }
-            Command::Help => {
-                println!("DEBUG: What are you asking me for? Read the source code!");
+        }
+        Command::Step(n) => {
+            if n > 1 {
+                println!("stepping {}", n);
+            } else {
+                println!("step");
             }
-            Command::Step(n) => {
-                if n > 0 {
-                    println!("stepping {}", n);
-                    meta.debugging = true;
-                } else {
-                    println!("step");
-                    meta.debugging = true;
-                }
-                //         meta.counters.push(100);
-                return Ok(())
+            meta.resume = Resume::Step(n.max(1));
+            return Ok(true)
+        }
+        Command::Next => {
+            if machine.is_call(machine.ip()) {
+                println!("stepping over call");
+                meta.resume = Resume::Return(machine.frames().len());
+            } else {
+                println!("step");
+                meta.resume = Resume::Step(1);
             }
-            Command::Save(path) => {
-                println!("saving program to {}", path);
-                fs::write(path, State::save(state))?;
-                println!("dumped!");
+            return Ok(true)
+        }
+        Command::Finish => {
+            if let Some(frame) = machine.frames().last() {
+                println!("running until {} returns", meta.symbols.describe(frame.callee));
+                meta.resume = Resume::Return(machine.frames().len() - 1);
+                return Ok(true)
             }
-            Command::StackGet => {
-                println!("DEBUG: {:?}", state.stack);
+            println!("DEBUG: not inside a call");
+        }
+        Command::Until(address) => {
+            println!("running until {:#06X}", address);
+            meta.resume = Resume::Until(address);
+            return Ok(true)
+        }
+        Command::Save(path) => {
+            println!("saving program to {}", path);
+            fs::write(path, machine.snapshot())?;
+            println!("dumped!");
+        }
+        Command::StackGet => {
+            println!("DEBUG: {:?}", machine.stack());
+        }
+        Command::StackSet(index, value) => {
+            if machine.set_stack(index, value as u64) {
+                println!("DEBUG: {:?}", machine.stack());
+            } else {
+                println!("DEBUG: no stack slot {}", index);
             }
-            Command::StackSet(index, value) => {
-                state.stack[index] = value;
-                println!("DEBUG: {:?}", state.stack);
+        }
+        Command::StackGetN(index) => {
+            match machine.stack().get(index) {
+                Some(value) => println!("DEBUG: {:?}", value),
+                None => println!("DEBUG: no stack slot {}", index),
             }
-            Command::StackGetN(index) => {
-                println!("DEBUG: {:?}", state.stack[index]);
+        }
+        Command::Backtrace => {
+            for (n, trace) in backtrace(machine).iter().enumerate() {
+                print_trace(meta, n, trace);
             }
-            Command::Null => {
+        }
+        Command::FrameSelect(n) => {
+            let traces = backtrace(machine);
+            if let Some(trace) = traces.get(n) {
+                meta.frame = n;
+                print_frame(machine, meta, n, trace);
+            } else {
+                println!("DEBUG: no frame {}, there are {}", n, traces.len());
+            }
+        }
+        Command::FrameGet => {
+            let traces = backtrace(machine);
+            let n = meta.frame.min(traces.len() - 1);
+            print_frame(machine, meta, n, &traces[n]);
+        }
+        Command::SymbolSet(address, name) => {
+            println!("DEBUG: {:#06X} = {}", address, name);
+            meta.symbols.insert(address, name);
+        }
+        Command::SymbolGet => {
+            for (address, name) in meta.symbols.iter() {
+                println!("{:#06X}: {}", address, name);
             }
-            Command::Halt => {
-                meta.halt = !meta.halt;
-                println!("DEBUG: halt set to: {}", meta.halt);
+        }
+        Command::Source(path) => {
+            if let Err(error) = source(meta, &path) {
+                eprintln!("{}: {}", path, error);
             }
         }
+        Command::Alias(name, expansion) => {
+            println!("DEBUG: {} = {}", name, expansion);
+            meta.aliases.insert(name, expansion);
+        }
+        Command::AliasGet => {
+            for (name, expansion) in meta.aliases.iter() {
+                println!("{} = {}", name, expansion);
+            }
+        }
+        Command::Define(name) => {
+            let mut body = Vec::new();
+            loop {
+                match next_line(meta, "> ")? {
+                    Some(line) if line.trim() == "end" => break,
+                    Some(line) => body.extend(script_lines(&line)),
+                    None => break,
+                }
+            }
+            println!("DEBUG: {} defined with {} commands", name, body.len());
+            meta.macros.insert(name, body);
+        }
+        Command::Null => {
+        }
+        Command::Halt => {
+            meta.halt = !meta.halt;
+            println!("DEBUG: halt set to: {}", meta.halt);
+        }
+    }
+    Ok(false)
+}
+
+/// a debugger command as listed by `help`, the first name is the one `lex` matches on
+pub struct Help {
+    pub names: &'static [&'static str],
+    pub usage: &'static str,
+    pub about: &'static str,
+}
+
+pub const COMMANDS: &[Help] = &[
+    Help { names: &["run"], usage: "run", about: "leave the debugger and continue execution" },
+    Help { names: &["step", "n"], usage: "step [n]", about: "execute n instructions, 1 by default" },
+    Help { names: &["next", "over"], usage: "next", about: "step over a call, otherwise execute one instruction" },
+    Help { names: &["finish", "fin"], usage: "finish", about: "run until the current function returns" },
+    Help { names: &["until", "u"], usage: "until <address>", about: "run until ip reaches the address" },
+    Help { names: &["memory", "m"], usage: "memory [start [end]]", about: "disassemble all memory, n bytes from ip, or a range" },
+    Help { names: &["stack", "s"], usage: "stack [n]", about: "print the stack or a single slot of it" },
+    Help { names: &["register", "r"], usage: "register [n [value]]", about: "print all registers, or print or set register n" },
+    Help { names: &["bp", "op"], usage: "bp [opcode]", about: "print or set the opcode to break on" },
+    Help { names: &["debug"], usage: "debug [on|off]", about: "print or set verbose execution output" },
+    Help { names: &["backtrace", "bt"], usage: "backtrace", about: "print the call stack with symbol names" },
+    Help { names: &["frame", "f"], usage: "frame [n]", about: "select frame n and print the stack slots it owns" },
+    Help { names: &["symbol", "sym"], usage: "symbol [address name]", about: "list symbols or name an address" },
+    Help { names: &["source"], usage: "source <file>", about: "run the debugger commands in a file" },
+    Help { names: &["alias"], usage: "alias [name command...]", about: "list aliases or make name expand to command" },
+    Help { names: &["define"], usage: "define <name>", about: "record the following lines up to `end` as a macro" },
+    Help { names: &["save"], usage: "save [file]", about: "write a snapshot of the VM, ./out by default" },
+    Help { names: &["halt"], usage: "halt", about: "toggle halting once the debugger is left" },
+    Help { names: &["help", "man", "?"], usage: "help [command]", about: "list commands or describe one" },
+];
+
+/// find the table entry for any of a command's names
+pub fn help_for(name: &str) -> Option<&'static Help> {
+    COMMANDS.iter().find(|help| help.names.contains(&name))
+}
+
+fn print_help(help: &Help) {
+    println!("{:<24} {}", help.usage, help.about);
+    if help.names.len() > 1 {
+        println!("{:<24} also: {}", "", help.names[1..].join(", "));
     }
-    Ok(())
 }
 
-fn lex(line: String) -> BoxResult<Command> {
+fn lex(line: String, symbols: &Symbols) -> BoxResult<Command> {
     use std::io::{Error, ErrorKind};
 
     let mut argv = line.split_whitespace();
 
     if let Some(command) = argv.next() {
-        match command {
-            "n" | "step" => {
+        match help_for(command).map(|help| help.names[0]).unwrap_or(command) {
+            "step" => {
                 if let Some(arg) = argv.next() {
                     let n = arg.parse::<usize>()?;
                     Ok(Command::Step(n))
@@ -226,20 +493,33 @@ fn lex(line: String) -> BoxResult<Command> {
                     Ok(Command::Step(0))
                 }
             }
-            "m" | "memory" => {
+            "next" => {
+                Ok(Command::Next)
+            }
+            "finish" => {
+                Ok(Command::Finish)
+            }
+            "until" => {
+                if let Some(arg) = argv.next() {
+                    Ok(Command::Until(symbols.resolve(arg)?))
+                } else {
+                    Err(Box::new(Error::new(ErrorKind::InvalidInput, "until needs an address".to_string())))
+                }
+            }
+            "memory" => {
                 if let Some(arg) = argv.next() {
-                    let n = arg.parse::<usize>()?;
                     if let Some(arg2) = argv.next() {
-                        let m = arg2.parse::<usize>()?;
+                        let n = symbols.resolve(arg)?;
+                        let m = symbols.resolve(arg2)?;
                         Ok(Command::PrintMemoryRange(n, m))
                     } else {
-                        Ok(Command::PrintMemoryX(n))
+                        Ok(Command::PrintMemoryX(arg.parse::<usize>()?))
                     }
                 } else {
                     Ok(Command::PrintMemory)
                 }
             }
-            "s" | "stack" => {
+            "stack" => {
                 if let Some(arg) = argv.next() {
                     let n = arg.parse::<usize>()?;
                     Ok(Command::StackGetN(n))
@@ -254,7 +534,7 @@ fn lex(line: String) -> BoxResult<Command> {
                 println!("command fuck not given");
                 Ok(Command::Noop)
             }
-            "op" | "bp" | "bp op" => {
+            "bp" => {
                 if let Some(arg) = argv.next() {
                     let n = arg.parse::<u8>()?;
                     Ok(Command::BreakPointOpSet(n))
@@ -262,13 +542,9 @@ fn lex(line: String) -> BoxResult<Command> {
                     Ok(Command::BreakPointOpGet)
                 }
             }
-            "r" | "register" => {
+            "register" => {
                 if let Some(register) = argv.next() {
                     let register = register.parse::<usize>()?;
-
-                    if register > 7 {
-                        return Err(Box::new(Error::new(ErrorKind::InvalidInput, format!("We only have 8 registers, thats 0 to 7"))))
-                    }
                     if let Some(value) = argv.next() {
                         let value = value.parse::<u16>()?;
                         Ok(Command::RegisterSet(register, value))
@@ -294,12 +570,60 @@ fn lex(line: String) -> BoxResult<Command> {
                     Ok(Command::DebugGet)
                 }
             }
-            "help" | "man" | "?" => {
-                Ok(Command::Help)
+            "help" => {
+                Ok(Command::Help(argv.next().map(String::from)))
             }
             "halt" => {
                 Ok(Command::Halt)
             }
+            "source" => {
+                if let Some(path) = argv.next() {
+                    Ok(Command::Source(String::from(path)))
+                } else {
+                    Err(Box::new(Error::new(ErrorKind::InvalidInput, "source needs a file".to_string())))
+                }
+            }
+            "alias" => {
+                if let Some(name) = argv.next() {
+                    let expansion = argv.collect::<Vec<&str>>().join(" ");
+                    if expansion.is_empty() {
+                        return Err(Box::new(Error::new(ErrorKind::InvalidInput, format!("no expansion given for {}", name))))
+                    }
+                    Ok(Command::Alias(String::from(name), expansion))
+                } else {
+                    Ok(Command::AliasGet)
+                }
+            }
+            "define" => {
+                if let Some(name) = argv.next() {
+                    Ok(Command::Define(String::from(name)))
+                } else {
+                    Err(Box::new(Error::new(ErrorKind::InvalidInput, "define needs a name".to_string())))
+                }
+            }
+            "backtrace" => {
+                Ok(Command::Backtrace)
+            }
+            "frame" => {
+                if let Some(arg) = argv.next() {
+                    let n = arg.parse::<usize>()?;
+                    Ok(Command::FrameSelect(n))
+                } else {
+                    Ok(Command::FrameGet)
+                }
+            }
+            "symbol" => {
+                if let Some(arg) = argv.next() {
+                    let address = symbols.resolve(arg)?;
+                    if let Some(name) = argv.next() {
+                        Ok(Command::SymbolSet(address, String::from(name)))
+                    } else {
+                        Err(Box::new(Error::new(ErrorKind::InvalidInput, format!("no name given for {}", arg))))
+                    }
+                } else {
+                    Ok(Command::SymbolGet)
+                }
+            }
             "save" => {
                 if let Some(path) = argv.next() {
                     Ok(Command::Save(String::from(path)))
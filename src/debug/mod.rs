@@ -1,38 +1,122 @@
+use crate::console::Io;
+use crate::console::Terminal;
 use crate::debug::debugger::Command;
+use crate::debug::symbol::Symbols;
+use crate::debug::prompt::Prompt;
+use crate::debug::profile::Profile;
+use std::collections::HashMap;
+use std::collections::VecDeque;
 use std::fmt;
-use crate::opcode::Code;
+use std::ops::Range;
+use crate::observer::Observer;
+use crate::machine::Machine;
+use crate::vm::State;
 
+pub mod coverage;
 pub mod debugger;
+pub mod profile;
+pub mod prompt;
+pub mod smc;
+pub mod symbol;
 
 
-pub struct Meta {
+/// how far the main loop runs before handing control back to the debugger
+#[derive(PartialEq, Clone, Debug)]
+pub enum Resume {
+    /// run until something else stops us
+    Continue,
+    /// stop after this many more instructions
+    Step(usize),
+    /// stop once the shadow call stack is no deeper than this
+    Return(usize),
+    /// stop once ip reaches this address
+    Until(usize),
+}
+
+/// why the guest itself handed over to the debugger, set next to `debugging` by the instruction that did it
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Reason {
+    /// an opcode past 21, or a word past 255 where an opcode should be
+    UnknownOpcode,
+    /// mod with a zero divisor
+    DivideByZero,
+    /// in with no input left
+    Input,
+    /// wmem into executed or decoded code while `--smc-break` is on
+    CodeWrite,
+}
+
+/// the debugger's state around a machine, the same for every architecture; what only one machine needs goes in hooks
+pub struct Meta<M: Machine = State> {
     pub op_count: usize,
     pub breakpoint: bool,
-    pub debug: bool,
+    /// told about every instruction as it runs, `-d` and `debug on` put the verbose trace here
+    pub observer: Option<Box<dyn Observer<M>>>,
     pub pause: bool,
     pub debugging: bool,
+    /// set when the guest stopped itself, None for breakpoints, steps and interrupts
+    pub reason: Option<Reason>,
     pub breakpoints: Vec<usize>,
-    pub break_op: Code,
     pub halt: bool,
     pub last: Command,
     pub counters: Vec<usize>,
+    pub symbols: Symbols,
+    pub frame: usize,
+    pub resume: Resume,
+    pub pending: VecDeque<String>,
+    /// per macro expansion still running, how many queued lines were behind its body
+    pub expansions: Vec<usize>,
+    pub aliases: HashMap<String, String>,
+    pub macros: HashMap<String, Vec<String>>,
+    pub prompt: Option<Prompt>,
+    pub io: Box<dyn Io>,
+    pub profile: Option<Profile>,
+    pub hooks: M::Hooks,
 }
 
-impl Meta {
-    pub fn new() -> Meta {
-        return Meta {
+impl<M: Machine> Meta<M> {
+    pub fn new() -> Meta<M> {
+        Meta {
             op_count: 0,
             breakpoint: true,
             debugging: false,
+            reason: None,
             pause: true,
             breakpoints: Vec::new(),
             counters: Vec::new(),
-            break_op: Code::Halt, // by default break on Halt
             halt: false,
             last: Command::Null,
-            debug: false,
+            observer: None,
+            symbols: Symbols::new(),
+            frame: 0,
+            resume: Resume::Continue,
+            pending: VecDeque::new(),
+            expansions: Vec::new(),
+            aliases: HashMap::new(),
+            macros: HashMap::new(),
+            prompt: None,
+            io: Box::new(Terminal::new()),
+            profile: None,
+            hooks: M::Hooks::default(),
         }
     }
+
+    /// called by the main loop after every instruction, true when the debugger should open
+    pub fn stop(&mut self, machine: &M) -> bool {
+        let stop = match self.resume {
+            Resume::Continue => false,
+            Resume::Step(ref mut n) => {
+                *n -= 1;
+                *n == 0
+            }
+            Resume::Return(depth) => machine.frames().len() <= depth,
+            Resume::Until(address) => machine.ip() == address,
+        };
+        if stop {
+            self.resume = Resume::Continue;
+        }
+        stop
+    }
     // pub fn recover(op_count: usize) -> Meta {
     //     return Meta {
     //         op_count,
@@ -46,14 +130,46 @@ impl Meta {
     // }
 }
 
+/// one frame of a reconstructed backtrace
+pub struct Trace {
+    /// called address, None for the outermost frame
+    pub function: Option<usize>,
+    /// where execution is in this frame, the call site for outer frames
+    pub pc: usize,
+    /// stack slots owned by the frame, starting at its return address
+    pub slots: Range<usize>,
+}
+
+/// rebuild the backtrace from the shadow call stack, innermost frame first
+pub fn backtrace<M: Machine>(machine: &M) -> Vec<Trace> {
+    let mut traces = Vec::new();
+    let mut pc = machine.ip();
+    let mut end = machine.stack().len();
+    for frame in machine.frames().iter().rev() {
+        let start = frame.depth - 1;
+        traces.push(Trace {
+            function: Some(frame.callee),
+            pc,
+            slots: start..end,
+        });
+        pc = frame.caller;
+        end = start;
+    }
+    traces.push(Trace {
+        function: None,
+        pc,
+        slots: 0..end,
+    });
+    traces
+}
 
-impl fmt::Debug for Meta {
+impl<M: Machine> fmt::Debug for Meta<M> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{:?}", self.op_count)
     }
 }
 
-impl fmt::Display for Meta {
+impl<M: Machine> fmt::Display for Meta<M> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{}", self.op_count)
     }
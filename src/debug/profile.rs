@@ -0,0 +1,183 @@
+use crate::debug::symbol::Symbols;
+use crate::machine::{Frame, Machine};
+use crate::vm::BoxResult;
+
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::fs;
+
+/// rows in each table of the report
+const TOP: usize = 20;
+
+/// byte addresses the ip can take
+const MEMORY: usize = 0x10000;
+
+/// instruction counts by address, opcode and call stack
+pub struct Profile {
+    total: u64,
+    addresses: Vec<u64>,
+    opcodes: [u64; 256],
+    /// called addresses from the outermost frame in, mapped to instructions run with exactly that stack
+    stacks: HashMap<Vec<usize>, u64>,
+    /// instructions run since the stack last changed
+    pending: u64,
+    /// called addresses of the stack the pending count belongs to
+    current: Vec<usize>,
+    /// its depth and innermost callee, enough to tell when the stack changes
+    shape: (usize, Option<usize>),
+}
+
+fn percent(count: u64, total: u64) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        count as f64 * 100.0 / total as f64
+    }
+}
+
+impl Profile {
+    pub fn new() -> Profile {
+        Profile {
+            total: 0,
+            addresses: vec![0; MEMORY],
+            opcodes: [0; 256],
+            stacks: HashMap::new(),
+            pending: 0,
+            current: Vec::new(),
+            shape: (0, None),
+        }
+    }
+
+    /// count the instruction at ip, called right before it executes
+    pub fn record<M: Machine>(&mut self, machine: &M) {
+        let ip = machine.ip();
+        let frames = machine.frames();
+        self.total += 1;
+        if let Some(count) = self.addresses.get_mut(ip) {
+            *count += 1;
+        }
+        if let Some(op) = machine.opcode(ip) {
+            self.opcodes[op as usize] += 1;
+        }
+        // one instruction pushes a frame or pops any number of them, either way depth or top changes
+        let shape = (frames.len(), frames.last().map(|frame| frame.callee));
+        if shape != self.shape {
+            self.flush(frames);
+            self.shape = shape;
+        }
+        self.pending += 1;
+    }
+
+    /// file the pending count under the stack it was counted with, then start counting for the machine's
+    fn flush(&mut self, frames: &[Frame]) {
+        if self.pending > 0 {
+            *self.stacks.entry(self.current.clone()).or_insert(0) += self.pending;
+            self.pending = 0;
+        }
+        self.current = frames.iter().map(|frame| frame.callee).collect();
+    }
+
+    fn stacks(&self) -> HashMap<Vec<usize>, u64> {
+        let mut stacks = self.stacks.clone();
+        if self.pending > 0 {
+            *stacks.entry(self.current.clone()).or_insert(0) += self.pending;
+        }
+        stacks
+    }
+
+    /// stacks in the folded format flamegraph.pl and inferno read, one `outer;inner count` per line
+    pub fn folded(&self, symbols: &Symbols) -> String {
+        let mut lines: Vec<String> = self
+            .stacks()
+            .into_iter()
+            .map(|(stack, count)| {
+                let mut names = vec![String::from("entry")];
+                names.extend(stack.iter().map(|address| symbols.describe(*address)));
+                format!("{} {}", names.join(";"), count)
+            })
+            .collect();
+        lines.sort();
+        let mut text = lines.join("\n");
+        text.push('\n');
+        text
+    }
+
+    pub fn report<M: Machine>(&self, machine: &M, symbols: &Symbols) -> String {
+        let mut text = String::new();
+        let total = self.total;
+        let _ = writeln!(text, "{} instructions", total);
+
+        let mut addresses: Vec<(usize, u64)> = self
+            .addresses
+            .iter()
+            .enumerate()
+            .filter(|(_, count)| **count > 0)
+            .map(|(address, count)| (address, *count))
+            .collect();
+        addresses.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        let _ = writeln!(text, "\ntop addresses\n{:>12} {:>7}  {:<8} {:<24} instruction", "count", "%", "address", "symbol");
+        for (address, count) in addresses.iter().take(TOP) {
+            let _ = writeln!(
+                text,
+                "{:>12} {:>6.2}%  {:#06X}   {:<24} {}",
+                count,
+                percent(*count, total),
+                address,
+                symbols.describe(*address),
+                machine.decode(*address).0
+            );
+        }
+
+        let mut opcodes: Vec<(usize, u64)> = self
+            .opcodes
+            .iter()
+            .enumerate()
+            .filter(|(_, count)| **count > 0)
+            .map(|(op, count)| (op, *count))
+            .collect();
+        opcodes.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        let _ = writeln!(text, "\nopcodes\n{:>12} {:>7}  opcode", "count", "%");
+        for (op, count) in opcodes {
+            let _ = writeln!(text, "{:>12} {:>6.2}%  {}", count, percent(count, total), machine.mnemonic(op as u8));
+        }
+
+        // exclusive counts the innermost frame, inclusive every distinct function on the stack
+        let mut functions: HashMap<Option<usize>, (u64, u64)> = HashMap::new();
+        for (stack, count) in self.stacks() {
+            functions.entry(stack.last().copied()).or_insert((0, 0)).1 += count;
+            let mut seen: Vec<Option<usize>> = vec![None];
+            seen.extend(stack.iter().map(|address| Some(*address)));
+            seen.sort();
+            seen.dedup();
+            for function in seen {
+                functions.entry(function).or_insert((0, 0)).0 += count;
+            }
+        }
+        let mut functions: Vec<(Option<usize>, (u64, u64))> = functions.into_iter().collect();
+        functions.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        let _ = writeln!(text, "\ntop functions\n{:>12} {:>7} {:>12} {:>7}  function", "inclusive", "%", "exclusive", "%");
+        for (function, (inclusive, exclusive)) in functions.iter().take(TOP) {
+            let name = match function {
+                Some(address) => symbols.describe(*address),
+                None => String::from("<entry>"),
+            };
+            let _ = writeln!(
+                text,
+                "{:>12} {:>6.2}% {:>12} {:>6.2}%  {}",
+                inclusive,
+                percent(*inclusive, total),
+                exclusive,
+                percent(*exclusive, total),
+                name
+            );
+        }
+        text
+    }
+
+    /// write the report to the path and the folded stacks next to it
+    pub fn write<M: Machine>(&self, path: &str, machine: &M, symbols: &Symbols) -> BoxResult<()> {
+        fs::write(path, self.report(machine, symbols))?;
+        fs::write(format!("{}.folded", path), self.folded(symbols))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,124 @@
+use crate::debug::debugger::help_for;
+use crate::debug::debugger::COMMANDS;
+use crate::vm::BoxResult;
+
+use rustyline::completion::{Completer, FilenameCompleter, Pair};
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Config, Context, Editor, Helper};
+use std::env;
+use std::path::Path;
+
+/// commands that take a file instead of an address
+const FILE_COMMANDS: &[&str] = &["source", "save"];
+
+/// completes command, alias, macro and symbol names and file paths at the prompt
+pub struct Completion {
+    pub commands: Vec<String>,
+    pub symbols: Vec<String>,
+    files: FilenameCompleter,
+}
+
+fn candidates<'a>(word: &str, names: impl Iterator<Item = &'a str>) -> Vec<Pair> {
+    let mut pairs: Vec<Pair> = names
+        .filter(|name| name.starts_with(word))
+        .map(|name| Pair { display: name.to_owned(), replacement: name.to_owned() })
+        .collect();
+    pairs.sort_by(|a, b| a.display.cmp(&b.display));
+    pairs.dedup_by(|a, b| a.display == b.display);
+    pairs
+}
+
+impl Completer for Completion {
+    type Candidate = Pair;
+
+    fn complete(&self, line: &str, pos: usize, _: &Context<'_>) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let head = &line[..pos];
+        let start = head.rfind(char::is_whitespace).map(|i| i + 1).unwrap_or(0);
+        let word = &head[start..];
+        let command = head.split_whitespace().next().map(|name| help_for(name).map(|help| help.names[0]).unwrap_or(name));
+
+        let commands = || COMMANDS.iter()
+            .flat_map(|help| help.names.iter().copied())
+            .chain(self.commands.iter().map(|name| name.as_str()));
+
+        match command {
+            _ if start == 0 => Ok((start, candidates(word, commands()))),
+            Some(command) if FILE_COMMANDS.contains(&command) => self.files.complete_path(line, pos),
+            Some("help") => Ok((start, candidates(word, commands()))),
+            _ => Ok((start, candidates(word, self.symbols.iter().map(|name| name.as_str())))),
+        }
+    }
+}
+
+impl Hinter for Completion {
+    type Hint = String;
+}
+
+impl Highlighter for Completion {}
+
+impl Validator for Completion {}
+
+impl Helper for Completion {}
+
+/// line editor behind the DEBUG> prompt, history is kept in ~/.synacor_history
+pub struct Prompt {
+    editor: Editor<Completion, DefaultHistory>,
+    history: Option<String>,
+}
+
+impl Prompt {
+    pub fn new() -> BoxResult<Prompt> {
+        let config = Config::builder().auto_add_history(false).build();
+        let mut editor = Editor::with_config(config)?;
+        editor.set_helper(Some(Completion {
+            commands: Vec::new(),
+            symbols: Vec::new(),
+            files: FilenameCompleter::new(),
+        }));
+
+        // history is best effort, a prompt without it beats no prompt
+        let history = env::var("HOME").ok().map(|home| format!("{}/.synacor_history", home));
+        if let Some(path) = &history {
+            if Path::new(path).is_file() {
+                if let Err(error) = editor.load_history(path) {
+                    eprintln!("DEBUG: could not load history from {}: {}", path, error);
+                }
+            }
+        }
+
+        Ok(Prompt { editor, history })
+    }
+
+    /// the completer, to refresh the alias, macro and symbol names it offers
+    pub fn completion(&mut self) -> &mut Completion {
+        self.editor.helper_mut().expect("the prompt always has a completer")
+    }
+
+    /// read a line, Ctrl-C discards the line and asks again, None once input is closed
+    pub fn read(&mut self, prompt: &str) -> BoxResult<Option<String>> {
+        loop {
+            match self.editor.readline(prompt) {
+                Ok(line) => {
+                    if !line.trim().is_empty() {
+                        let _ = self.editor.add_history_entry(line.as_str());
+                        if let Some(path) = &self.history {
+                            if let Err(error) = self.editor.save_history(path) {
+                                // say it once rather than after every line
+                                eprintln!("DEBUG: could not save history to {}, not trying again: {}", path, error);
+                                self.history = None;
+                            }
+                        }
+                    }
+                    return Ok(Some(line));
+                }
+                Err(ReadlineError::Interrupted) => continue,
+                Err(ReadlineError::Eof) => return Ok(None),
+                Err(error) => return Err(Box::new(error)),
+            }
+        }
+    }
+}
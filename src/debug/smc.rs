@@ -0,0 +1,139 @@
+use crate::debug::symbol::Symbols;
+use crate::opcode::disassemble;
+use crate::opcode::length;
+use crate::vm::State;
+
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::fmt::Write as _;
+
+/// 32768 words
+const WORDS: usize = 0x8000;
+
+/// one write into memory that had already run or been decoded as code
+pub struct Modification {
+    /// address of the wmem that did it
+    pub writer: usize,
+    pub address: usize,
+    /// start of the instruction the word belonged to
+    pub instruction: usize,
+    /// false when the instruction was only decoded, never run
+    pub executed: bool,
+    pub old: String,
+    pub new: String,
+}
+
+/// watches wmem for writes into code that already ran, or that the instruction cache decoded
+pub struct Smc {
+    /// per word, the start of the last executed instruction covering it plus one, zero when it never ran
+    code: Vec<u32>,
+    pub modifications: Vec<Modification>,
+    /// print every modification as it happens
+    pub log: bool,
+    /// stop for the debugger on every modification
+    pub stop: bool,
+}
+
+impl Smc {
+    pub fn new() -> Smc {
+        Smc {
+            code: vec![0; WORDS],
+            modifications: Vec::new(),
+            log: true,
+            stop: false,
+        }
+    }
+
+    /// mark the words of the instruction at ip as code, called right before it executes
+    pub fn execute(&mut self, state: &State) {
+        let start = state.ip;
+        for n in 0..length(&state.program, start) {
+            if let Some(owner) = self.code.get_mut(start / 2 + n) {
+                *owner = start as u32 + 1;
+            }
+        }
+    }
+
+    /// check a wmem of value to address before it happens, true when it rewrites code
+    pub fn write(&mut self, state: &State, writer: usize, address: usize, value: u16) -> bool {
+        let (instruction, executed) = match self.code.get(address / 2) {
+            Some(0) | None => match state.cache.covering(address) {
+                Some(start) => (start, false),
+                None => return false,
+            },
+            Some(owner) => (*owner as usize - 1, true),
+        };
+        let byte = |i: usize| state.program.get(i).copied().unwrap_or(0) as u16;
+        if byte(address + 1) << 8 | byte(address) == value {
+            return false; // same word again, nothing changes
+        }
+        let old = disassemble(&state.program, instruction).0;
+        // patch a copy of the instruction, the real write happens after us
+        let mut copy: Vec<u8> = (instruction..instruction + 8).map(|i| state.program.get(i).copied().unwrap_or(0)).collect();
+        let offset = address - instruction;
+        if offset + 1 < copy.len() {
+            copy[offset] = value as u8;
+            copy[offset + 1] = (value >> 8) as u8;
+        }
+        let new = disassemble(&copy, 0).0;
+        if self.log {
+            println!();
+            let kind = if executed { "executed" } else { "decoded" };
+            println!(
+                "SMC: {:#06X} wrote {} to {:#06X}: {} {:#06X} `{}` is now `{}`",
+                writer, value, address, kind, instruction, old, new
+            );
+        }
+        self.modifications.push(Modification {
+            writer,
+            address,
+            instruction,
+            executed,
+            old,
+            new,
+        });
+        true
+    }
+
+    /// modified words grouped into contiguous regions with the writers that touched them
+    pub fn report(&self, symbols: &Symbols) -> String {
+        let mut text = String::new();
+        if self.modifications.is_empty() {
+            let _ = writeln!(text, "no writes into executed or decoded code");
+            return text;
+        }
+        let mut words: BTreeMap<usize, (usize, BTreeSet<usize>)> = BTreeMap::new();
+        for modification in &self.modifications {
+            let entry = words.entry(modification.address).or_insert((0, BTreeSet::new()));
+            entry.0 += 1;
+            entry.1.insert(modification.writer);
+        }
+
+        let mut regions: Vec<(usize, usize, usize, BTreeSet<usize>)> = Vec::new();
+        for (address, (count, writers)) in words {
+            match regions.last_mut() {
+                Some(region) if region.1 + 2 == address => {
+                    region.1 = address;
+                    region.2 += count;
+                    region.3.extend(writers);
+                }
+                _ => regions.push((address, address, count, writers)),
+            }
+        }
+
+        let _ = writeln!(text, "{} writes into executed or decoded code, {} region(s)", self.modifications.len(), regions.len());
+        for (start, end, count, writers) in regions {
+            let writers: Vec<String> = writers.iter().map(|writer| symbols.label(*writer)).collect();
+            let _ = writeln!(
+                text,
+                "  {}..={:#06x} ({} words, {} writes) by {}",
+                symbols.label(start),
+                end,
+                (end - start) / 2 + 1,
+                count,
+                writers.join(", ")
+            );
+        }
+        text
+    }
+}
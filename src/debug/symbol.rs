@@ -1,2 +1,76 @@
-MacroRulesDefinition :
-macro_rules ! add
+use crate::vm::BoxResult;
+use std::collections::BTreeMap;
+use std::fs;
+
+/// names for program addresses, used when printing backtraces
+pub struct Symbols {
+    names: BTreeMap<usize, String>,
+}
+
+impl Symbols {
+    pub fn new() -> Symbols {
+        Symbols {
+            names: BTreeMap::new(),
+        }
+    }
+
+    /// load a symbol file, one `<address> <name>` pair per line, `#` starts a comment
+    pub fn load(&mut self, path: &str) -> BoxResult<()> {
+        let file = fs::read_to_string(path)?;
+        for line in file.lines() {
+            let line = line.split('#').next().unwrap_or("");
+            let mut words = line.split_whitespace();
+            if let (Some(address), Some(name)) = (words.next(), words.next()) {
+                self.insert(parse_address(address)?, name.to_owned());
+            }
+        }
+        Ok(())
+    }
+
+    pub fn insert(&mut self, address: usize, name: String) {
+        self.names.insert(address, name);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&usize, &String)> {
+        self.names.iter()
+    }
+
+    /// exact name of an address, if there is one
+    pub fn get(&self, address: usize) -> Option<&str> {
+        self.names.get(&address).map(|name| name.as_str())
+    }
+
+    /// turn a symbol name or a literal address into an address
+    pub fn resolve(&self, word: &str) -> BoxResult<usize> {
+        match self.names.iter().find(|(_, name)| name.as_str() == word) {
+            Some((address, _)) => Ok(*address),
+            None => parse_address(word),
+        }
+    }
+
+    /// render an address as `name+offset` using the closest symbol at or below it
+    pub fn describe(&self, address: usize) -> String {
+        match self.names.range(..=address).next_back() {
+            Some((start, name)) if *start == address => name.clone(),
+            Some((start, name)) => format!("{}+{:#X}", name, address - start),
+            None => format!("{:#06X}", address),
+        }
+    }
+
+    /// an address, followed by its `name+offset` when there is a symbol at or below it
+    pub fn label(&self, address: usize) -> String {
+        match self.names.range(..=address).next_back() {
+            Some(_) => format!("{:#06x} ({})", address, self.describe(address)),
+            None => format!("{:#06x}", address),
+        }
+    }
+}
+
+/// parse an address given either in decimal or as 0x prefixed hex
+pub fn parse_address(word: &str) -> BoxResult<usize> {
+    if let Some(hex) = word.strip_prefix("0x").or_else(|| word.strip_prefix("0X")) {
+        Ok(usize::from_str_radix(hex, 16)?)
+    } else {
+        Ok(word.parse::<usize>()?)
+    }
+}
@@ -0,0 +1,430 @@
+use crate::debug::backtrace;
+use crate::debug::Meta;
+use crate::debug::Reason;
+use crate::machine::Machine;
+use crate::opcode::step;
+use crate::signal;
+use crate::vm::BoxResult;
+use crate::vm::State;
+
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+/// r0 to r7 followed by pc
+const REGISTERS: usize = 9;
+
+/// 32768 words of 16 bits, addressed by byte like the ip is
+const MEMORY: usize = 0x10000;
+
+/// instructions executed between checks for an interrupt from gdb
+const POLL_INTERVAL: usize = 1 << 14;
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.synacor.core">
+    <reg name="r0" bitsize="16" type="uint16" regnum="0"/>
+    <reg name="r1" bitsize="16" type="uint16"/>
+    <reg name="r2" bitsize="16" type="uint16"/>
+    <reg name="r3" bitsize="16" type="uint16"/>
+    <reg name="r4" bitsize="16" type="uint16"/>
+    <reg name="r5" bitsize="16" type="uint16"/>
+    <reg name="r6" bitsize="16" type="uint16"/>
+    <reg name="r7" bitsize="16" type="uint16"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+  </feature>
+</target>
+"#;
+
+const MEMORY_MAP: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE memory-map PUBLIC "+//IDN gnu.org//DTD GDB Memory Map V1.0//EN" "http://sourceware.org/gdb/gdb-memory-map.dtd">
+<memory-map>
+  <!-- 15-bit word addresses, every word stored as a little endian byte pair -->
+  <memory type="ram" start="0x0" length="0x10000"/>
+</memory-map>
+"#;
+
+enum Packet {
+    Data(String),
+    Interrupt,
+}
+
+enum Action {
+    Reply(String),
+    Resume(bool),
+    Kill,
+    Detach,
+}
+
+struct Client {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    ack: bool,
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+}
+
+impl Client {
+    fn new(stream: TcpStream) -> BoxResult<Client> {
+        stream.set_nodelay(true)?;
+        Ok(Client {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+            ack: true,
+        })
+    }
+
+    /// next packet from gdb, None once it hung up
+    fn receive(&mut self) -> BoxResult<Option<Packet>> {
+        loop {
+            let mut byte = [0u8; 1];
+            loop {
+                if self.reader.read(&mut byte)? == 0 {
+                    return Ok(None);
+                }
+                match byte[0] {
+                    b'$' => break,
+                    0x03 => return Ok(Some(Packet::Interrupt)),
+                    _ => {} // acks and line noise
+                }
+            }
+
+            let mut raw = Vec::new();
+            self.reader.read_until(b'#', &mut raw)?;
+            if raw.pop() != Some(b'#') {
+                return Ok(None);
+            }
+            let mut sum = [0u8; 2];
+            self.reader.read_exact(&mut sum)?;
+            let valid = std::str::from_utf8(&sum)
+                .ok()
+                .and_then(|sum| u8::from_str_radix(sum, 16).ok())
+                == Some(checksum(&raw));
+
+            if self.ack {
+                self.writer.write_all(if valid { b"+" } else { b"-" })?;
+                if !valid {
+                    continue;
+                }
+            }
+
+            let mut data = Vec::with_capacity(raw.len());
+            let mut bytes = raw.into_iter();
+            while let Some(byte) = bytes.next() {
+                if byte == b'}' {
+                    data.push(bytes.next().unwrap_or(0) ^ 0x20);
+                } else {
+                    data.push(byte);
+                }
+            }
+            return Ok(Some(Packet::Data(String::from_utf8_lossy(&data).into_owned())));
+        }
+    }
+
+    fn send(&mut self, data: &str) -> BoxResult<()> {
+        let mut raw = Vec::with_capacity(data.len() + 4);
+        for byte in data.bytes() {
+            if let b'$' | b'#' | b'}' | b'*' = byte {
+                raw.push(b'}');
+                raw.push(byte ^ 0x20);
+            } else {
+                raw.push(byte);
+            }
+        }
+        let sum = checksum(&raw);
+        self.writer.write_all(b"$")?;
+        self.writer.write_all(&raw)?;
+        write!(self.writer, "#{:02x}", sum)?;
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    /// check without blocking whether gdb sent a break while the guest runs
+    fn interrupted(&mut self) -> BoxResult<bool> {
+        if self.reader.buffer().is_empty() {
+            self.reader.get_ref().set_nonblocking(true)?;
+            let filled = self.reader.fill_buf().map(|buffer| !buffer.is_empty());
+            self.reader.get_ref().set_nonblocking(false)?;
+            match filled {
+                Ok(true) => {}
+                Ok(false) => return Ok(false),
+                Err(error) if error.kind() == ErrorKind::WouldBlock => return Ok(false),
+                Err(error) => return Err(Box::new(error)),
+            }
+        }
+        if self.reader.buffer()[0] == 0x03 {
+            self.reader.consume(1);
+            return Ok(true);
+        }
+        Ok(false)
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// bytes from pairs of hex digits, a trailing odd digit is ignored
+fn unhex(text: &str) -> BoxResult<Vec<u8>> {
+    let digit = |byte: u8| (byte as char).to_digit(16).ok_or_else(|| format!("{:?} is not a hex digit", byte as char));
+    text.as_bytes()
+        .chunks_exact(2)
+        .map(|pair| -> BoxResult<u8> { Ok((digit(pair[0])? << 4 | digit(pair[1])?) as u8) })
+        .collect()
+}
+
+fn number(text: &str) -> BoxResult<usize> {
+    Ok(usize::from_str_radix(text, 16)?)
+}
+
+fn read_register(state: &State, n: usize) -> Option<u16> {
+    match n {
+        0..=7 => Some(state.register[n]),
+        8 => Some(state.ip as u16),
+        _ => None,
+    }
+}
+
+fn write_register(state: &mut State, n: usize, value: u16) -> bool {
+    match n {
+        0..=7 => state.register[n] = value,
+        8 => state.ip = value as usize,
+        _ => return false,
+    }
+    true
+}
+
+/// reply to a qXfer read with the requested window of a document
+fn transfer(document: &str, window: &str) -> BoxResult<String> {
+    let mut parts = window.split(',');
+    let offset = number(parts.next().unwrap_or("0"))?;
+    let length = number(parts.next().unwrap_or("0"))?;
+    let bytes = document.as_bytes();
+    if offset >= bytes.len() {
+        return Ok(String::from("l"));
+    }
+    let end = (offset + length).min(bytes.len());
+    let marker = if end == bytes.len() { 'l' } else { 'm' };
+    Ok(format!("{}{}", marker, String::from_utf8_lossy(&bytes[offset..end])))
+}
+
+/// text for `monitor <command>`
+fn monitor(state: &State, meta: &Meta, command: &str) -> String {
+    let mut out = String::new();
+    match command.trim() {
+        "stack" => {
+            for (i, value) in state.stack.iter().enumerate() {
+                out.push_str(&format!("<{}> = {}\n", i, value));
+            }
+        }
+        "bt" | "backtrace" => {
+            for (n, trace) in backtrace(state).iter().enumerate() {
+                let function = match trace.function {
+                    Some(address) => meta.symbols.describe(address),
+                    None => String::from("<entry>"),
+                };
+                out.push_str(&format!("#{} {} at {:#06X}\n", n, function, trace.pc));
+            }
+        }
+        _ => out.push_str("monitor commands: stack, backtrace\n"),
+    }
+    out
+}
+
+fn handle(state: &mut State, meta: &mut Meta, packet: &str) -> BoxResult<Action> {
+    let reply = |text: &str| Ok(Action::Reply(String::from(text)));
+
+    if packet.starts_with("qSupported") {
+        return reply("PacketSize=4000;qXfer:features:read+;qXfer:memory-map:read+;QStartNoAckMode+;swbreak+;vContSupported+");
+    }
+    if let Some(window) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+        return Ok(Action::Reply(transfer(TARGET_XML, window)?));
+    }
+    if let Some(window) = packet.strip_prefix("qXfer:memory-map:read::") {
+        return Ok(Action::Reply(transfer(MEMORY_MAP, window)?));
+    }
+    if let Some(command) = packet.strip_prefix("qRcmd,") {
+        let command = String::from_utf8_lossy(&unhex(command)?).into_owned();
+        return Ok(Action::Reply(hex(monitor(state, meta, &command).as_bytes())));
+    }
+    if packet == "QStartNoAckMode" {
+        return reply("OK");
+    }
+    if packet == "vCont?" {
+        return reply("vCont;c;C;s;S");
+    }
+    if let Some(actions) = packet.strip_prefix("vCont;") {
+        return Ok(Action::Resume(actions.starts_with('s') || actions.starts_with('S')));
+    }
+
+    let (kind, rest) = packet.split_at(packet.chars().next().map(|c| c.len_utf8()).unwrap_or(0));
+    match kind {
+        "?" => reply("S05"),
+        "g" => {
+            let mut bytes = Vec::with_capacity(REGISTERS * 2);
+            for n in 0..REGISTERS {
+                let value = read_register(state, n).unwrap_or(0);
+                bytes.extend_from_slice(&value.to_le_bytes());
+            }
+            Ok(Action::Reply(hex(&bytes)))
+        }
+        "G" => {
+            let bytes = unhex(rest)?;
+            for (n, pair) in bytes.chunks(2).take(REGISTERS).enumerate() {
+                if let [lower, higher] = pair {
+                    write_register(state, n, u16::from_le_bytes([*lower, *higher]));
+                }
+            }
+            reply("OK")
+        }
+        "p" => match read_register(state, number(rest)?) {
+            Some(value) => Ok(Action::Reply(hex(&value.to_le_bytes()))),
+            None => reply("E00"),
+        },
+        "P" => {
+            let mut parts = rest.split('=');
+            let n = number(parts.next().unwrap_or(""))?;
+            let bytes = unhex(parts.next().unwrap_or(""))?;
+            let value = u16::from_le_bytes([bytes.first().copied().unwrap_or(0), bytes.get(1).copied().unwrap_or(0)]);
+            if write_register(state, n, value) {
+                reply("OK")
+            } else {
+                reply("E00")
+            }
+        }
+        "m" => {
+            let mut parts = rest.split(',');
+            let address = number(parts.next().unwrap_or(""))?;
+            let length = number(parts.next().unwrap_or(""))?;
+            let end = match address.checked_add(length) {
+                Some(end) if address < MEMORY => end.min(MEMORY),
+                _ => return reply("E01"),
+            };
+            // memory past the loaded image reads as zero
+            let bytes: Vec<u8> = (address..end).map(|i| state.program.get(i).copied().unwrap_or(0)).collect();
+            Ok(Action::Reply(hex(&bytes)))
+        }
+        "M" => {
+            let mut parts = rest.split([',', ':']);
+            let address = number(parts.next().unwrap_or(""))?;
+            let length = number(parts.next().unwrap_or(""))?;
+            let bytes = unhex(parts.next().unwrap_or(""))?;
+            if !matches!(address.checked_add(length), Some(end) if end <= MEMORY && bytes.len() >= length) {
+                return reply("E01");
+            }
+            state.write_memory(&mut meta.hooks, address, &bytes[..length]);
+            reply("OK")
+        }
+        "c" | "s" => {
+            if !rest.is_empty() {
+                state.ip = number(rest)?;
+            }
+            Ok(Action::Resume(kind == "s"))
+        }
+        "Z" | "z" => {
+            let mut parts = rest.split(',');
+            let kind_of = parts.next().unwrap_or("");
+            let address = number(parts.next().unwrap_or(""))?;
+            if kind_of != "0" && kind_of != "1" {
+                return reply(""); // no watchpoints
+            }
+            if kind == "Z" {
+                if !meta.breakpoints.contains(&address) {
+                    meta.breakpoints.push(address);
+                }
+            } else {
+                meta.breakpoints.retain(|breakpoint| *breakpoint != address);
+            }
+            reply("OK")
+        }
+        "H" => reply("OK"),
+        "k" => Ok(Action::Kill),
+        "D" => Ok(Action::Detach),
+        _ => match packet {
+            "qAttached" => reply("1"),
+            "qC" => reply("QC1"),
+            "qfThreadInfo" => reply("m1"),
+            "qsThreadInfo" => reply("l"),
+            "qSymbol::" => reply("OK"),
+            _ => reply(""),
+        },
+    }
+}
+
+/// run until a breakpoint, the end of a single step, a halt or an interrupt, returns the stop reply
+fn resume(state: &mut State, meta: &mut Meta, client: &mut Client, single: bool) -> BoxResult<String> {
+    let mut count = 0usize;
+    let reply = loop {
+        step(state, meta);
+        if meta.halt {
+            break "W00";
+        }
+        if meta.debugging {
+            // the guest stopped itself, SIGILL and SIGFPE for the faults, a trap for the rest
+            meta.debugging = false;
+            break match meta.reason.take() {
+                Some(Reason::UnknownOpcode) => "S04",
+                Some(Reason::DivideByZero) => "S08",
+                _ => "S05",
+            };
+        }
+        if single {
+            break "S05";
+        }
+        if meta.breakpoints.contains(&state.ip) {
+            break "T05swbreak:;";
+        }
+        count += 1;
+        if signal::interrupted() || (count.is_multiple_of(POLL_INTERVAL) && client.interrupted()?) {
+            break "S02";
+        }
+    };
+    meta.io.flush();
+    Ok(String::from(reply))
+}
+
+/// wait for gdb on the port and let it drive the VM until it detaches, kills or hangs up
+pub fn serve(state: &mut State, meta: &mut Meta, port: u16) -> BoxResult<()> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    println!("gdb: waiting for a connection on {}", listener.local_addr()?);
+    let (stream, address) = listener.accept()?;
+    println!("gdb: {} connected", address);
+    let mut client = Client::new(stream)?;
+
+    while let Some(packet) = client.receive()? {
+        let packet = match packet {
+            Packet::Data(packet) => packet,
+            Packet::Interrupt => {
+                client.send("S02")?;
+                continue;
+            }
+        };
+        // a packet that does not parse gets an error reply, gdb carries on
+        let action = handle(state, meta, &packet).unwrap_or_else(|_| Action::Reply(String::from("E01")));
+        match action {
+            Action::Reply(reply) => {
+                client.send(&reply)?;
+                if packet == "QStartNoAckMode" {
+                    client.ack = false;
+                }
+            }
+            Action::Resume(single) => {
+                let reply = resume(state, meta, &mut client, single)?;
+                client.send(&reply)?;
+            }
+            Action::Kill => {
+                meta.halt = true;
+                return Ok(());
+            }
+            Action::Detach => {
+                client.send("OK")?;
+                println!("gdb: detached");
+                return Ok(());
+            }
+        }
+    }
+    println!("gdb: connection closed");
+    Ok(())
+}
@@ -0,0 +1,493 @@
+//! hot basic blocks compiled to native code with cranelift, everything else stays with the interpreter
+//!
+//! a block is straight-line arithmetic, stack and `rmem` instructions ending in a jump, call or ret.
+//! `out`, `in`, `wmem`, `halt` and data end a block before them so they always run interpreted, and a
+//! block whose memory changed after it was compiled is thrown away and its address is never compiled again.
+
+use crate::cache::Instruction;
+use crate::debug::Meta;
+use crate::debug::Resume;
+use crate::opcode;
+use crate::opcode::Code;
+use crate::opcode::Flow;
+use crate::opcode::Operand;
+use crate::vm::BoxResult;
+use crate::vm::Frame;
+use crate::vm::State;
+
+use cranelift_codegen::ir::condcodes::IntCC;
+use cranelift_codegen::ir::types::{I16, I32, I64};
+use cranelift_codegen::ir::{AbiParam, FuncRef, InstBuilder, MemFlags, Signature, Value};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{default_libcall_names, FuncId, Linkage, Module};
+
+/// 32768 words
+const WORDS: usize = 0x8000;
+
+/// times the interpreter has to arrive at an address before it gets compiled, unless `Jit::hot` says otherwise
+const HOT: u32 = 64;
+
+/// instructions in a block at most
+const LONGEST: usize = 64;
+
+/// instructions run in one go before handing back to the main loop
+const BUDGET: usize = 1 << 12;
+
+/// returned by helpers when the interpreter has to run the instruction instead
+const BAIL: u32 = u32::MAX;
+
+/// registers, then the whole state for the helpers; returns the next ip with the instruction count above it
+type Compiled = unsafe extern "C" fn(*mut u16, *mut State) -> u64;
+
+struct Block {
+    function: Compiled,
+    /// the memory the block was compiled from, to notice it being rewritten
+    code: Vec<u8>,
+    /// the last instruction, what the interpreter would have reported running
+    last: Code,
+}
+
+enum Slot {
+    /// interpreted this many times so far
+    Cold(u32),
+    Compiled(Block),
+    /// nothing compilable starts here, or the code under it was modified
+    Never,
+}
+
+pub struct Jit {
+    module: JITModule,
+    slots: Vec<Slot>,
+    helpers: Helpers<FuncId>,
+    /// blocks compiled so far
+    pub compiled: usize,
+    /// blocks thrown away because the guest rewrote them
+    pub discarded: usize,
+    /// arrivals at an address before it gets compiled, the conformance tests drop it to 1 to compile everything
+    pub hot: u32,
+}
+
+struct Helpers<T> {
+    push: T,
+    pop: T,
+    call: T,
+    ret: T,
+    rmem: T,
+}
+
+unsafe extern "C" fn push(state: *mut State, value: u32) {
+    (*state).stack.push(value as u16);
+}
+
+unsafe extern "C" fn pop(state: *mut State, register: u32) {
+    let state = &mut *state;
+    if let Some(data) = state.stack.pop() {
+        state.register[register as usize] = data;
+        state.unwind();
+    }
+}
+
+unsafe extern "C" fn call(state: *mut State, caller: u32, target: u32, next: u32) {
+    let state = &mut *state;
+    state.stack.push(next as u16 / 2);
+    state.frames.push(Frame {
+        caller: caller as usize,
+        callee: target as usize * 2,
+        depth: state.stack.len(),
+    });
+}
+
+/// the address to return to, or BAIL on an empty stack so the interpreter halts the way it does
+unsafe extern "C" fn ret(state: *mut State) -> u32 {
+    let state = &mut *state;
+    match state.stack.pop() {
+        Some(n) => {
+            state.unwind();
+            n as u32 * 2
+        }
+        None => BAIL,
+    }
+}
+
+/// the word at a word address, or BAIL past the end of memory
+unsafe extern "C" fn rmem(state: *mut State, address: u32) -> u32 {
+    let state = &*state;
+    let b = opcode::address(address as u16);
+    match state.program.get(b..b + 2) {
+        Some(word) => ((word[1] as u16) << 8 | word[0] as u16) as u32,
+        None => BAIL,
+    }
+}
+
+/// instructions a block can hold, the ones `Emitter::instruction` has code for; no wildcard so a new opcode has to be
+/// sorted into one side or the other
+fn compilable(code: Code) -> bool {
+    match code {
+        Code::Set
+        | Code::Push
+        | Code::Pop
+        | Code::Equals
+        | Code::GreaterThan
+        | Code::Jump
+        | Code::JumpIfTrue
+        | Code::JumpIfFalse
+        | Code::Add
+        | Code::Multiply
+        | Code::Modulo
+        | Code::And
+        | Code::Or
+        | Code::Not
+        | Code::ReadMemory
+        | Code::Call
+        | Code::Return
+        | Code::Noop => true,
+        Code::Halt | Code::WriteMemory | Code::Out | Code::In | Code::Data => false,
+    }
+}
+
+/// whatever can leave ip anywhere but the next instruction
+fn ends_block(code: Code) -> bool {
+    code.flow() != Flow::Next
+}
+
+/// the register an operand writes to, None for anything the interpreter would choke on
+fn destination(raw: u16) -> Option<i32> {
+    match raw {
+        0..=7 => Some(raw as i32),
+        32768..=32775 => Some(raw as i32 - 32768),
+        _ => None,
+    }
+}
+
+/// the instructions of the block starting at ip
+fn scan(program: &[u8], ip: usize) -> Vec<(usize, Instruction)> {
+    let mut instructions = Vec::new();
+    let mut at = ip;
+    while instructions.len() < LONGEST {
+        let instruction = Instruction::decode(program, at);
+        let code = instruction.code();
+        let writes = code.operands().first() == Some(&Operand::Register);
+        if at + instruction.len as usize * 2 > program.len()
+            || !compilable(code)
+            || (writes && destination(instruction.args[0]).is_none())
+        {
+            break;
+        }
+        instructions.push((at, instruction));
+        at += instruction.len as usize * 2;
+        if ends_block(code) {
+            break;
+        }
+    }
+    instructions
+}
+
+impl Jit {
+    pub fn new() -> BoxResult<Jit> {
+        let mut builder = JITBuilder::new(default_libcall_names())?;
+        builder.symbol("synacor_push", push as *const u8);
+        builder.symbol("synacor_pop", pop as *const u8);
+        builder.symbol("synacor_call", call as *const u8);
+        builder.symbol("synacor_ret", ret as *const u8);
+        builder.symbol("synacor_rmem", rmem as *const u8);
+        let mut module = JITModule::new(builder);
+
+        let pointer = module.target_config().pointer_type();
+        let mut declare = |name: &str, params: usize, returns: bool| -> BoxResult<FuncId> {
+            let mut signature = module.make_signature();
+            signature.params.push(AbiParam::new(pointer));
+            signature.params.extend((0..params).map(|_| AbiParam::new(I32)));
+            if returns {
+                signature.returns.push(AbiParam::new(I32));
+            }
+            Ok(module.declare_function(name, Linkage::Import, &signature)?)
+        };
+        let helpers = Helpers {
+            push: declare("synacor_push", 1, false)?,
+            pop: declare("synacor_pop", 1, false)?,
+            call: declare("synacor_call", 3, false)?,
+            ret: declare("synacor_ret", 0, true)?,
+            rmem: declare("synacor_rmem", 1, true)?,
+        };
+
+        let mut slots = Vec::with_capacity(WORDS);
+        slots.resize_with(WORDS, || Slot::Cold(0));
+        Ok(Jit {
+            module,
+            slots,
+            helpers,
+            compiled: 0,
+            discarded: 0,
+            hot: HOT,
+        })
+    }
+
+    /// whether blocks can run without skipping anything the debugger or the tools watch per instruction
+    pub fn usable(meta: &Meta) -> bool {
+        meta.observer.is_none()
+            && meta.resume == Resume::Continue
+            && meta.breakpoints.is_empty()
+            && meta.profile.is_none()
+            && meta.hooks.coverage.is_none()
+            && meta.hooks.smc.is_none()
+            && !compilable(meta.hooks.break_op)
+    }
+
+    /// run compiled blocks from ip for as long as they chain into each other, up to a budget so the
+    /// caller still gets to check for interrupts; returns the last instruction run, None to interpret a step
+    pub fn run(&mut self, state: &mut State, meta: &mut Meta) -> Option<Code> {
+        let mut ran = 0;
+        let mut last = None;
+        while ran < BUDGET && state.ip.is_multiple_of(2) {
+            let index = state.ip / 2;
+            let block = match self.slots.get_mut(index) {
+                Some(Slot::Compiled(block)) => block,
+                Some(Slot::Cold(count)) => {
+                    *count += 1;
+                    if *count < self.hot {
+                        break;
+                    }
+                    self.slots[index] = match self.compile(state) {
+                        Ok(Some(block)) => {
+                            self.compiled += 1;
+                            Slot::Compiled(block)
+                        }
+                        _ => Slot::Never,
+                    };
+                    continue; // run it straight away
+                }
+                _ => break,
+            };
+            if state.program.get(state.ip..state.ip + block.code.len()) != Some(&block.code[..]) {
+                // self-modified, leave this address to the interpreter from now on
+                self.discarded += 1;
+                self.slots[index] = Slot::Never;
+                break;
+            }
+            let pointer: *mut State = state;
+            let result = unsafe {
+                let registers = std::ptr::addr_of_mut!((*pointer).register) as *mut u16;
+                (block.function)(registers, pointer)
+            };
+            state.ip = result as u32 as usize;
+            let count = (result >> 32) as usize;
+            if count == 0 {
+                break; // bailed on its first instruction
+            }
+            ran += count;
+            last = Some(block.last);
+        }
+        meta.op_count += ran;
+        last
+    }
+
+    fn compile(&mut self, state: &State) -> BoxResult<Option<Block>> {
+        let instructions = scan(&state.program, state.ip);
+        let (end, last) = match instructions.last() {
+            Some((at, instruction)) => (at + instruction.len as usize * 2, instruction.code()),
+            None => return Ok(None),
+        };
+
+        let pointer = self.module.target_config().pointer_type();
+        let mut context = self.module.make_context();
+        context.func.signature = Signature::new(self.module.isa().default_call_conv());
+        context.func.signature.params.push(AbiParam::new(pointer));
+        context.func.signature.params.push(AbiParam::new(pointer));
+        context.func.signature.returns.push(AbiParam::new(I64));
+
+        let mut functions = FunctionBuilderContext::new();
+        let mut builder = FunctionBuilder::new(&mut context.func, &mut functions);
+        let helpers = Helpers {
+            push: self.module.declare_func_in_func(self.helpers.push, builder.func),
+            pop: self.module.declare_func_in_func(self.helpers.pop, builder.func),
+            call: self.module.declare_func_in_func(self.helpers.call, builder.func),
+            ret: self.module.declare_func_in_func(self.helpers.ret, builder.func),
+            rmem: self.module.declare_func_in_func(self.helpers.rmem, builder.func),
+        };
+        let entry = builder.create_block();
+        builder.append_block_params_for_function_params(entry);
+        builder.switch_to_block(entry);
+        builder.seal_block(entry);
+        let registers = builder.block_params(entry)[0];
+        let state_pointer = builder.block_params(entry)[1];
+
+        let mut emit = Emitter {
+            builder,
+            registers,
+            state: state_pointer,
+            helpers,
+        };
+        for (n, (at, instruction)) in instructions.iter().enumerate() {
+            emit.instruction(*at, instruction, n as u64);
+        }
+        if !ends_block(last) {
+            // cut short by something the interpreter has to run, carry on there
+            emit.exit_to(end as u64, instructions.len() as u64);
+        }
+        emit.builder.finalize();
+
+        let id = self.module.declare_anonymous_function(&context.func.signature)?;
+        self.module.define_function(id, &mut context)?;
+        self.module.clear_context(&mut context);
+        self.module.finalize_definitions()?;
+        let code = self.module.get_finalized_function(id);
+        Ok(Some(Block {
+            function: unsafe { std::mem::transmute::<*const u8, Compiled>(code) },
+            code: state.program[state.ip..end].to_vec(),
+            last,
+        }))
+    }
+}
+
+struct Emitter<'a> {
+    builder: FunctionBuilder<'a>,
+    registers: Value,
+    state: Value,
+    helpers: Helpers<FuncRef>,
+}
+
+impl Emitter<'_> {
+    /// an operand as a 32 bit value, registers loaded at the point of use
+    fn operand(&mut self, raw: u16) -> Value {
+        match raw {
+            32768..=32775 => {
+                let offset = (raw as i32 - 32768) * 2;
+                let value = self.builder.ins().load(I16, MemFlags::trusted(), self.registers, offset);
+                self.builder.ins().uextend(I32, value)
+            }
+            _ => self.builder.ins().iconst(I32, (raw % 32768) as i64),
+        }
+    }
+
+    fn store(&mut self, raw: u16, value: Value) {
+        let register = destination(raw).unwrap_or(0);
+        let value = self.builder.ins().ireduce(I16, value);
+        self.builder.ins().store(MemFlags::trusted(), value, self.registers, register * 2);
+    }
+
+    /// return to the dispatcher with the next ip and how many instructions ran
+    fn exit(&mut self, ip: Value, count: u64) {
+        let ip = self.builder.ins().uextend(I64, ip);
+        let result = self.builder.ins().bor_imm(ip, (count << 32) as i64);
+        self.builder.ins().return_(&[result]);
+    }
+
+    fn exit_to(&mut self, ip: u64, count: u64) {
+        let ip = self.builder.ins().iconst(I32, ip as i64);
+        self.exit(ip, count);
+    }
+
+    /// leave for the interpreter at `at` when the condition holds, carry on in a fresh block otherwise
+    fn bail_if(&mut self, condition: Value, at: usize, count: u64) {
+        let bail = self.builder.create_block();
+        let next = self.builder.create_block();
+        self.builder.ins().brif(condition, bail, &[], next, &[]);
+        self.builder.switch_to_block(bail);
+        self.builder.seal_block(bail);
+        self.exit_to(at as u64, count);
+        self.builder.switch_to_block(next);
+        self.builder.seal_block(next);
+    }
+
+    /// emit one instruction, `count` instructions of the block ran before it
+    fn instruction(&mut self, at: usize, instruction: &Instruction, count: u64) {
+        let [x, y, z] = instruction.args;
+        let next = (at + instruction.len as usize * 2) as i64;
+        let done = count + 1;
+        match instruction.code() {
+            Code::Set => {
+                let b = self.operand(y);
+                self.store(x, b);
+            }
+            Code::Push => {
+                let a = self.operand(x);
+                self.builder.ins().call(self.helpers.push, &[self.state, a]);
+            }
+            Code::Pop => {
+                let register = self.builder.ins().iconst(I32, destination(x).unwrap_or(0) as i64);
+                self.builder.ins().call(self.helpers.pop, &[self.state, register]);
+            }
+            code @ (Code::Equals | Code::GreaterThan) => {
+                let b = self.operand(y);
+                let c = self.operand(z);
+                let condition = if code == Code::Equals { IntCC::Equal } else { IntCC::UnsignedGreaterThan };
+                let flag = self.builder.ins().icmp(condition, b, c);
+                let flag = self.builder.ins().uextend(I32, flag);
+                self.store(x, flag);
+            }
+            Code::Jump => {
+                let a = self.operand(x);
+                let ip = self.builder.ins().imul_imm(a, 2);
+                self.exit(ip, done);
+            }
+            code @ (Code::JumpIfTrue | Code::JumpIfFalse) => {
+                let a = self.operand(x);
+                let b = self.operand(y);
+                let target = self.builder.ins().imul_imm(b, 2);
+                let fall = self.builder.ins().iconst(I32, next);
+                let ip = if code == Code::JumpIfTrue {
+                    self.builder.ins().select(a, target, fall)
+                } else {
+                    self.builder.ins().select(a, fall, target)
+                };
+                self.exit(ip, done);
+            }
+            code @ (Code::Add | Code::Multiply | Code::And | Code::Or) => {
+                let b = self.operand(y);
+                let c = self.operand(z);
+                let result = match code {
+                    Code::Add => self.builder.ins().iadd(b, c),
+                    Code::Multiply => self.builder.ins().imul(b, c),
+                    Code::And => self.builder.ins().band(b, c),
+                    _ => self.builder.ins().bor(b, c),
+                };
+                let result = self.builder.ins().band_imm(result, 0x7FFF);
+                self.store(x, result);
+            }
+            Code::Modulo => {
+                let b = self.operand(y);
+                let c = self.operand(z);
+                // the interpreter stops for the debugger on a zero divisor, let it
+                let zero = self.builder.ins().icmp_imm(IntCC::Equal, c, 0);
+                self.bail_if(zero, at, count);
+                let result = self.builder.ins().urem(b, c);
+                let result = self.builder.ins().band_imm(result, 0x7FFF);
+                self.store(x, result);
+            }
+            Code::Not => {
+                let b = self.operand(y);
+                let result = self.builder.ins().bnot(b);
+                let result = self.builder.ins().band_imm(result, 0x7FFF);
+                self.store(x, result);
+            }
+            Code::ReadMemory => {
+                let b = self.operand(y);
+                let call = self.builder.ins().call(self.helpers.rmem, &[self.state, b]);
+                let word = self.builder.inst_results(call)[0];
+                let outside = self.builder.ins().icmp_imm(IntCC::Equal, word, BAIL as i64);
+                self.bail_if(outside, at, count);
+                self.store(x, word);
+            }
+            Code::Call => {
+                let a = self.operand(x);
+                let caller = self.builder.ins().iconst(I32, at as i64);
+                let after = self.builder.ins().iconst(I32, next);
+                self.builder.ins().call(self.helpers.call, &[self.state, caller, a, after]);
+                let ip = self.builder.ins().imul_imm(a, 2);
+                self.exit(ip, done);
+            }
+            Code::Return => {
+                let call = self.builder.ins().call(self.helpers.ret, &[self.state]);
+                let ip = self.builder.inst_results(call)[0];
+                let empty = self.builder.ins().icmp_imm(IntCC::Equal, ip, BAIL as i64);
+                self.bail_if(empty, at, count);
+                self.exit(ip, done);
+            }
+            Code::Noop => {}
+            Code::Halt | Code::WriteMemory | Code::Out | Code::In | Code::Data => {
+                unreachable!("scan leaves {} to the interpreter", instruction.code().mnemonic())
+            }
+        }
+    }
+}
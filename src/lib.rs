@@ -0,0 +1,23 @@
+//! a virtual machine and debugger for the synacor challenge, the `synacor` binary is the front end
+
+// these lints only started firing once the modules became a library, the types were never meant to be defaulted
+#![allow(clippy::new_without_default, clippy::len_without_is_empty)]
+
+pub mod aot;
+pub mod cache;
+pub mod console;
+pub mod dap;
+pub mod debug;
+pub mod error;
+pub mod gdb;
+#[cfg(feature = "jit")]
+pub mod jit;
+pub mod machine;
+pub mod observer;
+pub mod opcode;
+pub mod peephole;
+pub mod rpc;
+pub mod signal;
+pub mod tui;
+pub mod util;
+pub mod vm;
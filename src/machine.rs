@@ -0,0 +1,65 @@
+use crate::debug::Meta;
+use crate::vm::BoxResult;
+
+/// shadow call stack entry, pushed by `call` and dropped by `ret`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    /// address of the call instruction
+    pub caller: usize,
+    /// address that was called
+    pub callee: usize,
+    /// stack depth right after the return address was pushed
+    pub depth: usize,
+}
+
+/// what the debugger, the profiler, the trace recorder and snapshots need from a virtual machine, another
+/// architecture implements this to reuse them; addresses are byte offsets into memory, register and stack values are
+/// widened to u64
+pub trait Machine: Sized + 'static {
+    /// the machine's own per instruction tools, kept in `Meta::hooks` next to the debugger's state
+    type Hooks: Default;
+
+    fn ip(&self) -> usize;
+    fn set_ip(&mut self, ip: usize);
+    fn registers(&self) -> Vec<u64>;
+    /// false when there is no such register or the value does not fit it
+    fn set_register(&mut self, register: usize, value: u64) -> bool;
+    fn memory(&self) -> &[u8];
+    /// write bytes at address for a debugger, dropping anything decoded from them and telling the hooks the way a
+    /// write by the guest would
+    fn write_memory(&mut self, hooks: &mut Self::Hooks, address: usize, bytes: &[u8]);
+    /// the data stack, bottom first, empty for architectures without one
+    fn stack(&self) -> Vec<u64>;
+    /// false when there is no such slot or the value does not fit it
+    fn set_stack(&mut self, slot: usize, value: u64) -> bool;
+    /// the shadow call stack, outermost call first
+    fn frames(&self) -> &[Frame];
+    /// the instruction at address as assembly and its length in bytes
+    fn decode(&self, address: usize) -> (String, usize);
+    /// what the instruction at address does, for the verbose trace; empty when there is nothing to add
+    fn describe(&self, _address: usize) -> &'static str {
+        ""
+    }
+    /// the opcode of the instruction at address for the profiler, None when it decodes as data
+    fn opcode(&self, address: usize) -> Option<u8> {
+        self.memory().get(address).copied()
+    }
+    /// what the profiler calls the opcode a first byte stands for
+    fn mnemonic(&self, op: u8) -> &'static str;
+    /// whether the instruction at address calls something, for stepping over it
+    fn is_call(&self, address: usize) -> bool;
+    /// the address a return address pushed by a call sends execution back to
+    fn return_address(&self, value: u64) -> usize {
+        value as usize
+    }
+    /// stop for the debugger after every instruction with this opcode, what `bp` sets
+    fn break_on(hooks: &mut Self::Hooks, op: u8);
+    /// the opcode `bp` last set, as the debugger shows it
+    fn break_op(hooks: &Self::Hooks) -> String;
+    /// run the instruction at ip
+    fn step(&mut self, meta: &mut Meta<Self>);
+    /// everything needed to pick the machine up again later
+    fn snapshot(&self) -> Vec<u8>;
+    /// a machine from a snapshot, or from a plain program image
+    fn restore(snapshot: Vec<u8>) -> BoxResult<Self>;
+}
@@ -1,19 +1,30 @@
 // use std::io::Read;
-use crate::debug::debugger::debugger;
+use synacor::debug::debugger::debugger;
+use synacor::debug::debugger::script;
+use synacor::debug::debugger::source;
 use std::env;
 use std::error::Error;
 use std::fs;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
 
-use vm::State;
-use debug::Meta;
+use synacor::vm::State;
+use synacor::debug::Meta;
+use synacor::debug::Resume;
+use synacor::debug::Reason;
+use synacor::observer::{Recorder, Verbose};
+use synacor::debug::profile::Profile;
+use synacor::debug::coverage::Coverage;
+use synacor::debug::smc::Smc;
+use synacor::debug::symbol::Symbols;
+use synacor::rpc::{Rpc, POLL_INTERVAL};
+use synacor::{aot, dap, gdb, opcode, peephole, signal, tui};
+#[cfg(feature = "jit")]
+use synacor::jit::Jit;
 
-// use crate::util::*;
-use crate::error::*;
-mod vm;
-mod opcode;
-mod util;
-mod debug;
-mod error;
+// use synacor::util::*;
+use synacor::error::*;
 
 /***
  * DOING:
@@ -21,16 +32,16 @@ mod error;
  *       and register access.
  * DONE:
  *     - add stepping.
- * TODO:
  *     - pausing the VM from anywhere in the code
- *     - seperating the parsing from the execution
  *     - implementing an ABI or some other API to allow for seperate debugger
  *       processes.
- *     - taking snapshots at any state in the code.
- *     - snapshots which don't mutate program memory.
- *     - seperate the debug printing from the operation execution.
  *     - add GUI or TUI to allow for rendering the stack and registers while
  *       stepping trough the code.
+ *     - seperating the parsing from the execution
+ *     - seperate the debug printing from the operation execution.
+ * TODO:
+ *     - taking snapshots at any state in the code.
+ *     - snapshots which don't mutate program memory.
  *
  ***/
 
@@ -38,6 +49,20 @@ struct Config {
     quiet: bool,
     debug: bool,
     path: String,
+    symbols: Option<String>,
+    commands: Option<String>,
+    init: bool,
+    tstp: bool,
+    gdb: Option<u16>,
+    dap: Option<u16>,
+    tui: bool,
+    profile: Option<String>,
+    trace: Option<String>,
+    coverage: Option<String>,
+    smc: bool,
+    smc_break: bool,
+    rpc: Option<String>,
+    jit: bool,
 }
 
 type BoxResult<T> = Result<T, Box<dyn Error>>;
@@ -45,9 +70,32 @@ type BoxResult<T> = Result<T, Box<dyn Error>>;
 fn main() -> BoxResult<()> {
     let args: Vec<String> = env::args().collect();
 
+    if args.len() > 1 && args[1] == "coverage" {
+        return coverage(&args[2..]);
+    }
+    if args.len() > 1 && args[1] == "aot" {
+        return translate(&args[2..]);
+    }
+
     if args.len() == 1 {
         println!("USAGE: {} [OPTIONS] [FILE]", args[0]);
+        println!("       {} coverage [--symbols <file>] [--annotate <file>] [--merge <file>] FILE COVERAGE...", args[0]);
+        println!("       {} aot [--coverage <file>] [--output <file>] FILE", args[0]);
         println!("-d: start with debug mode on");
+        println!("--symbols <file>: load address names for backtraces");
+        println!("-x, --commands <file>: run debugger commands from a file on startup");
+        println!("--no-init: do not read ~/.synacorrc or ./.synacorrc");
+        println!("--tstp: Ctrl-Z pauses into the debugger like Ctrl-C instead of suspending");
+        println!("--gdb <port>: wait for a gdb remote protocol connection on localhost");
+        println!("--dap <port>: serve the debug adapter protocol on localhost, port 0 picks one, FILE is optional");
+        println!("--tui: full screen view with disassembly, registers, stack, memory and the guest console");
+        println!("--profile <file>: count instructions per address, opcode and function, write a report and <file>.folded stacks on exit");
+        println!("--trace <file>: write every instruction run and what it changed to the file, instead of -d's explanations");
+        println!("--coverage <file>: record executed, read and written words and add them to the coverage file on exit");
+        println!("--smc: report wmem writes into code that already ran, and list the modified regions at exit");
+        println!("--smc-break: like --smc, and stop for the debugger on every such write");
+        println!("--rpc <socket>: serve the JSON-RPC control API on a unix socket");
+        println!("--jit: compile hot code to native code, needs a build with the jit feature");
         return Ok(());
     }
 
@@ -55,21 +103,111 @@ fn main() -> BoxResult<()> {
         quiet: false,
         debug: false,
         path: String::new(),
+        symbols: None,
+        commands: None,
+        init: true,
+        tstp: false,
+        gdb: None,
+        dap: None,
+        tui: false,
+        profile: None,
+        trace: None,
+        coverage: None,
+        smc: false,
+        smc_break: false,
+        rpc: None,
+        jit: false,
     };
 
     if args.len() == 2 {
         config.path = args[1].clone();
     } else {
-        for i in 1..args.len() {
-            match args[i].as_ref() {
+        let mut argv = args.iter().skip(1);
+        while let Some(arg) = argv.next() {
+            match arg.as_ref() {
                 "-d" | "--debug" => {
                     config.debug = true;
                 }
                 "-q" | "--quiet" => {
                     config.quiet = true;
                 }
+                "--symbols" => {
+                    if let Some(path) = argv.next() {
+                        config.symbols = Some(path.clone());
+                    } else {
+                        return Err(InvalidArgError::new(String::from("--symbols needs a file")));
+                    }
+                }
+                "-x" | "--commands" => {
+                    if let Some(path) = argv.next() {
+                        config.commands = Some(path.clone());
+                    } else {
+                        return Err(InvalidArgError::new(String::from("--commands needs a file")));
+                    }
+                }
+                "--no-init" => {
+                    config.init = false;
+                }
+                "--tui" => {
+                    config.tui = true;
+                }
+                "--tstp" => {
+                    config.tstp = true;
+                }
+                "--gdb" => {
+                    if let Some(port) = argv.next() {
+                        config.gdb = Some(port.parse::<u16>()?);
+                    } else {
+                        return Err(InvalidArgError::new(String::from("--gdb needs a port")));
+                    }
+                }
+                "--dap" => {
+                    if let Some(port) = argv.next() {
+                        config.dap = Some(port.parse::<u16>()?);
+                    } else {
+                        return Err(InvalidArgError::new(String::from("--dap needs a port")));
+                    }
+                }
+                "--profile" => {
+                    if let Some(path) = argv.next() {
+                        config.profile = Some(path.clone());
+                    } else {
+                        return Err(InvalidArgError::new(String::from("--profile needs a file")));
+                    }
+                }
+                "--trace" => {
+                    if let Some(path) = argv.next() {
+                        config.trace = Some(path.clone());
+                    } else {
+                        return Err(InvalidArgError::new(String::from("--trace needs a file")));
+                    }
+                }
+                "--coverage" => {
+                    if let Some(path) = argv.next() {
+                        config.coverage = Some(path.clone());
+                    } else {
+                        return Err(InvalidArgError::new(String::from("--coverage needs a file")));
+                    }
+                }
+                "--smc" => {
+                    config.smc = true;
+                }
+                "--smc-break" => {
+                    config.smc = true;
+                    config.smc_break = true;
+                }
+                "--jit" => {
+                    config.jit = true;
+                }
+                "--rpc" => {
+                    if let Some(path) = argv.next() {
+                        config.rpc = Some(path.clone());
+                    } else {
+                        return Err(InvalidArgError::new(String::from("--rpc needs a socket path")));
+                    }
+                }
                 file => {
-                    if config.path == "" {
+                    if config.path.is_empty() {
                         config.path = file.to_owned();
                     } else {
                         return Err(InvalidArgError::new(format!("unknown argument {}", file)));
@@ -86,6 +224,9 @@ fn main() -> BoxResult<()> {
 }
 
 fn load(config: &Config) -> BoxResult<Vec<u8>> {
+    if config.path.is_empty() && config.dap.is_some() {
+        return Ok(Vec::new()); // the editor names the program when it launches
+    }
     if config.quiet {
         println!("reading: {}", config.path);
     }
@@ -93,56 +234,267 @@ fn load(config: &Config) -> BoxResult<Vec<u8>> {
     if config.quiet {
         println!("running: {}", config.path);
     }
-    return Ok(program);
+    Ok(program)
 }
 
 
-// extern fn handle_sigint(s:i32) {
-//     println!("{}", s);
-// }
+/// `coverage`: merge coverage files of a program, report on them and annotate its disassembly,
+/// the program can be a snapshot so code the guest decrypted at runtime disassembles too
+fn coverage(args: &[String]) -> BoxResult<()> {
+    let mut symbols = Symbols::new();
+    let mut annotate = None;
+    let mut merge = None;
+    let mut files = Vec::new();
+    let mut argv = args.iter();
+    while let Some(arg) = argv.next() {
+        match arg.as_ref() {
+            "--symbols" | "--annotate" | "--merge" => {
+                let value = match argv.next() {
+                    Some(value) => value,
+                    None => return Err(InvalidArgError::new(format!("{} needs a file", arg))),
+                };
+                match arg.as_ref() {
+                    "--symbols" => symbols.load(value)?,
+                    "--annotate" => annotate = Some(value),
+                    _ => merge = Some(value),
+                }
+            }
+            file => files.push(file),
+        }
+    }
+    if files.len() < 2 {
+        return Err(InvalidArgError::new(String::from("coverage needs a program and at least one coverage file")));
+    }
 
-fn run(program: Vec<u8>, config: &Config) -> BoxResult<()> {
-    // use nix::sys::signal;
+    let program = State::recover(fs::read(files[0])?)?.program;
+    let mut total = Coverage::empty();
+    for path in &files[1..] {
+        total.merge(&Coverage::load(path)?)?;
+    }
+    print!("{}", total.report(&program, &symbols));
+    if let Some(path) = annotate {
+        fs::write(path, total.annotate(&program, &symbols))?;
+        println!("annotated disassembly written to {}", path);
+    }
+    if let Some(path) = merge {
+        total.save(path)?;
+        println!("merged coverage written to {}", path);
+    }
+    Ok(())
+}
 
-    // unsafe { signal::signal(signal::Signal::SIGTSTP, signal::SigHandler::Handler(handle_sigint)) }?;
+/// `aot`: translate a program or snapshot into a Rust module that runs it against `synacor::aot::Runtime`,
+/// a coverage file of a run adds the code it only ever reached through registers
+fn translate(args: &[String]) -> BoxResult<()> {
+    let mut output = None;
+    let mut coverage = None;
+    let mut files = Vec::new();
+    let mut argv = args.iter();
+    while let Some(arg) = argv.next() {
+        match arg.as_ref() {
+            "-o" | "--output" | "--coverage" => {
+                let value = match argv.next() {
+                    Some(value) => value,
+                    None => return Err(InvalidArgError::new(format!("{} needs a file", arg))),
+                };
+                match arg.as_ref() {
+                    "--coverage" => coverage = Some(Coverage::load(value)?),
+                    _ => output = Some(value),
+                }
+            }
+            file => files.push(file),
+        }
+    }
+    let path = match files.as_slice() {
+        [path] => path,
+        _ => return Err(InvalidArgError::new(String::from("aot needs exactly one program"))),
+    };
 
-    let mut state = State::recover(program)?;
+    let name = Path::new(path).file_name().map(|name| name.to_string_lossy().into_owned());
+    let module = aot::translate(&fs::read(path)?, name.as_deref().unwrap_or("program"), coverage.as_ref())?;
+    match output {
+        Some(output) => {
+            fs::write(output, module)?;
+            println!("module written to {}", output);
+        }
+        None => print!("{}", module),
+    }
+    Ok(())
+}
 
-    let mut meta = Meta::new();
+/// the user wide and then the project local init file, whichever exist
+fn init_files() -> Vec<String> {
+    let mut files = Vec::new();
+    if let Ok(home) = env::var("HOME") {
+        files.push(format!("{}/.synacorrc", home));
+    }
+    files.push(String::from("./.synacorrc"));
+    files.dedup_by(|a, b| fs::canonicalize(a).ok() == fs::canonicalize(b).ok());
+    files.into_iter().filter(|path| Path::new(path).is_file()).collect()
+}
 
-    meta.debug = config.debug;
+fn run(program: Vec<u8>, config: &Config) -> BoxResult<()> {
+    signal::install(config.tstp)?;
 
-    loop {
-        // first check if there is any user input to handle
-        let last = opcode::parse(&state.program, &state.ip);
-        if meta.debug {
-            println!("{}: {:?}", state.ip, last);
+    let mut state = if program.is_empty() { State::new(program) } else { State::recover(program)? };
+
+    let mut meta = Meta::new();
+
+    if let Some(path) = &config.trace {
+        meta.observer = Some(Box::new(Recorder::new(Box::new(BufWriter::new(File::create(path)?)))));
+    } else if config.debug {
+        meta.observer = Some(Box::new(Verbose));
+    }
+    if let Some(path) = &config.symbols {
+        meta.symbols.load(path)?;
+    }
+    if config.init {
+        for path in init_files() {
+            source(&mut meta, &path)?;
         }
-        meta.op_count = meta.op_count + 1;
-        let curr = opcode::parse(&state.program, &state.ip);
+    }
+    if let Some(path) = &config.commands {
+        source(&mut meta, path)?;
+    }
+    script(&mut state, &mut meta)?;
+    if config.profile.is_some() {
+        meta.profile = Some(Profile::new());
+    }
+    if config.coverage.is_some() {
+        meta.hooks.coverage = Some(Coverage::new(&state.program));
+    }
+    if config.smc {
+        let mut smc = Smc::new();
+        smc.stop = config.smc_break;
+        meta.hooks.smc = Some(smc);
+    }
 
-        if meta.debug {
-            println!("{}: {:?}", state.ip, curr);
+    let result = session(&mut state, &mut meta, config);
+    if let (Some(path), Some(profile)) = (&config.profile, &meta.profile) {
+        profile.write(path, &state, &meta.symbols)?;
+        println!("profile written to {} and {}.folded", path, path);
+    }
+    if let (Some(path), Some(coverage)) = (&config.coverage, &meta.hooks.coverage) {
+        coverage.accumulate(path)?;
+        println!("coverage added to {}", path);
+    }
+    if let Some(smc) = &meta.hooks.smc {
+        print!("{}", smc.report(&meta.symbols));
+    }
+    result
+}
+
+/// hand the VM to whichever front end was asked for, or run it on the terminal
+fn session(state: &mut State, meta: &mut Meta, config: &Config) -> BoxResult<()> {
+    if let Some(port) = config.gdb {
+        gdb::serve(state, meta, port)?;
+    }
+
+    if let Some(port) = config.dap {
+        let name = Path::new(&config.path).file_name().map(|name| name.to_string_lossy().into_owned());
+        dap::serve(state, meta, port, name.as_deref().unwrap_or("program"))?;
+        if state.program.is_empty() {
+            return Ok(()); // the editor never launched anything
         }
+    }
+
+    if meta.halt {
+        game_over(state, meta);
+        return Ok(());
+    }
 
+    if config.tui {
+        tui::run(state, meta)?;
+        game_over(state, meta);
+        return Ok(());
+    }
 
+    let mut rpc = match &config.rpc {
+        Some(path) => Some(Rpc::listen(path, meta)?),
+        None => None,
+    };
 
+    #[cfg(not(feature = "jit"))]
+    if config.jit {
+        return Err(InvalidArgError::new(String::from("--jit needs a build with the jit feature")));
+    }
+    // blocks and superinstructions skip the per instruction polling the rpc server relies on
+    #[cfg(feature = "jit")]
+    let mut jit = if config.jit && rpc.is_none() { Some(Jit::new()?) } else { None };
+    #[cfg(feature = "jit")]
+    let mut native = Jit::usable(meta);
+    let mut fusing = rpc.is_none() && peephole::usable(meta);
+
+    loop {
         // if we want, run the opcode;
-        opcode::execute(&mut state, &mut meta);
+        #[cfg(feature = "jit")]
+        let compiled = match jit.as_mut() {
+            Some(jit) if native => jit.run(state, meta),
+            _ => None,
+        };
+        #[cfg(not(feature = "jit"))]
+        let compiled = None;
+        let compiled = compiled.or_else(|| if fusing { peephole::run(state, meta) } else { None });
+        let curr = match compiled {
+            Some(code) => code,
+            None => opcode::step(state, meta),
+        };
 
-        if meta.break_op == curr {
-            println!("DEBUG: hit break OP: {}", meta.break_op);
-            game_over(&state, &meta);
+        if meta.hooks.break_op == curr {
+            meta.io.flush();
+            println!("DEBUG: hit break OP: {}", meta.hooks.break_op);
+            game_over(state, meta);
+            meta.debugging = true;
+        }
+
+        if meta.stop(state) {
+            meta.debugging = true;
+        }
+
+        if signal::interrupted() {
+            meta.io.flush();
+            println!();
+            println!("DEBUG: interrupted, Ctrl-C again within a second quits");
             meta.debugging = true;
         }
 
         if meta.debugging {
             meta.debugging = false;
-            debugger(&mut state, &mut meta)?;
+            match meta.reason.take() {
+                Some(Reason::DivideByZero) => {
+                    meta.io.flush();
+                    println!("DEBUG: mod by zero at {}", state.ip);
+                }
+                Some(Reason::UnknownOpcode) => {
+                    meta.io.flush();
+                    println!("DEBUG: unknown opcode at {}: {}", state.ip, opcode::disassemble(&state.program, state.ip).0);
+                }
+                _ => {}
+            }
+            meta.resume = Resume::Continue;
+            debugger(state, meta)?;
+            signal::interrupted(); // drop interrupts that arrived while the prompt was open
+            fusing = rpc.is_none() && peephole::usable(meta);
+            #[cfg(feature = "jit")]
+            {
+                native = Jit::usable(meta);
+            }
+        }
+
+        if let Some(rpc) = rpc.as_mut() {
+            if meta.breakpoints.contains(&state.ip) {
+                rpc.stop(state, meta, "breakpoint")?;
+            } else if meta.op_count.is_multiple_of(POLL_INTERVAL) {
+                rpc.service(state, meta)?;
+            }
         }
 
-        if meta.halt == true {
-            game_over(&state, &meta);
+        if meta.halt {
+            meta.io.flush();
+            if let Some(rpc) = &rpc {
+                rpc.notify("halted", serde_json::json!({ "ip": state.ip, "instructions": meta.op_count }));
+            }
+            game_over(state, meta);
             break;
         }
     }
@@ -153,12 +505,12 @@ fn run(program: Vec<u8>, config: &Config) -> BoxResult<()> {
 pub fn game_over(state: &State, meta: & Meta) {
     println!("instructions completed {}", meta.op_count);
     println!("[IP] at {}", state.ip);
-    println!("");
+    println!();
     println!("Registers: ");
     let mut i = 0;
     loop {
         println!("[{}] = {}", i, state.register[i]);
-        i = i + 1;
+        i += 1;
         if i > 7 {
             break;
         }
@@ -166,7 +518,7 @@ pub fn game_over(state: &State, meta: & Meta) {
     println!("Stack: ");
     let mut i = 0;
     loop {
-        i = i + 1;
+        i += 1;
         if i >= state.stack.len() {
             break;
         }
@@ -176,6 +528,6 @@ pub fn game_over(state: &State, meta: & Meta) {
 
 /// opcode 0: HALT
 pub fn halt(state: &State, meta: &mut Meta) {
-    game_over(&state, &meta);
+    game_over(state, meta);
     meta.halt = true;
 }
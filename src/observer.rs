@@ -0,0 +1,166 @@
+use crate::machine::Machine;
+
+use std::fmt::Write as _;
+use std::io::Write;
+
+/// gets told what each instruction does as it runs, every callback does nothing unless overridden; values are widened
+/// to u64 like the machine's registers
+pub trait Observer<M: Machine> {
+    /// whether the observer prints to stdout itself, guest output is then flushed as it happens so the two interleave
+    fn prints(&self) -> bool {
+        false
+    }
+    /// the instruction at ip is about to run
+    fn on_fetch(&mut self, _machine: &M, _ip: usize) {}
+    fn on_register_write(&mut self, _register: usize, _value: u64) {}
+    /// a write to the raw destination was dropped, it names no register
+    fn on_dropped_write(&mut self, _raw: u64) {}
+    /// value was read from the byte address
+    fn on_memory_read(&mut self, _address: usize, _value: u64) {}
+    /// value was written to the byte address
+    fn on_memory_write(&mut self, _address: usize, _value: u64) {}
+    /// value went onto the stack, which is now depth deep
+    fn on_stack_push(&mut self, _value: u64, _depth: usize) {}
+    /// value came off the stack, None when it was already empty
+    fn on_stack_pop(&mut self, _value: Option<u64>, _depth: usize) {}
+    /// ip left straight-line order, for taken branches, calls and returns
+    fn on_jump(&mut self, _from: usize, _to: usize) {}
+    fn on_output(&mut self, _byte: u8) {}
+    fn on_input(&mut self, _byte: u8) {}
+}
+
+/// the plain run path, every callback compiles away
+pub struct Silent;
+
+impl<M: Machine> Observer<M> for Silent {}
+
+/// explains every instruction and its effects on stdout, what `-d` and `debug on` turn on
+pub struct Verbose;
+
+impl<M: Machine> Observer<M> for Verbose {
+    fn prints(&self) -> bool {
+        true
+    }
+
+    fn on_fetch(&mut self, machine: &M, ip: usize) {
+        println!("{:#06X}: {:<24} ; {}", ip, machine.decode(ip).0, machine.describe(ip));
+    }
+
+    fn on_register_write(&mut self, register: usize, value: u64) {
+        println!("          [r{}] = {}", register, value);
+    }
+
+    fn on_dropped_write(&mut self, raw: u64) {
+        println!("          dropped write to {:#06x}, it names no register", raw);
+    }
+
+    fn on_memory_read(&mut self, address: usize, value: u64) {
+        println!("          read {} from &{:#06X}", value, address);
+    }
+
+    fn on_memory_write(&mut self, address: usize, value: u64) {
+        println!("          [&{:#06X}] = {}", address, value);
+    }
+
+    fn on_stack_push(&mut self, value: u64, depth: usize) {
+        println!("          <{}> = {}", depth, value);
+    }
+
+    fn on_stack_pop(&mut self, value: Option<u64>, depth: usize) {
+        match value {
+            Some(value) => println!("          popped {}, <{}> left", value, depth),
+            None => println!("          pop on an empty stack"),
+        }
+    }
+
+    fn on_jump(&mut self, from: usize, to: usize) {
+        println!("          [IP] {:#06X} -> {:#06X}", from, to);
+    }
+
+    fn on_output(&mut self, byte: u8) {
+        println!(); // after the guest's own character
+        println!("          out {:?}", byte as char);
+    }
+
+    fn on_input(&mut self, byte: u8) {
+        println!("          in {:?}", byte as char);
+    }
+}
+
+/// writes one line per instruction run, its address, its assembly and what it changed, what `--trace` turns on
+pub struct Recorder {
+    out: Box<dyn Write>,
+    /// the instruction running now, written out once the next one is fetched
+    line: String,
+}
+
+impl Recorder {
+    pub fn new(out: Box<dyn Write>) -> Recorder {
+        Recorder {
+            out,
+            line: String::new(),
+        }
+    }
+
+    fn finish(&mut self) {
+        if !self.line.is_empty() {
+            let _ = writeln!(self.out, "{}", self.line);
+            self.line.clear();
+        }
+    }
+}
+
+impl<M: Machine> Observer<M> for Recorder {
+    fn on_fetch(&mut self, machine: &M, ip: usize) {
+        self.finish();
+        let _ = write!(self.line, "{:#06X}: {:<24} ;", ip, machine.decode(ip).0);
+    }
+
+    fn on_register_write(&mut self, register: usize, value: u64) {
+        let _ = write!(self.line, " r{}={}", register, value);
+    }
+
+    fn on_dropped_write(&mut self, raw: u64) {
+        let _ = write!(self.line, " dropped {:#06x}", raw);
+    }
+
+    fn on_memory_read(&mut self, address: usize, value: u64) {
+        let _ = write!(self.line, " read &{:#06X}={}", address, value);
+    }
+
+    fn on_memory_write(&mut self, address: usize, value: u64) {
+        let _ = write!(self.line, " &{:#06X}={}", address, value);
+    }
+
+    fn on_stack_push(&mut self, value: u64, _depth: usize) {
+        let _ = write!(self.line, " push {}", value);
+    }
+
+    fn on_stack_pop(&mut self, value: Option<u64>, _depth: usize) {
+        match value {
+            Some(value) => {
+                let _ = write!(self.line, " pop {}", value);
+            }
+            None => self.line.push_str(" pop empty"),
+        }
+    }
+
+    fn on_jump(&mut self, _from: usize, to: usize) {
+        let _ = write!(self.line, " -> {:#06X}", to);
+    }
+
+    fn on_output(&mut self, byte: u8) {
+        let _ = write!(self.line, " out {:?}", byte as char);
+    }
+
+    fn on_input(&mut self, byte: u8) {
+        let _ = write!(self.line, " in {:?}", byte as char);
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        self.finish();
+        let _ = self.out.flush();
+    }
+}
@@ -1,848 +1,471 @@
-use crate::util::read;
-use crate::util::read_x;
-use crate::util::read_argument;
+use crate::cache::Instruction;
+use crate::observer::Observer;
+use crate::observer::Silent;
 use crate::debug::Meta;
+use crate::debug::Reason;
 use crate::vm::State;
-use crate::util::write_argument;
+use crate::vm::Frame;
 use std::fmt;
 
-#[derive(Debug, PartialEq)]
-pub enum Code {
-    /// halt: 0
-    ///   stop execution and terminate the program
-    Halt,
-    /// set: 1 a b
-    ///   set register <a> to the value of <b>
-    Set(u8, u8),
-    /// push: 2 a
-    ///   push <a> onto the stack
-    Push(u8),
-    /// pop: 3 a
-    ///   remove the top element from the stack and write it into <a>; empty stack = error
-    Pop(u8),
-    /// eq: 4 a b c
-    ///   set <a> to 1 if <b> is equal to <c>; set it to 0 otherwise
-    Equals(u8, u8, u8),
-    /// gt: 5 a b c
-    ///   set <a> to 1 if <b> is greater than <c>; set it to 0 otherwise
-    GreaterThan(u8, u8, u8),
-    /// jmp: 6 a
-    ///   jump to <a>
-    Jump(u8),
-    /// jt: 7 a b
-    ///   if <a> is nonzero, jump to <b>
-    JumpIfTrue(u8, u8),
-    /// jf: 8 a b
-    ///   if <a> is zero, jump to <b>
-    JumpIfFalse(u8, u8),
-    /// add: 9 a b c
-    ///   assign into <a> the sum of <b> and <c> (modulo 32768)
-    Add(u8, u8, u8),
-    /// mult: 10 a b c
-    ///   store into <a> the product of <b> and <c> (modulo 32768)
-    Multiply(u8, u8, u8),
-    /// mod: 11 a b c
-    ///   store into <a> the remainder of <b> divided by <c>
-    Modulo(u8, u8, u8),
-    /// and: 12 a b c
-    ///   stores into <a> the bitwise and of <b> and <c>
-    And(u8, u8, u8),
-    /// or: 13 a b c
-    ///   stores into <a> the bitwise or of <b> and <c>
-    Or(u8, u8, u8),
-    /// not: 14 a b
-    ///   stores 15-bit bitwise inverse of <b> in <a>
-    Not(u8, u8),
-    /// rmem: 15 a b
-    ///   read memory at address <b> and write it to <a>
-    ReadMemory(u8, u8),
-    /// wmem: 16 a b
-    ///   write the value from <b> into memory at address <a>
-    WriteMemory(u8, u8),
-    /// call: 17 a
-    ///   write the address of the next instruction to the stack and jump to <a>
-    Call(u8),
-    /// ret: 18
-    ///   remove the top element from the stack and jump to it; empty stack = halt
-    Return,
-    /// out: 19 a
-    ///   write the character represented by ascii code <a> to the terminal
-    Out(u8),
-    /// in: 20 a
-    ///   read a character from the terminal and write its ascii code to <a>; it can be assumed that once input starts, it will continue until a newline is encountered; this means that you can safely read whole lines from the keyboard and trust that they will be fully read
-    In(u8),
-    /// noop: 21
-    ///   no operation
-    Noop,
-    /// unkown: ??
-    ///   no operation, likely data
-    Data,
+/// what an opcode does with an operand
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operand {
+    /// a register written to
+    Register,
+    /// a literal or a register read from
+    Value,
+    /// a value used as a word address, jumped to or read or written
+    Address,
 }
 
-impl Code {
-    pub fn len(&self) -> usize {
-        match self {
-            Code::Halt => 0,
-            Code::Set(..) => 2,
-            Code::Push(..) => 1,
-            Code::Pop(..) => 1,
-            Code::Equals(..) => 3,
-            Code::GreaterThan(..) => 3,
-            Code::Jump(..) => 1,
-            Code::JumpIfTrue(..) => 2,
-            Code::JumpIfFalse(..) => 2,
-            Code::Add(..) => 3,
-            Code::Multiply(..) => 3,
-            Code::Modulo(..) => 3,
-            Code::And(..) => 3,
-            Code::Or(..) => 3,
-            Code::Not(..) => 2,
-            Code::ReadMemory(..) => 2,
-            Code::WriteMemory(..) => 2,
-            Code::Call(..) => 1,
-            Code::Return => 0,
-            Code::Out(..) => 1,
-            Code::In(..) => 1,
-            _ => 0,
-        }
-    }
-    pub fn description(&self) -> &str {
-        match self {
-            Code::Halt => "stop execution and terminate the program",
-            Code::Set(..) => "set register <a> to the value of <b>",
-            Code::Push(..) => "push <a> onto the stack",
-            Code::Pop(..) => "remove the top element from the stack and write it into <a>; empty stack = error",
-            Code::Equals(..) => "set <a> to 1 if <b> is equal to <c>; set it to 0 otherwise",
-            Code::GreaterThan(..) => "set <a> to 1 if <b> is greater than <c>; set it to 0 otherwise",
-            Code::Jump(..) => "jump to <a>",
-            Code::JumpIfTrue(..) => "if <a> is nonzero, jump to <b>",
-            Code::JumpIfFalse(..) => "if <a> is zero, jump to <b>",
-            Code::Add(..) => "assign into <a> the sum of <b> and <c> (modulo 32768)",
-            Code::Multiply(..) => "store into <a> the product of <b> and <c> (modulo 32768)",
-            Code::Modulo(..) => "store into <a> the remainder of <b> divided by <c>",
-            Code::And(..) => "stores into <a> the bitwise and of <b> and <c>",
-            Code::Or(..) => "stores into <a> the bitwise or of <b> and <c>",
-            Code::Not(..) => "stores 15-bit bitwise inverse of <b> in <a>",
-            Code::ReadMemory(..) => "read memory at address <b> and write it to <a>",
-            Code::WriteMemory(..) => "write the value from <b> into memory at address <a>",
-            Code::Call(..) => "write the address of the next instruction to the stack and jump to <a>",
-            Code::Return => "remove the top element from the stack and jump to it; empty stack = halt",
-            Code::Out(..) => "write the character represented by ascii code <a> to the terminal",
-            Code::In(..) => "read a character from the terminal and write its ascii code to <a>; it can be assumed that once input starts, it will continue until a newline is encountered; this means that you can safely read whole lines from the keyboard and trust that they will be fully read",
-            Code::Noop => "no operation",
-            Code::Data => "Not a known opcode, likely data",
-        }
-    }
-
-    pub fn name(&self) -> &str {
-        match self {
-            Code::Halt => "Halt",
-            Code::Set(..) => "Set",
-            Code::Push(..) => "Push",
-            Code::Pop(..) => "Pop",
-            Code::Equals(..) => "Equals",
-            Code::GreaterThan(..) => "GreaterThan",
-            Code::Jump(..) => "Jump",
-            Code::JumpIfTrue(..) => "JumpIfTrue",
-            Code::JumpIfFalse(..) => "JumpIfFalse",
-            Code::Add(..) => "Add",
-            Code::Multiply(..) => "Multiply",
-            Code::Modulo(..) => "Modulo",
-            Code::And(..) => "And",
-            Code::Or(..) => "Or",
-            Code::Not(..) => "Not",
-            Code::ReadMemory(..) => "ReadMemory",
-            Code::WriteMemory(..) => "WriteMemory",
-            Code::Call(..) => "Call",
-            Code::Return => "Return",
-            Code::Out(..) => "Out",
-            Code::In(..) => "In",
-            Code::Noop => "Noop",
-            Code::Data => "Data",
-        }
-    }
+/// where an instruction leaves ip, for the back ends that cut the program into blocks
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Flow {
+    /// on to the next instruction, or nowhere while it waits for the debugger
+    Next,
+    /// to its address operand or the next instruction: jt, jf, and call which comes back
+    Branch,
+    /// somewhere else and not back: jmp, and ret to whatever the stack holds
+    Jump,
+    /// the machine stops: halt, and data
+    Stop,
 }
 
-impl fmt::Display for Code {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-           Code::Data => write!(f, "{:?}", self),
-           _ => write!(f, "{:?}", self),
-        }
-    }
+/// the flow an entry names, Next when it names none
+macro_rules! flow {
+    () => {
+        Flow::Next
+    };
+    ($flow:ident) => {
+        Flow::$flow
+    };
 }
 
-/// lookup opcode
-pub fn lookup(op: u8)  -> Code {
-    match op {
-        0 => Code::Halt,
-        1 => Code::Set(0, 0),
-        2 => Code::Push(0),
-        3 => Code::Pop(0),
-        4 => Code::Equals(0, 0, 0),
-        5 => Code::GreaterThan(0, 0, 0),
-        6 => Code::Jump(0),
-        7 => Code::JumpIfTrue(0, 0),
-        8 => Code::JumpIfFalse(0, 0),
-        9 => Code::Add(0, 0, 0),
-        10 => Code::Multiply(0, 0, 0),
-        11 => Code::Modulo(0, 0, 0),
-        12 => Code::And(0, 0, 0),
-        13 => Code::Or(0, 0, 0),
-        14 => Code::Not(0, 0),
-        15 => Code::ReadMemory(0, 0),
-        16 => Code::WriteMemory(0, 0),
-        17 => Code::Call(0),
-        18 => Code::Return,
-        19 => Code::Out(0),
-        20 => Code::In(0),
-        21 => Code::Noop,
-        _ => Code::Data,
-    }
+/// expands to the second token, for repeating something once per operand
+macro_rules! per_operand {
+    ($operand:tt $with:tt) => {
+        $with
+    };
 }
 
-/// get the opcode and arguments
-pub fn parse(program: &Vec<u8>, ip: &usize)  -> Code {
-    match program[*ip] {
-        0 => Code::Halt,
-        1 => Code::Set(program[ip+1], program[ip+2]),
-        2 => Code::Push(program[ip+1]),
-        3 => Code::Pop(program[ip+1]),
-        4 => Code::Equals(program[ip+1], program[ip+2], program[ip+3]),
-        5 => Code::GreaterThan(program[ip+1], program[ip+2], program[ip+3]),
-        6 => Code::Jump(program[ip+1]),
-        7 => Code::JumpIfTrue(program[ip+1], program[ip+2]),
-        8 => Code::JumpIfFalse(program[ip+1], program[ip+2]),
-        9 => Code::Add(program[ip+1], program[ip+2], program[ip+3]),
-        10 => Code::Multiply(program[ip+1], program[ip+2], program[ip+3]),
-        11 => Code::Modulo(program[ip+1], program[ip+2], program[ip+3]),
-        12 => Code::And(program[ip+1], program[ip+2], program[ip+3]),
-        13 => Code::Or(program[ip+1], program[ip+2], program[ip+3]),
-        14 => Code::Not(program[ip+1], program[ip+2]),
-        15 => Code::ReadMemory(program[ip+1], program[ip+2]),
-        16 => Code::WriteMemory(program[ip+1], program[ip+2]),
-        17 => Code::Call(program[ip+1]),
-        18 => Code::Return,
-        19 => Code::Out(program[ip+1]),
-        20 => Code::In(program[ip+1]),
-        21 => Code::Noop,
-        _ => Code::Data,
-    }
-}
-
-/// get debug information about op
-pub fn debug_op(state: &mut State, meta: &mut Meta, code: Code) {
-}
-
-// /// print debug information about op
-// pub fn inspect_op(code: Code) {
-//     match code {
-//         Code::Halt => {
-//             println!("opcode 0: Halt");
-//             println!("length: 2");
-//         },
-//         Code::Set(arg0, arg1) => {
-//             println!("opcode 1: SET [A] TO B");
-//             println!("length: 4");
-//             println!(" A: REGISTER");
-//             println!(" B: INTEGER");
-//         }
-//         Code::Data => {
-//             println!("opcode unknown");
-//         }
-//     }
-// }
-
-// /// execute the opcode with side effects
-// pub fn execute_op(state: &mut State, meta: &mut Meta, code: Code) {
-//     match code {
-//         Code::Halt => {
-//             meta.halt = true;
-//         },
-//         Code::Set(arg0, arg1) => {
-//             if meta.debug {
-//                 println!("opcode 1: SET [A] TO B");
-//                 println!(" A: STATE.REGISTER");
-//             }
-//             state.ip = state.ip + 2;
-//             let a = write_argument(&state) as usize;
-//             if meta.debug {
-//                 println!(" B: INTEGER");
-//             }
-//             state.ip = state.ip + 2;
-//             let b: u16 = read_argument(&state);
-
-//             state.register[a] = b;
-
-//             state.ip = state.ip + 2;
-//             if meta.debug {
-//                 println!(" RESULT:  [A{}] = B{}", a, b);
-//                 println!("          [A{}] = {}", a, state.register[a]);
-//                 println!("");
-//                 println!(" [IP A{}]", a);
-//             }
-//         }
-//         // Code::Push(program[ip+1]),
-//         // Code::Pop(program[ip+1]),
-//         // Code::Equals(program[ip+1], program[ip+2], program[ip+3]),
-//         // Code::GreaterThan(program[ip+1], program[ip+2], program[ip+3]),
-//         // Code::Jump(program[ip+1]),
-//         // Code::JumpIfTrue(program[ip+1], program[ip+2]),
-//         // Code::JumpIfFalse(program[ip+1], program[ip+2]),
-//         // Code::Add(program[ip+1], program[ip+2], program[ip+3]),
-//         // Code::Multiply(program[ip+1], program[ip+2], program[ip+3]),
-//         // Code::Modulo(program[ip+1], program[ip+2], program[ip+3]),
-//         // Code::And(program[ip+1], program[ip+2], program[ip+3]),
-//         // Code::Or(program[ip+1], program[ip+2], program[ip+3]),
-//         // Code::Not(program[ip+1], program[ip+2]),
-//         // Code::ReadMemory(program[ip+1], program[ip+2]),
-//         // Code::WriteMemory(program[ip+1], program[ip+2]),
-//         // Code::Call(program[ip+1]),
-//         // Code::Return,
-//         // Code::Out(program[ip+1]),
-//         // Code::In(program[ip+1]),
-//         // Code::Noop,
-//         // Code::Data,
-//     }
-// }
-
-/// run the OP code with side effects
-pub fn execute(state: &mut State, meta: &mut Meta) {
-    match state.program[state.ip as usize] {
-        0 => {
-            meta.halt = true;
-        },
-        1 => {
-            if meta.debug {
-                println!("opcode 1: SET [A] TO B");
-                println!(" A: STATE.REGISTER");
-            }
-            state.ip = state.ip + 2;
-            let a = write_argument(&state) as usize;
-            if meta.debug {
-                println!(" B: INTEGER");
-            }
-            state.ip = state.ip + 2;
-            let b: u16 = read_argument(&state);
-
-            state.register[a] = b;
-
-            state.ip = state.ip + 2;
-            if meta.debug {
-                println!(" RESULT:  [A{}] = B{}", a, b);
-                println!("          [A{}] = {}", a, state.register[a]);
-                println!("");
-                println!(" [IP A{}]", a);
-            }
-        }
-        2 => {
-            if meta.debug {
-                println!("opcode 2: PUSH TO STACK FROM [A]");
-                println!(" A: STATE.REGISTER");
-            }
-            state.ip = state.ip + 2;
-            let a: u16 = read_argument(&state);
-            let b: u16 = write_argument(&state);
-
-            state.stack.push(a);
-
-            state.ip = state.ip + 2;
-            if meta.debug {
-                println!(" RESULT:  <{}> = [A{}]", state.stack.len(), b);
-                println!("          <{}> = {}", state.stack.len(), a);
-                println!("          <{}> = {}", state.stack.len(), state.stack[state.stack.len() - 1]);
-                println!(" [SP IP] <{}>", state.stack.len());
-            }
+/// the opcode table, everything the VM knows about an opcode is generated from its one entry:
+/// `number mnemonic Variant(operand: Kind, ..) [Flow] "spec description" => { what it does }`, the flow left out
+/// for instructions that go on to the next one; the executor body sees the operands as raw words next to the names
+/// bound in the header
+macro_rules! opcodes {
+    (
+        ($state:ident, $meta:ident, $observer:ident, $next:ident);
+        $(
+            $op:literal $mnemonic:literal $variant:ident $( ( $($arg:ident: $kind:ident),+ ) )? $( [$flow:ident] )?
+                $description:literal => $body:block
+        )*
+        _ $unknown_mnemonic:literal $unknown:ident $unknown_description:literal => $unknown_body:block
+    ) => {
+        /// an opcode, the operands are in the `Instruction` it was decoded as
+        #[derive(Debug, Clone, Copy, PartialEq)]
+        pub enum Code {
+            $(
+                #[doc = concat!($mnemonic, ": ", $op $($(, " ", stringify!($arg))+)?)]
+                #[doc = concat!("  ", $description)]
+                $variant,
+            )*
+            #[doc = concat!($unknown_mnemonic, ": ??")]
+            #[doc = concat!("  ", $unknown_description)]
+            $unknown,
         }
-        3 => {
-            if meta.debug {
-                println!("opcode 3: POP FROM STACK TO [A]");
-                println!(" A: STATE.REGISTER");
-            }
-            state.ip = state.ip + 2;
-            let a = write_argument(&state) as usize;
 
-            if let Some(data) = state.stack.pop() {
-                state.register[a] = data;
-            } else {
-                // halt
+        impl Code {
+            pub fn len(&self) -> usize {
+                match self {
+                    $( Code::$variant => 0 $($(+ per_operand!($arg 1))+)?, )*
+                    Code::$unknown => 0,
+                }
             }
 
-            state.ip = state.ip + 2;
-            if meta.debug {
-                println!(" RESULT:  [A{}] = <{}>", a, state.stack.len());
-                println!("          [A{}] = {}", a, state.register[a]);
-                println!(" [IP SP A{}] <{}>", a, state.stack.len());
-            }
-        }
-        4 => {
-            if meta.debug {
-                println!("opcode 4: IF B EQUALS C SET A TO 1 ELSE A TO 0");
-                println!(" A: STATE.REGISTER");
+            pub fn description(&self) -> &'static str {
+                match self {
+                    $( Code::$variant => $description, )*
+                    Code::$unknown => $unknown_description,
+                }
             }
-            state.ip = state.ip + 2;
-            let a = write_argument(&state) as usize;
 
-            if meta.debug {
-                println!(" B: INTEGER");
+            /// the name the arch-spec gives the instruction
+            pub fn mnemonic(&self) -> &'static str {
+                match self {
+                    $( Code::$variant => $mnemonic, )*
+                    Code::$unknown => $unknown_mnemonic,
+                }
             }
-            state.ip = state.ip + 2;
-            let b: u16 = read_argument(&state);
 
-            if meta.debug {
-                println!(" C: INTEGER");
+            pub fn name(&self) -> &'static str {
+                match self {
+                    $( Code::$variant => stringify!($variant), )*
+                    Code::$unknown => stringify!($unknown),
+                }
             }
-            state.ip = state.ip + 2;
-            let c: u16 = read_argument(&state);
 
-            if b == c {
-                state.register[a] = 1;
-            } else {
-                state.register[a] = 0;
+            /// what each operand is for, in order
+            pub fn operands(&self) -> &'static [Operand] {
+                match self {
+                    $( Code::$variant => &[$($(Operand::$kind),+)?], )*
+                    Code::$unknown => &[],
+                }
             }
 
-            state.ip = state.ip + 2;
-            if meta.debug {
-                println!(" RESULT:  [A{}] = B{} == C{}", a, b, c);
-                println!("          [A{}] = {}", a, state.register[a]);
-                println!("");
-                println!(" [{} IP]", a);
+            pub fn flow(&self) -> Flow {
+                match self {
+                    $( Code::$variant => flow!($($flow)?), )*
+                    Code::$unknown => Flow::Stop,
+                }
             }
         }
-        5 => {
-            //3 args, should increment ip by 8
-            if meta.debug {
-                println!("opcode 5: IF B LARGER THAN C SET A TO 1 ELSE A TO 0");
-                println!(" A: STATE.REGISTER");
-            }
-            state.ip = state.ip + 2;
-            let a = write_argument(&state) as usize;
-
-            if meta.debug {
-                println!(" B: INTEGER");
-            }
-            state.ip = state.ip + 2;
-            let b: u16 = read_argument(&state);
-
-            if meta.debug {
-                println!(" C: INTEGER");
-            }
-            state.ip = state.ip + 2;
-            let c: u16 = read_argument(&state);
-
-            if b > c {
-                state.register[a] = 1;
-            } else {
-                state.register[a] = 0;
-            }
 
-            state.ip = state.ip + 2;
-            if meta.debug {
-                println!(" RESULT:  [A{}] = B{} > C{}", a, b, c);
-                println!("          [A{}] = {}", a, state.register[a]);
-                println!("");
-                println!("[{} IP]", a);
+        /// lookup opcode
+        pub fn lookup(op: u8) -> Code {
+            match op {
+                $( $op => Code::$variant, )*
+                _ => Code::$unknown,
             }
         }
-        6 => {
-            if meta.debug {
-                println!("opcode 6: JUMP");
-                println!(" A: JUMP ADDRESS");
-            }
-            state.ip = state.ip + 2;
-            let a = read_argument(&state) as usize;
-
-            state.ip = a * 2;
 
-            if meta.debug {
-                println!(" RESULT:  [IP] = &{}", a * 2);
-                println!("          [IP] = &{}", state.ip);
-                println!("");
-                println!(" [IP]");
+        /// run a decoded instruction with side effects, reporting each of them to the observer
+        pub fn execute<O: Observer<State> + ?Sized>(
+            $state: &mut State,
+            $meta: &mut Meta,
+            instruction: &Instruction,
+            $observer: &mut O,
+        ) {
+            let $next = $state.ip + instruction.len as usize * 2;
+            match instruction.op {
+                $(
+                    $op => {
+                        $( let [$($arg,)+ ..] = instruction.args; )?
+                        $body
+                    }
+                )*
+                _ => $unknown_body
             }
         }
-        7 => {
-            if meta.debug {
-                println!("opcode 7: JUMP IF NONZERO");
-                println!(" A: CONDITIONAL");
-            }
-            state.ip = state.ip + 2;
-            let a: u16 = read_argument(&state);
-
-            if meta.debug {
-                println!(" B: JUMP ADDRESS");
-            }
-            state.ip = state.ip + 2;
-            let b = read_argument(&state) as usize;
+    };
+}
 
-            if a != 0 {
-                state.ip = b * 2;
-            } else {
-                state.ip = state.ip + 2;
-            }
+opcodes! {
+    (state, meta, observer, next);
 
-            if meta.debug {
-                println!(" RESULT:  A{} != 0", b * 2);
-                println!("          [IP] = B{}", b * 2);
-                println!("          [IP] == {}", state.ip);
-                println!(" [IP]");
-            }
+    0 "halt" Halt [Stop] "stop execution and terminate the program" => {
+        meta.halt = true;
+    }
+    1 "set" Set(a: Register, b: Value) "set register <a> to the value of <b>" => {
+        let b = value(state, b);
+        set(state, observer, a, b);
+        state.ip = next;
+    }
+    2 "push" Push(a: Value) "push <a> onto the stack" => {
+        let a = value(state, a);
+        state.stack.push(a);
+        observer.on_stack_push(a as u64, state.stack.len());
+        state.ip = next;
+    }
+    3 "pop" Pop(a: Register) "remove the top element from the stack and write it into <a>; empty stack = error" => {
+        let data = state.stack.pop();
+        observer.on_stack_pop(data.map(u64::from), state.stack.len());
+        if let Some(data) = data {
+            set(state, observer, a, data);
+            state.unwind();
+        } else {
+            // halt
         }
-        8 => {
-            if meta.debug {
-                println!("opcode 8: JUMP IF ZERO");
-                println!(" A: CONDITIONAL");
-            }
-            state.ip = state.ip + 2;
-            let a: u16 = read_argument(&state);
-
-            if meta.debug {
-                println!(" B: JUMP ADDRESS");
-            }
-            state.ip = state.ip + 2;
-            let b = read_argument(&state) as usize;
-
-            if a == 0 {
-                state.ip = b * 2;
-            } else {
-                state.ip = state.ip + 2;
-            }
-
-            if meta.debug {
-                println!(" RESULT:  A{} == 0", a);
-                println!("          [IP] = B{}", b * 2);
-                println!("          [IP] == {}", state.ip);
-                println!(" [IP]");
-            }
+        state.ip = next;
+    }
+    4 "eq" Equals(a: Register, b: Value, c: Value) "set <a> to 1 if <b> is equal to <c>; set it to 0 otherwise" => {
+        let b = value(state, b);
+        let c = value(state, c);
+        set(state, observer, a, (b == c) as u16);
+        state.ip = next;
+    }
+    5 "gt" GreaterThan(a: Register, b: Value, c: Value) "set <a> to 1 if <b> is greater than <c>; set it to 0 otherwise" => {
+        let b = value(state, b);
+        let c = value(state, c);
+        set(state, observer, a, (b > c) as u16);
+        state.ip = next;
+    }
+    6 "jmp" Jump(a: Address) [Jump] "jump to <a>" => {
+        let a = value(state, a) as usize;
+        jump(state, observer, a * 2);
+    }
+    7 "jt" JumpIfTrue(a: Value, b: Address) [Branch] "if <a> is nonzero, jump to <b>" => {
+        let a = value(state, a);
+        let b = value(state, b) as usize;
+        if a != 0 {
+            jump(state, observer, b * 2);
+        } else {
+            state.ip = next;
         }
-        9 => {
-            if meta.debug {
-                println!("opcode 9: ADD SET [A] RESULT B + C");
-                println!(" A: STATE.REGISTER");
-            }
-            state.ip = state.ip + 2;
-            let a = write_argument(&state) as usize;
-
-            if meta.debug {
-                println!(" B: INTEGER");
-            }
-            state.ip = state.ip + 2;
-            let b: u16 = read_argument(&state);
-
-            if meta.debug {
-                println!(" C: INTEGER");
-            }
-            state.ip = state.ip + 2;
-            let c: u16 = read_argument(&state);
-
-            state.register[a] = (b + c) % 32768;
-
-            state.ip = state.ip + 2;
-            if meta.debug {
-                println!(" RESULT:  B{} + C{} = {}", b, c, (b + c) % 32768);
-                println!("          [A{}] = {}", a, state.register[a]);
-                println!("");
-                println!(" [IP {}]", a);
-            }
+    }
+    8 "jf" JumpIfFalse(a: Value, b: Address) [Branch] "if <a> is zero, jump to <b>" => {
+        let a = value(state, a);
+        let b = value(state, b) as usize;
+        if a == 0 {
+            jump(state, observer, b * 2);
+        } else {
+            state.ip = next;
         }
-        10 => {
-            if meta.debug {
-                println!("opcode 10: MUTIPLY SET [A] RESULT B * C");
-                println!(" A: STATE.REGISTER");
-            }
-            state.ip = state.ip + 2;
-            let a = write_argument(&state) as usize;
-
-            if meta.debug {
-                println!(" B: INTEGER");
-            }
-            state.ip = state.ip + 2;
-            let b = read_argument(&state) as usize;
-
-            if meta.debug {
-                println!(" C: INTEGER");
-            }
-            state.ip = state.ip + 2;
-            let c = read_argument(&state) as usize;
-
-            state.register[a] = ((b * c) % 32768) as u16;
-
-            state.ip = state.ip + 2;
-            if meta.debug {
-                println!(" RESULT:  B{} * C{} = {}", b, c, (b * c) % 32768);
-                println!("          [A{}] = {}", a, state.register[a]);
-                println!("");
-                println!(" [IP {}]", a);
-            }
+    }
+    9 "add" Add(a: Register, b: Value, c: Value) "assign into <a> the sum of <b> and <c> (modulo 32768)" => {
+        let b = value(state, b) as u32;
+        let c = value(state, c) as u32;
+        set(state, observer, a, ((b + c) % 32768) as u16);
+        state.ip = next;
+    }
+    10 "mult" Multiply(a: Register, b: Value, c: Value) "store into <a> the product of <b> and <c> (modulo 32768)" => {
+        let b = value(state, b) as usize;
+        let c = value(state, c) as usize;
+        set(state, observer, a, ((b * c) % 32768) as u16);
+        state.ip = next;
+    }
+    11 "mod" Modulo(a: Register, b: Value, c: Value) "store into <a> the remainder of <b> divided by <c>" => {
+        let b = value(state, b);
+        let c = value(state, c);
+        if c == 0 {
+            // ip stays on the mod, the front end reports it from meta.reason
+            meta.debugging = true;
+            meta.reason = Some(Reason::DivideByZero);
+        } else {
+            set(state, observer, a, (b % c) % 32768);
+            state.ip = next;
         }
-        11 => {
-            if meta.debug {
-                println!("opcode 11: MODULO SET [A] RESULT B % C");
-                println!(" A: STATE.REGISTER");
-            }
-            state.ip = state.ip + 2;
-            let a = write_argument(&state) as usize;
-
-            if meta.debug {
-                println!(" B: INTEGER");
-            }
-            state.ip = state.ip + 2;
-            let b: u16 = read_argument(&state);
-
-            if meta.debug {
-                println!(" C: INTEGER");
-            }
-            state.ip = state.ip + 2;
-            let c: u16 = read_argument(&state);
-
-            state.register[a] = (b % c) % 32768;
-
-            state.ip = state.ip + 2;
-            if meta.debug {
-                println!(" RESULT:  {} % {} = {}", b, c, (b % c) % 32768);
-                println!("          [A{}] = {}", a, state.register[a]);
-                println!("");
-                println!(" [IP {}]", a);
-            }
+    }
+    12 "and" And(a: Register, b: Value, c: Value) "stores into <a> the bitwise and of <b> and <c>" => {
+        let b = value(state, b);
+        let c = value(state, c);
+        set(state, observer, a, (b & c) % 32768);
+        state.ip = next;
+    }
+    13 "or" Or(a: Register, b: Value, c: Value) "stores into <a> the bitwise or of <b> and <c>" => {
+        let b = value(state, b);
+        let c = value(state, c);
+        set(state, observer, a, (b | c) % 32768);
+        state.ip = next;
+    }
+    14 "not" Not(a: Register, b: Value) "stores 15-bit bitwise inverse of <b> in <a>" => {
+        let b = value(state, b);
+        set(state, observer, a, (!b) % 32768);
+        state.ip = next;
+    }
+    15 "rmem" ReadMemory(a: Register, b: Address) "read memory at address <b> and write it to <a>" => {
+        let b = address(value(state, b));
+        if let Some(coverage) = &mut meta.hooks.coverage {
+            coverage.read(b);
         }
-        12 => {
-            if meta.debug {
-                println!("opcode 12: AND SET [A] RESULT B & C");
-                println!(" A: STATE.REGISTER");
-            }
-            state.ip = state.ip + 2;
-            let a = write_argument(&state) as usize;
-
-            if meta.debug {
-                println!(" B: INTEGER");
-            }
-            state.ip = state.ip + 2;
-            let b: u16 = read_argument(&state);
-
-            if meta.debug {
-                println!(" C: INTEGER");
-            }
-            state.ip = state.ip + 2;
-            let c: u16 = read_argument(&state);
-
-            state.register[a] = (b & c) % 32768;
-
-            state.ip = state.ip + 2;
-            if meta.debug {
-                println!(" RESULT:  {} & {} = {}", b, c, (b & c) % 32768);
-                println!("          [A{}] = {}", a, state.register[a]);
-                println!("");
-                println!(" [IP {}]", a);
-            }
+        // the word as stored, memory past the end of the image reads as zero
+        let c = word(&state.program, b);
+        observer.on_memory_read(b, c as u64);
+        set(state, observer, a, c);
+        state.ip = next;
+    }
+    16 "wmem" WriteMemory(a: Address, b: Value) "write the value from <b> into memory at address <a>" => {
+        let a = address(value(state, a));
+        let b = value(state, b);
+        if let Some(coverage) = &mut meta.hooks.coverage {
+            coverage.write(a);
         }
-        13 => {
-            if meta.debug {
-                println!("opcode 13: OR SET [A] RESULT B | C");
-                println!(" A: REGISTER");
-            }
-            state.ip = state.ip + 2;
-            let a = write_argument(&state) as usize;
-
-            if meta.debug {
-                println!(" B: INTEGER");
+        if let Some(smc) = &mut meta.hooks.smc {
+            if smc.log {
+                meta.io.flush();
             }
-            state.ip = state.ip + 2;
-            let b: u16 = read_argument(&state);
-
-            if meta.debug {
-                println!(" C: INTEGER");
-            }
-            state.ip = state.ip + 2;
-            let c: u16 = read_argument(&state);
-
-            state.register[a] = (b | c) % 32768;
-
-            state.ip = state.ip + 2;
-            if meta.debug {
-                println!(" RESULT:  {} | {} = {}", b, c, (b | c) % 32768);
-                println!("          [A{}] = {}", a, state.register[a]);
-                println!("");
-                println!(" [IP {}]", a);
+            if smc.write(state, state.ip, a, b) && smc.stop {
+                meta.debugging = true;
+                meta.reason = Some(Reason::CodeWrite);
             }
         }
-        14 => {
-            if meta.debug {
-                println!("opcode 14: NOT SET [A] RESULT !B");
-                println!(" A: REGISTER");
-            }
-            state.ip = state.ip + 2;
-            let a = write_argument(&state) as usize;
-
-            if meta.debug {
-                println!(" B: INTEGER");
-            }
-            state.ip = state.ip + 2;
-            let b: u16 = read_argument(&state);
-
-            state.register[a] = (!b) % 32768;
-            state.ip = state.ip + 2;
-            if meta.debug {
-                println!(" RESULT:  !{} = {}", b, (!b) % 32768);
-                println!("          [A{}] = {}", a, state.register[a]);
-                println!("");
-                println!(" [IP {}]", a);
-            }
+        if state.program.len() < a + 2 {
+            state.program.resize(a + 2, 0);
         }
-        15 => {
-            if meta.debug {
-                println!("opcode 15: RMEM READ TO [A] FROM &B");
-                println!(" A: REGISTER");
+        state.program[a + 1] = (b >> 8) as u8;
+        state.program[a] = b as u8;
+        state.cache.invalidate(a);
+        observer.on_memory_write(a, b as u64);
+        state.ip = next;
+    }
+    17 "call" Call(a: Address) [Branch] "write the address of the next instruction to the stack and jump to <a>" => {
+        let caller = state.ip;
+        let a = value(state, a) as usize;
+        state.stack.push(next as u16 / 2);
+        observer.on_stack_push(next as u64 / 2, state.stack.len());
+        state.frames.push(Frame {
+            caller,
+            callee: a * 2,
+            depth: state.stack.len(),
+        });
+        jump(state, observer, a * 2);
+    }
+    18 "ret" Return [Jump] "remove the top element from the stack and jump to it; empty stack = halt" => {
+        let n = state.stack.pop();
+        observer.on_stack_pop(n.map(u64::from), state.stack.len());
+        match n {
+            Some(n) => {
+                jump(state, observer, n as usize * 2);
+                state.unwind();
+            }
+            None => {
+                meta.halt = true;
             }
-            state.ip = state.ip + 2;
-            let a = write_argument(&state) as usize;
-
-            if meta.debug {
-                println!(" B: ADDRESS");
+        }
+    }
+    19 "out" Out(a: Value) "write the character represented by ascii code <a> to the terminal" => {
+        let a = value(state, a);
+        meta.io.write(a as u8);
+        if observer.prints() {
+            meta.io.flush();
+        }
+        observer.on_output(a as u8);
+        state.ip = next;
+    }
+    20 "in" In(a: Register) "read a character from the terminal and write its ascii code to <a>; it can be assumed that once input starts, it will continue until a newline is encountered; this means that you can safely read whole lines from the keyboard and trust that they will be fully read" => {
+        meta.io.flush(); // the prompt has to be out before we wait for an answer
+        match meta.io.read() {
+            Some(res) => {
+                observer.on_input(res);
+                set(state, observer, a, res as u16);
+                state.ip = next;
+            }
+            None => {
+                // EOF, interrupt or ~, hand over to the debugger and read again later
+                meta.debugging = true;
+                meta.reason = Some(Reason::Input);
             }
-            state.ip = state.ip + 2;
-            let mut b = read_argument(&state) as usize;
-
-            b = b * 2;
+        }
+    }
+    21 "noop" Noop "no operation" => {
+        state.ip = next;
+    }
+    _ "data" Data "Not a known opcode, likely data" => {
+        // ip stays on the word, the front end reports it from meta.reason
+        meta.debugging = true;
+        meta.reason = Some(Reason::UnknownOpcode);
+    }
+}
 
-            if meta.debug {
-                println!(" &B: MEMORY AT B");
-            }
-            let c = read_x(&state, b);
+impl fmt::Display for Code {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
 
-            state.register[a] = c;
+/// an operand as assembly, registers as r0 to r7
+fn operand(value: u16) -> String {
+    match value {
+        0..=32767 => value.to_string(),
+        32768..=32775 => format!("r{}", value - 32768),
+        _ => format!("{:#06x}", value),
+    }
+}
 
-            state.ip = state.ip + 2;
-            if meta.debug {
-                println!(" RESULT:  [A{}] = &{}", a, b);
-                println!("          [A{}] = {}", a, c);
-                println!("          [A{}] = {}", a, state.register[a]);
-                println!(" [IP {}]", a);
-            }
-        }
-        16 => {
-            if meta.debug {
-                println!("opcode 16: WMEM WRITE B TO &A");
-                println!(" A: ADDRESS");
-            }
-            state.ip = state.ip + 2;
-            let mut a = read_argument(&state) as usize;
-            a = a * 2;
+/// the word at a byte address, zero past the end of the program
+pub(crate) fn word(program: &[u8], i: usize) -> u16 {
+    let byte = |i: usize| program.get(i).copied().unwrap_or(0) as u16;
+    byte(i + 1) << 8 | byte(i)
+}
 
-            if meta.debug {
-                println!(" B: INTEGER");
-            }
-            state.ip = state.ip + 2;
-            let b: u16 = read_argument(&state);
+/// the opcode at ip, a whole word like the executor decodes it, past the end reads as zero
+pub fn parse(program: &[u8], ip: &usize) -> Code {
+    decode(program, *ip)
+}
 
-            let higher = (b >> 8) as u8;
-            let lower = b as u8;
+fn decode(program: &[u8], ip: usize) -> Code {
+    match word(program, ip) {
+        op @ 0..=255 => lookup(op as u8),
+        _ => Code::Data,
+    }
+}
 
-            state.program[a + 1] = higher;
-            state.program[a] = lower;
+/// length in words of the instruction at ip, data counts as one word
+pub fn length(program: &[u8], ip: usize) -> usize {
+    decode(program, ip).len() + 1
+}
 
-            state.ip = state.ip + 2;
-            if meta.debug {
-                println!(" RESULT:  [PROGRAM{}] = B{}", a, b);
-                println!("          b{:b} b{:b}", higher, lower);
-                println!(
-                    "          b{:b} b{:b} = b{:b}",
-                    state.program[a + 1],
-                    state.program[a],
-                    b
-                );
-                println!(" [IP PROGRAM]");
-            }
+/// the instruction at ip as assembly and its length in words, words past the end read as zero
+pub fn disassemble(program: &[u8], ip: usize) -> (String, usize) {
+    let op = word(program, ip);
+    let code = decode(program, ip);
+    if code == Code::Data {
+        return (format!("data {}", operand(op)), 1);
+    }
+    let mut text = String::from(code.mnemonic());
+    for i in 1..=code.len() {
+        let value = word(program, ip + i * 2);
+        match code {
+            Code::Out if (0x20..0x7F).contains(&value) => text.push_str(&format!(" '{}'", value as u8 as char)),
+            Code::Out if value == 10 => text.push_str(" '\\n'"),
+            _ => text.push_str(&format!(" {}", operand(value))),
         }
-        17 => {
-            if meta.debug {
-                println!("opcode 17: CALL &A");
-                println!(" A: ADDRESS");
-            }
-            state.ip = state.ip + 2;
-            let a = read_argument(&state) as usize;
-
-            state.ip = state.ip + 2;
-            state.stack.push(state.ip as u16 / 2);
+    }
+    (text, code.len() + 1)
+}
 
-            state.ip = a * 2;
-            if meta.debug {
-                println!(" RESULT:  [IP{}] = A{}", state.ip, a * 2);
-                println!("          <{}> = IP{}", state.stack.len() - 1, state.stack[state.stack.len() - 1]);
-                println!("");
-                println!(" [IP SP]");
-            }
-        }
-        18 => {
-            if state.stack.len() == 0 {
-                if meta.debug {
-                    println!("opcode 18: return: {}", state.stack[state.stack.len() - 1]);
-                }
-                println!("instructions completed {}", meta.op_count);
-                println!("IP at {}", state.ip);
-                meta.halt = true;
-            }
+/// decode and run the instruction at ip, returns the instruction that ran
+pub fn step(state: &mut State, meta: &mut Meta) -> Code {
+    meta.op_count += 1;
+    if let Some(profile) = &mut meta.profile {
+        profile.record(state);
+    }
+    if let Some(coverage) = &mut meta.hooks.coverage {
+        coverage.execute(state.ip);
+    }
+    if let Some(smc) = &mut meta.hooks.smc {
+        smc.execute(state);
+    }
+    let instruction = state.cache.fetch(&state.program, state.ip);
+    if meta.observer.is_none() {
+        execute(state, meta, &instruction, &mut Silent);
+    } else if let Some(mut observer) = meta.observer.take() {
+        observer.on_fetch(state, state.ip);
+        execute(state, meta, &instruction, observer.as_mut());
+        meta.observer = Some(observer);
+    }
+    instruction.code()
+}
 
+/// an operand's value, registers read through; arch-spec calls 32776 and up invalid without saying what they do,
+/// taking them modulo 32768 like all of its math keeps every back end total instead of stopping on them
+pub(crate) fn value(state: &State, raw: u16) -> u16 {
+    match raw {
+        0..=32767 => raw,
+        32768..=32775 => state.register[raw as usize - 32768],
+        _ => raw % 32768,
+    }
+}
 
-            if meta.debug {
-                println!("opcode 18: RETURN: {}", state.stack[state.stack.len() - 1] * 2);
-            }
+/// the byte offset of a memory address, registers can hold words past the 15-bit address space and those wrap
+pub(crate) fn address(word: u16) -> usize {
+    (word % 32768) as usize * 2
+}
 
-            if let Some(n) = state.stack.pop() {
-                state.ip = n as usize * 2;
-            } else {
-                // bad state?
-            }
-        }
-        19 => {
-            state.ip = state.ip + 2;
-            if meta.debug {
-                let a = read_argument(&state);
-                println!("opcode 19: PRINT: {}", state.program[state.ip]);
-                println!("{}", a as u8 as char);
-            } else {
-                let a = read_argument(&state);
-                // eprintln!("opcode 19: PRINT: {} {}", a as u8 as char, a);
-                print!("{}", a as u8 as char);
-                // eprint!("{}", program[ip] as char);
-            }
-            state.ip = state.ip + 2;
-        }
-        20 => {
-            if meta.debug {
-                println!("opcode 20: READ TO [A]");
-                println!(" A: STATE.REGISTER");
-            }
-            state.ip = state.ip + 2;
-            let a = write_argument(&state) as usize;
+/// the register an operand names as a destination, None for words past the eighth register
+fn register(raw: u16) -> Option<usize> {
+    let mut argument = raw;
+    if argument > 32767 {
+        argument %= 32768;
+    }
+    if argument > 7 {
+        return None;
+    }
+    Some(argument as usize)
+}
 
-            let res = read();
-            if res as char == '~' {
-                meta.debugging = true;
-                state.ip = state.ip - 2;
-            } else {
-                state.register[a] = res as u16;
-                state.ip = state.ip + 2;
-            }
-        }
-        21 => {
-            if meta.debug {
-                println!("opcode 21: NOOP");
-            }
-            state.ip = state.ip + 2;
-        }
-        c => {
-            println!(
-                "opcode {}: err unknown opcode at {} follows: {:x} {:x}",
-                c,
-                state.ip,
-                state.program[(state.ip + 1)],
-                state.program[(state.ip + 2)]
-            );
-            // println!("dumping program");
-            // if let Ok(_) = fs::write("./out", program) {
-            //     println!("dumped!");
-            // }
-            meta.debugging = true;
+/// write a register and tell the observer
+fn set<O: Observer<State> + ?Sized>(state: &mut State, observer: &mut O, raw: u16, value: u16) {
+    match register(raw) {
+        Some(a) => {
+            state.register[a] = value;
+            observer.on_register_write(a, value as u64);
         }
+        None => observer.on_dropped_write(raw as u64),
     }
 }
+
+/// move ip out of straight-line order and tell the observer
+fn jump<O: Observer<State> + ?Sized>(state: &mut State, observer: &mut O, to: usize) {
+    observer.on_jump(state.ip, to);
+    state.ip = to;
+}
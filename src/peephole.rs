@@ -0,0 +1,237 @@
+use crate::cache::Instruction;
+use crate::debug::{Meta, Resume};
+use crate::observer::Silent;
+use crate::opcode::{execute, value, Code};
+use crate::vm::State;
+
+/// most pushes or pops fused into one superinstruction
+const RUN: usize = 4;
+
+/// most words a superinstruction covers, a full run of pops and the ret after them
+pub(crate) const SPAN: usize = RUN * 2 + 1;
+
+/// opcodes that end up inside superinstructions, the ones the patterns in `Super::fuse` match; no wildcard so a new
+/// opcode has to be sorted into one side or the other
+fn fused(code: Code) -> bool {
+    match code {
+        Code::Push
+        | Code::Pop
+        | Code::Equals
+        | Code::GreaterThan
+        | Code::Jump
+        | Code::JumpIfTrue
+        | Code::JumpIfFalse
+        | Code::Add
+        | Code::Return => true,
+        Code::Halt
+        | Code::Set
+        | Code::Multiply
+        | Code::Modulo
+        | Code::And
+        | Code::Or
+        | Code::Not
+        | Code::ReadMemory
+        | Code::WriteMemory
+        | Code::Call
+        | Code::Out
+        | Code::In
+        | Code::Noop
+        | Code::Data => false,
+    }
+}
+
+/// what a superinstruction does, operands stay raw words like in an instruction
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    /// eq or gt into a register, then jt or jf on that same register
+    Branch {
+        compare: Code,
+        register: u8,
+        b: u16,
+        c: u16,
+        when: bool,
+        target: u16,
+    },
+    /// add into a register then jmp, the tail of a counting loop
+    AddJump {
+        register: u8,
+        b: u16,
+        c: u16,
+        target: u16,
+    },
+    /// pushes in a row, saving registers before a call
+    Push { values: [u16; RUN] },
+    /// pops in a row restoring registers, and the ret that often follows them
+    Pop { registers: [u8; RUN], ret: bool },
+}
+
+/// a run of instructions executed in one dispatch
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Super {
+    pub kind: Kind,
+    /// instructions fused
+    pub count: u8,
+    /// words covered, every instruction's operands included
+    pub len: u8,
+    /// the last fused instruction, what a step through them would have returned
+    pub last: Code,
+}
+
+/// the register an operand names, None for literals and the invalid words the executor warns about
+fn register(raw: u16) -> Option<u8> {
+    match raw {
+        32768..=32775 => Some((raw - 32768) as u8),
+        _ => None,
+    }
+}
+
+impl Super {
+    /// look for a fusable sequence starting at ip
+    pub fn fuse(program: &[u8], ip: usize) -> Option<Super> {
+        let first = Instruction::decode(program, ip);
+        let second = Instruction::decode(program, ip + first.len as usize * 2);
+        let pair = |kind| Super {
+            kind,
+            count: 2,
+            len: first.len + second.len,
+            last: second.code(),
+        };
+        match (first.code(), second.code()) {
+            (Code::Equals | Code::GreaterThan, Code::JumpIfTrue | Code::JumpIfFalse)
+                if first.args[0] == second.args[0] =>
+            {
+                Some(pair(Kind::Branch {
+                    compare: first.code(),
+                    register: register(first.args[0])?,
+                    b: first.args[1],
+                    c: first.args[2],
+                    when: second.code() == Code::JumpIfTrue,
+                    target: second.args[1],
+                }))
+            }
+            (Code::Add, Code::Jump) => Some(pair(Kind::AddJump {
+                register: register(first.args[0])?,
+                b: first.args[1],
+                c: first.args[2],
+                target: second.args[0],
+            })),
+            (Code::Push, Code::Push) => {
+                let mut values = [0; RUN];
+                let count = Super::run(program, ip, Code::Push, |n, instruction| {
+                    values[n] = instruction.args[0];
+                    true
+                });
+                Some(Super {
+                    kind: Kind::Push { values },
+                    count: count as u8,
+                    len: count as u8 * 2,
+                    last: Code::Push,
+                })
+            }
+            (Code::Pop, Code::Pop | Code::Return) => {
+                let mut registers = [0; RUN];
+                let count = Super::run(program, ip, Code::Pop, |n, instruction| match register(instruction.args[0]) {
+                    Some(register) => {
+                        registers[n] = register;
+                        true
+                    }
+                    None => false,
+                });
+                if count == 0 {
+                    return None;
+                }
+                let ret = Instruction::decode(program, ip + count * 4).code() == Code::Return;
+                if count == 1 && !ret {
+                    return None;
+                }
+                Some(Super {
+                    kind: Kind::Pop { registers, ret },
+                    count: (count + ret as usize) as u8,
+                    len: (count * 2 + ret as usize) as u8,
+                    last: if ret { Code::Return } else { Code::Pop },
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// how many instructions with this two word opcode follow each other from ip, up to RUN and while take agrees
+    fn run(program: &[u8], ip: usize, code: Code, mut take: impl FnMut(usize, &Instruction) -> bool) -> usize {
+        let mut count = 0;
+        while count < RUN {
+            let instruction = Instruction::decode(program, ip + count * 4);
+            if instruction.code() != code || !take(count, &instruction) {
+                break;
+            }
+            count += 1;
+        }
+        count
+    }
+}
+
+/// whether superinstructions can run without skipping anything the debugger or the tools watch per instruction
+pub fn usable(meta: &Meta) -> bool {
+    meta.observer.is_none()
+        && meta.resume == Resume::Continue
+        && meta.breakpoints.is_empty()
+        && meta.profile.is_none()
+        && meta.hooks.coverage.is_none()
+        && meta.hooks.smc.is_none()
+        && !fused(meta.hooks.break_op)
+}
+
+/// run the superinstruction at ip, or the plain instruction when nothing fused there, returns the last
+/// instruction run; None to interpret a step, when a run of pops would find the stack running dry
+pub fn run(state: &mut State, meta: &mut Meta) -> Option<Code> {
+    let Some(fused) = state.cache.superinstruction(&state.program, state.ip) else {
+        // step without its per instruction hooks, usable made sure none of them are on
+        meta.op_count += 1;
+        let instruction = state.cache.fetch(&state.program, state.ip);
+        execute(state, meta, &instruction, &mut Silent);
+        return Some(instruction.code());
+    };
+    let next = state.ip + fused.len as usize * 2;
+    match fused.kind {
+        Kind::Branch {
+            compare,
+            register,
+            b,
+            c,
+            when,
+            target,
+        } => {
+            let b = value(state, b);
+            let c = value(state, c);
+            let result = if compare == Code::Equals { b == c } else { b > c };
+            state.register[register as usize] = result as u16;
+            state.ip = if result == when { value(state, target) as usize * 2 } else { next };
+        }
+        Kind::AddJump { register, b, c, target } => {
+            state.register[register as usize] = ((value(state, b) as u32 + value(state, c) as u32) % 32768) as u16;
+            state.ip = value(state, target) as usize * 2;
+        }
+        Kind::Push { values } => {
+            for raw in &values[..fused.count as usize] {
+                let a = value(state, *raw);
+                state.stack.push(a);
+            }
+            state.ip = next;
+        }
+        Kind::Pop { registers, ret } => {
+            let pops = fused.count as usize - ret as usize;
+            if state.stack.len() < fused.count as usize {
+                return None; // let the interpreter deal with running dry one instruction at a time
+            }
+            for register in &registers[..pops] {
+                state.register[*register as usize] = state.stack.pop().unwrap();
+            }
+            state.ip = match ret {
+                true => state.stack.pop().unwrap() as usize * 2,
+                false => next,
+            };
+            state.unwind();
+        }
+    }
+    meta.op_count += fused.count as usize;
+    Some(fused.last)
+}
@@ -0,0 +1,374 @@
+use crate::console::Io;
+use crate::console::Terminal;
+use crate::debug::Meta;
+use crate::machine::Machine;
+use crate::opcode::step;
+use crate::vm::BoxResult;
+use crate::vm::State;
+
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::mem;
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// instructions executed between checks for new requests
+pub const POLL_INTERVAL: usize = 1 << 10;
+
+/// 32768 words of 16 bits, addressed by byte like the ip is
+const MEMORY: usize = 0x10000;
+
+/// instructions one step request runs at most, requests wait meanwhile so anything longer is what resume is for
+const MOST_STEPS: usize = 1 << 20;
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const SERVER_ERROR: i64 = -32000;
+
+type Clients = Arc<Mutex<HashMap<usize, UnixStream>>>;
+type Fault = (i64, String);
+
+struct Request {
+    client: usize,
+    line: String,
+}
+
+/// JSON-RPC 2.0 over a Unix socket, one message per line in both directions
+pub struct Rpc {
+    path: String,
+    requests: Receiver<Request>,
+    clients: Clients,
+    paused: bool,
+}
+
+fn send(stream: &mut UnixStream, message: &Value) -> bool {
+    let mut line = message.to_string();
+    line.push('\n');
+    stream.write_all(line.as_bytes()).is_ok()
+}
+
+/// send a message to every client, forgetting the ones that went away
+fn broadcast(clients: &Clients, message: &Value) {
+    let mut clients = clients.lock().expect("a client thread panicked");
+    clients.retain(|_, stream| send(stream, message));
+}
+
+fn notification(method: &str, params: Value) -> Value {
+    json!({ "jsonrpc": "2.0", "method": method, "params": params })
+}
+
+/// hands guest io through and sends finished lines of output to the clients
+struct Tee {
+    inner: Box<dyn Io>,
+    clients: Clients,
+    line: Vec<u8>,
+}
+
+impl Io for Tee {
+    fn read(&mut self) -> Option<u8> {
+        self.flush(); // a prompt without a newline should still show up
+        self.inner.read()
+    }
+
+    fn write(&mut self, byte: u8) {
+        self.inner.write(byte);
+        self.line.push(byte);
+        if byte == b'\n' {
+            self.flush();
+        }
+    }
+
+    fn flush(&mut self) {
+        self.inner.flush();
+        if !self.line.is_empty() {
+            let text = String::from_utf8_lossy(&self.line).into_owned();
+            broadcast(&self.clients, &notification("output", json!({ "text": text })));
+            self.line.clear();
+        }
+    }
+}
+
+fn accept(listener: UnixListener, clients: Clients, requests: Sender<Request>) {
+    for (client, stream) in listener.incoming().enumerate() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(_) => continue,
+        };
+        if let Ok(writer) = stream.try_clone() {
+            clients.lock().expect("a client thread panicked").insert(client, writer);
+        }
+        let requests = requests.clone();
+        thread::spawn(move || {
+            for line in BufReader::new(stream).lines() {
+                let line = match line {
+                    Ok(line) => line,
+                    Err(_) => break,
+                };
+                if requests.send(Request { client, line }).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+}
+
+fn number(params: &Value, name: &str) -> Result<usize, Fault> {
+    params
+        .get(name)
+        .and_then(Value::as_u64)
+        .map(|value| value as usize)
+        .ok_or((INVALID_PARAMS, format!("expected a number {}", name)))
+}
+
+fn word(params: &Value, name: &str) -> Result<u16, Fault> {
+    let value = number(params, name)?;
+    if value > u16::MAX as usize {
+        return Err((INVALID_PARAMS, format!("{} does not fit in 16 bits", name)));
+    }
+    Ok(value as u16)
+}
+
+fn text<'a>(params: &'a Value, name: &str) -> Result<&'a str, Fault> {
+    params
+        .get(name)
+        .and_then(Value::as_str)
+        .ok_or((INVALID_PARAMS, format!("expected a string {}", name)))
+}
+
+fn failed<E: std::fmt::Display>(error: E) -> Fault {
+    (SERVER_ERROR, error.to_string())
+}
+
+impl Rpc {
+    /// bind the socket and route guest output through it, clients may connect at any time
+    pub fn listen(path: &str, meta: &mut Meta) -> BoxResult<Rpc> {
+        if let Ok(metadata) = fs::metadata(path) {
+            if metadata.file_type().is_socket() {
+                fs::remove_file(path)?; // left behind by an earlier run
+            }
+        }
+        let listener = UnixListener::bind(path)?;
+        let (sender, requests) = channel();
+        let clients: Clients = Arc::new(Mutex::new(HashMap::new()));
+
+        let accepted = clients.clone();
+        thread::spawn(move || accept(listener, accepted, sender));
+
+        let inner = mem::replace(&mut meta.io, Box::new(Terminal::new()));
+        meta.io = Box::new(Tee {
+            inner,
+            clients: clients.clone(),
+            line: Vec::new(),
+        });
+
+        println!("rpc: listening on {}", path);
+        Ok(Rpc {
+            path: String::from(path),
+            requests,
+            clients,
+            paused: false,
+        })
+    }
+
+    pub fn notify(&self, method: &str, params: Value) {
+        broadcast(&self.clients, &notification(method, params));
+    }
+
+    /// pause the VM and tell the clients why
+    pub fn stop(&mut self, state: &mut State, meta: &mut Meta, reason: &str) -> BoxResult<()> {
+        self.paused = true;
+        meta.io.flush();
+        self.notify("stopped", json!({ "reason": reason, "ip": state.ip }));
+        self.service(state, meta)
+    }
+
+    /// answer waiting requests, and keep answering them while a client has the VM paused
+    pub fn service(&mut self, state: &mut State, meta: &mut Meta) -> BoxResult<()> {
+        loop {
+            let request = if self.paused {
+                match self.requests.recv() {
+                    Ok(request) => request,
+                    Err(_) => return Ok(()),
+                }
+            } else {
+                match self.requests.try_recv() {
+                    Ok(request) => request,
+                    Err(_) => return Ok(()),
+                }
+            };
+            self.answer(state, meta, request);
+            if meta.halt {
+                return Ok(());
+            }
+        }
+    }
+
+    fn answer(&mut self, state: &mut State, meta: &mut Meta, request: Request) {
+        let (id, result) = match serde_json::from_str::<Value>(&request.line) {
+            Ok(message) => {
+                let id = message.get("id").cloned();
+                let result = match message.get("method").and_then(Value::as_str) {
+                    Some(method) => {
+                        let params = message.get("params").cloned().unwrap_or(Value::Null);
+                        self.call(state, meta, method, &params)
+                    }
+                    None => Err((INVALID_REQUEST, String::from("no method"))),
+                };
+                (id, result)
+            }
+            Err(error) => (Some(Value::Null), Err((PARSE_ERROR, error.to_string()))),
+        };
+
+        // notifications, requests without an id, get no response
+        let id = match id {
+            Some(id) => id,
+            None => return,
+        };
+        let response = match result {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err((code, message)) => json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } }),
+        };
+        let mut clients = self.clients.lock().expect("a client thread panicked");
+        if let Some(stream) = clients.get_mut(&request.client) {
+            if !send(stream, &response) {
+                clients.remove(&request.client);
+            }
+        }
+    }
+
+    fn call(&mut self, state: &mut State, meta: &mut Meta, method: &str, params: &Value) -> Result<Value, Fault> {
+        match method {
+            "status" => Ok(json!({
+                "paused": self.paused,
+                "halted": meta.halt,
+                "ip": state.ip,
+                "instructions": meta.op_count,
+            })),
+            "pause" => {
+                self.paused = true;
+                self.notify("stopped", json!({ "reason": "pause", "ip": state.ip }));
+                Ok(json!({ "ip": state.ip }))
+            }
+            "resume" => {
+                self.paused = false;
+                Ok(Value::Null)
+            }
+            "step" => {
+                let count = if params.get("count").is_some() { number(params, "count")? } else { 1 };
+                if count > MOST_STEPS {
+                    return Err((INVALID_PARAMS, format!("at most {} steps at a time", MOST_STEPS)));
+                }
+                let mut reason = "step";
+                for _ in 0..count {
+                    step(state, meta);
+                    if meta.halt {
+                        break;
+                    }
+                    if meta.breakpoints.contains(&state.ip) {
+                        reason = "breakpoint";
+                        break;
+                    }
+                }
+                self.paused = true;
+                meta.io.flush();
+                self.notify("stopped", json!({ "reason": reason, "ip": state.ip }));
+                Ok(json!({ "ip": state.ip }))
+            }
+            "readRegisters" => Ok(json!({ "registers": state.register, "ip": state.ip })),
+            "writeRegister" => {
+                let register = number(params, "register")?;
+                if register > 7 {
+                    return Err((INVALID_PARAMS, String::from("registers are 0 to 7")));
+                }
+                state.register[register] = word(params, "value")?;
+                Ok(Value::Null)
+            }
+            "writeIp" => {
+                state.ip = number(params, "ip")?;
+                Ok(Value::Null)
+            }
+            "readMemory" => {
+                let address = number(params, "address")?;
+                let count = number(params, "count")?;
+                let end = count
+                    .checked_mul(2)
+                    .and_then(|bytes| bytes.checked_add(address))
+                    .ok_or((INVALID_PARAMS, String::from("address and count overflow")))?
+                    .min(MEMORY);
+                // memory past the loaded image reads as zero
+                let byte = |i: usize| state.program.get(i).copied().unwrap_or(0) as u16;
+                let words: Vec<u16> = (address..end).step_by(2).map(|i| byte(i + 1) << 8 | byte(i)).collect();
+                Ok(json!({ "address": address, "words": words }))
+            }
+            "writeMemory" => {
+                let address = number(params, "address")?;
+                let words = params
+                    .get("words")
+                    .and_then(Value::as_array)
+                    .ok_or((INVALID_PARAMS, String::from("expected an array words")))?;
+                if !matches!(address.checked_add(words.len() * 2), Some(end) if end <= MEMORY) {
+                    return Err((INVALID_PARAMS, String::from("write past the end of memory")));
+                }
+                // check every word before writing any, a bad request leaves memory alone
+                let words = words
+                    .iter()
+                    .map(|value| match value.as_u64() {
+                        Some(value) if value <= u16::MAX as u64 => Ok(value as u16),
+                        _ => Err((INVALID_PARAMS, String::from("words must be numbers that fit in 16 bits"))),
+                    })
+                    .collect::<Result<Vec<u16>, Fault>>()?;
+                let bytes: Vec<u8> = words.iter().flat_map(|value| value.to_le_bytes()).collect();
+                state.write_memory(&mut meta.hooks, address, &bytes);
+                Ok(Value::Null)
+            }
+            "readStack" => Ok(json!({ "stack": state.stack })),
+            "writeStack" => {
+                let index = number(params, "index")?;
+                let value = word(params, "value")?;
+                match state.stack.get_mut(index) {
+                    Some(slot) => *slot = value,
+                    None => return Err((INVALID_PARAMS, format!("the stack has {} slots", state.stack.len()))),
+                }
+                Ok(Value::Null)
+            }
+            "setBreakpoint" => {
+                let address = number(params, "address")?;
+                if !meta.breakpoints.contains(&address) {
+                    meta.breakpoints.push(address);
+                }
+                Ok(json!({ "breakpoints": meta.breakpoints }))
+            }
+            "clearBreakpoint" => {
+                let address = number(params, "address")?;
+                meta.breakpoints.retain(|breakpoint| *breakpoint != address);
+                Ok(json!({ "breakpoints": meta.breakpoints }))
+            }
+            "breakpoints" => Ok(json!({ "breakpoints": meta.breakpoints })),
+            "saveSnapshot" => {
+                let path = text(params, "path")?;
+                fs::write(path, State::save(state)).map_err(failed)?;
+                Ok(Value::Null)
+            }
+            "loadSnapshot" => {
+                let path = text(params, "path")?;
+                let save = fs::read(path).map_err(failed)?;
+                *state = State::recover(save).map_err(failed)?;
+                Ok(json!({ "ip": state.ip }))
+            }
+            _ => Err((METHOD_NOT_FOUND, format!("no method {}", method))),
+        }
+    }
+}
+
+impl Drop for Rpc {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
@@ -0,0 +1,49 @@
+use crate::vm::BoxResult;
+use nix::libc;
+use nix::sys::signal::{sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// a second interrupt this soon after the first one exits instead of pausing
+const WINDOW_MS: u64 = 1000;
+
+static PENDING: AtomicBool = AtomicBool::new(false);
+static LAST: AtomicU64 = AtomicU64::new(0);
+
+fn millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+extern "C" fn handle_interrupt(_: libc::c_int) {
+    let now = millis();
+    let last = LAST.swap(now, Ordering::SeqCst);
+    if now.saturating_sub(last) < WINDOW_MS {
+        // only async signal safe calls in here, so no flushing
+        unsafe { libc::_exit(130) };
+    }
+    PENDING.store(true, Ordering::SeqCst);
+}
+
+/// pause the VM on SIGINT, and on SIGTSTP as well when asked to
+pub fn install(tstp: bool) -> BoxResult<()> {
+    // no SA_RESTART, a blocked read of guest input has to come back to us
+    let action = SigAction::new(SigHandler::Handler(handle_interrupt), SaFlags::empty(), SigSet::empty());
+    unsafe { sigaction(Signal::SIGINT, &action) }?;
+    if tstp {
+        unsafe { sigaction(Signal::SIGTSTP, &action) }?;
+    }
+    Ok(())
+}
+
+/// true once per interrupt, the run loop polls this between instructions
+pub fn interrupted() -> bool {
+    PENDING.swap(false, Ordering::SeqCst)
+}
+
+/// whether an interrupt is waiting for the run loop, without taking it
+pub fn pending() -> bool {
+    PENDING.load(Ordering::SeqCst)
+}
@@ -0,0 +1,459 @@
+use crate::console::Pipe;
+use crate::console::Piped;
+use crate::debug::Meta;
+use crate::debug::Reason;
+use crate::debug::Resume;
+use crate::opcode::disassemble;
+use crate::opcode::length;
+use crate::opcode::parse;
+use crate::opcode::step;
+use crate::opcode::Code;
+use crate::vm::BoxResult;
+use crate::vm::State;
+
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+use std::cell::RefCell;
+use std::mem;
+use std::rc::Rc;
+use std::time::Duration;
+
+/// instructions run between redraws while the guest is running
+const BATCH: usize = 1 << 15;
+
+/// how long a paused screen waits for a key before drawing again
+const TICK: Duration = Duration::from_millis(100);
+
+/// bytes of guest output kept for the console pane
+const SCROLLBACK: usize = 1 << 16;
+
+/// words per row of the memory pane
+const ROW: usize = 8;
+
+const KEYS: &str = "s step  n next  f finish  c continue  p pause  b break  i input  g goto  PgUp/PgDn memory  q quit";
+
+#[derive(PartialEq)]
+enum Mode {
+    Normal,
+    /// typing a line of guest input
+    Input,
+    /// typing an address for the memory pane
+    Goto,
+}
+
+/// the full screen view and what keys do to it, drawn on whatever ratatui backend it is given
+pub struct Screen {
+    pipe: Rc<RefCell<Pipe>>,
+    console: Vec<u8>,
+    mode: Mode,
+    /// what is being typed in input or goto mode
+    line: String,
+    running: bool,
+    /// stopped on `in` with nothing to read, entering a line carries on
+    waiting: bool,
+    /// selected address in the disassembly pane
+    cursor: usize,
+    /// first address of the memory pane
+    memory: usize,
+    /// registers as they were when the guest last resumed
+    previous: [u16; 8],
+    status: String,
+    /// something printed over the screen, draw all of it again
+    dirty: bool,
+    quit: bool,
+}
+
+fn word(state: &State, address: usize) -> u16 {
+    let byte = |i: usize| state.program.get(i).copied().unwrap_or(0) as u16;
+    byte(address + 1) << 8 | byte(address)
+}
+
+/// instruction addresses around an anchor, decoding up to it and from it separately
+fn around(state: &State, anchor: usize, before: usize, total: usize) -> Vec<usize> {
+    let mut starts = Vec::new();
+    let mut i = 0;
+    while i < anchor.min(state.program.len()) {
+        starts.push(i);
+        i += length(&state.program, i) * 2;
+    }
+    let skip = starts.len().saturating_sub(before);
+    let mut addresses: Vec<usize> = starts.split_off(skip);
+    let mut i = anchor;
+    while addresses.len() < total && i < state.program.len() {
+        addresses.push(i);
+        i += length(&state.program, i) * 2;
+    }
+    addresses
+}
+
+fn block(title: &str) -> Block<'_> {
+    Block::bordered().title(title)
+}
+
+impl Screen {
+    /// paused at the machine's ip, guest io goes through pipe
+    pub fn new(state: &State, pipe: Rc<RefCell<Pipe>>) -> Screen {
+        Screen {
+            pipe,
+            console: Vec::new(),
+            mode: Mode::Normal,
+            line: String::new(),
+            running: false,
+            waiting: false,
+            cursor: state.ip,
+            memory: 0,
+            previous: state.register,
+            status: String::from("paused"),
+            dirty: false,
+            quit: false,
+        }
+    }
+
+    fn resume(&mut self, state: &State, meta: &mut Meta, resume: Resume) {
+        if meta.halt {
+            self.status = String::from("halted, q quits");
+            return;
+        }
+        self.previous = state.register;
+        meta.resume = resume;
+        self.running = true;
+        self.status = String::from("running");
+    }
+
+    fn stop(&mut self, state: &State, status: &str) {
+        self.running = false;
+        self.cursor = state.ip;
+        self.status = String::from(status);
+    }
+
+    /// run a batch of instructions, stopping early for anything the user should see
+    pub fn run(&mut self, state: &mut State, meta: &mut Meta) {
+        for _ in 0..BATCH {
+            step(state, meta);
+            if meta.halt {
+                self.dirty = true; // halt prints the final state over the screen
+                self.stop(state, "halted, q quits");
+                return;
+            }
+            if meta.debugging {
+                meta.debugging = false;
+                meta.resume = Resume::Continue;
+                match meta.reason.take() {
+                    Some(Reason::UnknownOpcode) => self.stop(state, "unknown opcode"),
+                    Some(Reason::DivideByZero) => self.stop(state, "mod by zero"),
+                    Some(Reason::Input) => {
+                        self.stop(state, "waiting for input");
+                        self.waiting = true;
+                        self.mode = Mode::Input;
+                    }
+                    Some(Reason::CodeWrite) => self.stop(state, "write into code"),
+                    None => self.stop(state, "stopped"),
+                }
+                return;
+            }
+            if meta.stop(state) {
+                self.stop(state, "stopped");
+                return;
+            }
+            if meta.breakpoints.contains(&state.ip) {
+                meta.resume = Resume::Continue;
+                self.stop(state, "breakpoint");
+                return;
+            }
+        }
+    }
+
+    pub fn key(&mut self, state: &mut State, meta: &mut Meta, key: KeyEvent) {
+        if key.kind != KeyEventKind::Press {
+            return;
+        }
+        if key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL) {
+            if self.running {
+                self.stop(state, "paused");
+            } else {
+                self.quit = true;
+            }
+            return;
+        }
+        match self.mode {
+            Mode::Normal => self.command(state, meta, key.code),
+            Mode::Input | Mode::Goto => match key.code {
+                KeyCode::Char(c) => self.line.push(c),
+                KeyCode::Backspace => {
+                    self.line.pop();
+                }
+                KeyCode::Esc => {
+                    self.line.clear();
+                    self.mode = Mode::Normal;
+                }
+                KeyCode::Enter => {
+                    let line = mem::take(&mut self.line);
+                    if self.mode == Mode::Input {
+                        self.enter(state, meta, &line);
+                    } else {
+                        match meta.symbols.resolve(line.trim()) {
+                            Ok(address) => {
+                                self.memory = address & !1;
+                                self.cursor = address & !1;
+                                self.status = format!("at {}", meta.symbols.describe(address));
+                            }
+                            Err(_) => self.status = format!("not an address or symbol: {}", line),
+                        }
+                    }
+                    self.mode = Mode::Normal;
+                }
+                _ => {}
+            },
+        }
+    }
+
+    /// hand a typed line to the guest
+    fn enter(&mut self, state: &State, meta: &mut Meta, line: &str) {
+        let mut pipe = self.pipe.borrow_mut();
+        pipe.input.extend(line.bytes());
+        pipe.input.push_back(b'\n');
+        drop(pipe);
+        // the guest does not echo, so show what was typed
+        self.console.extend(line.bytes());
+        self.console.push(b'\n');
+        if self.waiting {
+            self.waiting = false;
+            self.resume(state, meta, Resume::Continue);
+        }
+    }
+
+    fn command(&mut self, state: &mut State, meta: &mut Meta, code: KeyCode) {
+        match code {
+            KeyCode::Char('q') => self.quit = true,
+            KeyCode::Char('s') => self.resume(state, meta, Resume::Step(1)),
+            KeyCode::Char('n') => {
+                let resume = match parse(&state.program, &state.ip) {
+                    Code::Call => Resume::Return(state.frames.len()),
+                    _ => Resume::Step(1),
+                };
+                self.resume(state, meta, resume);
+            }
+            KeyCode::Char('f') => match state.frames.len() {
+                0 => self.status = String::from("not inside a call"),
+                depth => self.resume(state, meta, Resume::Return(depth - 1)),
+            },
+            KeyCode::Char('c') => self.resume(state, meta, Resume::Continue),
+            KeyCode::Char('p') | KeyCode::Esc => {
+                meta.resume = Resume::Continue;
+                self.stop(state, "paused");
+            }
+            KeyCode::Char('b') => {
+                if let Some(i) = meta.breakpoints.iter().position(|address| *address == self.cursor) {
+                    meta.breakpoints.remove(i);
+                    self.status = format!("cleared breakpoint at {}", meta.symbols.describe(self.cursor));
+                } else {
+                    meta.breakpoints.push(self.cursor);
+                    self.status = format!("breakpoint at {}", meta.symbols.describe(self.cursor));
+                }
+            }
+            KeyCode::Char('i') => self.mode = Mode::Input,
+            KeyCode::Char('g') => self.mode = Mode::Goto,
+            KeyCode::Down => {
+                let next = self.cursor + length(&state.program, self.cursor) * 2;
+                if next < state.program.len() {
+                    self.cursor = next;
+                }
+            }
+            KeyCode::Up => {
+                if let Some(previous) = around(state, self.cursor, 1, 1).first() {
+                    if *previous < self.cursor {
+                        self.cursor = *previous;
+                    }
+                }
+            }
+            KeyCode::PageDown => self.memory = (self.memory + ROW * 2 * 4).min(0xFFFF & !1),
+            KeyCode::PageUp => self.memory = self.memory.saturating_sub(ROW * 2 * 4),
+            _ => {}
+        }
+    }
+
+    /// move whatever the guest printed into the console pane
+    fn drain(&mut self) {
+        let output = mem::take(&mut self.pipe.borrow_mut().output);
+        self.console.extend(output);
+        if self.console.len() > SCROLLBACK {
+            let cut = self.console.len() - SCROLLBACK / 2;
+            self.console.drain(..cut);
+        }
+    }
+
+    pub fn draw(&self, frame: &mut Frame, state: &State, meta: &Meta) {
+        let [main, status] = Layout::vertical([Constraint::Min(0), Constraint::Length(1)]).areas(frame.area());
+        let [left, right] = Layout::horizontal([Constraint::Percentage(55), Constraint::Percentage(45)]).areas(main);
+        let [code, console] = Layout::vertical([Constraint::Percentage(60), Constraint::Percentage(40)]).areas(left);
+        let [registers, stack, memory] =
+            Layout::vertical([Constraint::Length(11), Constraint::Percentage(50), Constraint::Min(0)]).areas(right);
+
+        self.disassembly(frame, code, state, meta);
+        self.console(frame, console);
+        self.registers(frame, registers, state);
+        self.stack(frame, stack, state, meta);
+        self.memory(frame, memory, state);
+
+        let text = match self.mode {
+            Mode::Goto => format!("goto address or symbol: {}_", self.line),
+            _ => format!("{} | ip {:#06x} | {} instructions | {}", self.status, state.ip, meta.op_count, KEYS),
+        };
+        frame.render_widget(Paragraph::new(text).style(Style::default().add_modifier(Modifier::REVERSED)), status);
+    }
+
+    fn disassembly(&self, frame: &mut Frame, area: Rect, state: &State, meta: &Meta) {
+        let height = area.height.saturating_sub(2) as usize;
+        let anchor = if self.running { state.ip } else { self.cursor };
+        let lines: Vec<Line> = around(state, anchor, height / 3, height)
+            .into_iter()
+            .map(|address| {
+                let marker = match (meta.breakpoints.contains(&address), address == state.ip) {
+                    (true, true) => "●▶",
+                    (true, false) => "● ",
+                    (false, true) => " ▶",
+                    (false, false) => "  ",
+                };
+                let mut spans = vec![
+                    Span::styled(marker, Style::default().fg(Color::Red)),
+                    Span::raw(format!("{:#06x}  ", address)),
+                    Span::raw(disassemble(&state.program, address).0),
+                ];
+                if let Some(name) = meta.symbols.get(address) {
+                    spans.push(Span::styled(format!("  <{}>", name), Style::default().fg(Color::DarkGray)));
+                }
+                let mut line = Line::from(spans);
+                if address == state.ip {
+                    line = line.style(Style::default().fg(Color::Green));
+                }
+                if address == self.cursor && !self.running {
+                    line = line.style(Style::default().add_modifier(Modifier::REVERSED));
+                }
+                line
+            })
+            .collect();
+        frame.render_widget(Paragraph::new(lines).block(block("disassembly")), area);
+    }
+
+    fn console(&self, frame: &mut Frame, area: Rect) {
+        let height = area.height.saturating_sub(2) as usize;
+        let text = String::from_utf8_lossy(&self.console);
+        let mut lines: Vec<Line> = text.split('\n').map(|line| Line::raw(line.to_owned())).collect();
+        if self.mode == Mode::Input {
+            if lines.last().is_some_and(|line| line.width() == 0) {
+                lines.pop();
+            }
+            lines.push(Line::styled(format!("> {}_", self.line), Style::default().fg(Color::Cyan)));
+        }
+        let skip = lines.len().saturating_sub(height);
+        let title = if self.mode == Mode::Input { "console (enter sends, esc cancels)" } else { "console" };
+        frame.render_widget(Paragraph::new(lines.split_off(skip)).block(block(title)), area);
+    }
+
+    fn registers(&self, frame: &mut Frame, area: Rect, state: &State) {
+        let mut lines: Vec<Line> = (0..8)
+            .map(|i| {
+                let value = state.register[i];
+                let text = format!("r{} = {:5} {:#06x}", i, value, value);
+                if value != self.previous[i] {
+                    Line::styled(text, Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD))
+                } else {
+                    Line::raw(text)
+                }
+            })
+            .collect();
+        lines.push(Line::raw(format!("ip = {:#06x}", state.ip)));
+        frame.render_widget(Paragraph::new(lines).block(block("registers")), area);
+    }
+
+    fn stack(&self, frame: &mut Frame, area: Rect, state: &State, meta: &Meta) {
+        let height = area.height.saturating_sub(2) as usize;
+        // the slot right below each frame's depth holds its return address
+        let returns: Vec<usize> = state.frames.iter().map(|frame| frame.depth - 1).collect();
+        let lines: Vec<Line> = state
+            .stack
+            .iter()
+            .enumerate()
+            .rev()
+            .take(height)
+            .map(|(i, value)| {
+                if returns.contains(&i) {
+                    let target = *value as usize * 2;
+                    Line::styled(
+                        format!("<{}> = {} (return to {})", i, value, meta.symbols.describe(target)),
+                        Style::default().fg(Color::DarkGray),
+                    )
+                } else {
+                    Line::raw(format!("<{}> = {}", i, value))
+                }
+            })
+            .collect();
+        let title = format!("stack ({})", state.stack.len());
+        frame.render_widget(Paragraph::new(lines).block(block(&title)), area);
+    }
+
+    fn memory(&self, frame: &mut Frame, area: Rect, state: &State) {
+        let height = area.height.saturating_sub(2) as usize;
+        let lines: Vec<Line> = (0..height)
+            .map(|row| self.memory + row * ROW * 2)
+            .take_while(|address| *address < 0x10000)
+            .map(|address| {
+                let words: Vec<u16> = (0..ROW).map(|n| word(state, address + n * 2)).collect();
+                let hex: Vec<String> = words.iter().map(|word| format!("{:04x}", word)).collect();
+                let ascii: String = words
+                    .iter()
+                    .map(|word| if (0x20..0x7F).contains(word) { *word as u8 as char } else { '.' })
+                    .collect();
+                Line::raw(format!("{:#06x}: {} |{}|", address, hex.join(" "), ascii))
+            })
+            .collect();
+        frame.render_widget(Paragraph::new(lines).block(block("memory")), area);
+    }
+}
+
+fn session(terminal: &mut DefaultTerminal, screen: &mut Screen, state: &mut State, meta: &mut Meta) -> BoxResult<()> {
+    while !screen.quit {
+        screen.drain();
+        if screen.dirty {
+            terminal.clear()?;
+            screen.dirty = false;
+        }
+        terminal.draw(|frame| screen.draw(frame, state, meta))?;
+        if screen.running {
+            screen.run(state, meta);
+            while event::poll(Duration::ZERO)? {
+                if let Event::Key(key) = event::read()? {
+                    screen.key(state, meta, key);
+                }
+            }
+        } else if event::poll(TICK)? {
+            if let Event::Key(key) = event::read()? {
+                screen.key(state, meta, key);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// take over the terminal and drive the VM from a full screen view until the user quits
+pub fn run(state: &mut State, meta: &mut Meta) -> BoxResult<()> {
+    let pipe = Rc::new(RefCell::new(Pipe::default()));
+    let terminal_io = mem::replace(&mut meta.io, Box::new(Piped(pipe.clone())));
+    let observer = mem::take(&mut meta.observer); // the trace would print over the screen
+    let log = meta.hooks.smc.as_mut().map(|smc| mem::replace(&mut smc.log, false));
+
+    let mut screen = Screen::new(state, pipe);
+    let mut terminal = ratatui::init();
+    let result = session(&mut terminal, &mut screen, state, meta);
+    ratatui::restore();
+    meta.io = terminal_io;
+    meta.observer = observer;
+    if let (Some(smc), Some(log)) = (&mut meta.hooks.smc, log) {
+        smc.log = log;
+    }
+    result
+}
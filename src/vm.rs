@@ -1,9 +1,27 @@
 use std::error::Error;
+use std::convert::TryFrom;
 use std::fmt;
 use crate::util::to_u16;
+use crate::cache::{Cache, Instruction};
+use crate::debug::coverage::Coverage;
+use crate::debug::smc::Smc;
+use crate::debug::Meta;
+use crate::machine::Machine;
+use crate::opcode::{disassemble, lookup, step, Code};
+
+pub use crate::machine::Frame;
 
 pub type BoxResult<T> = Result<T,Box<dyn Error>>;
 
+/// marker, stack size, ip and the registers in front of a save
+const HEADER: usize = 21;
+
+/// bytes a legacy save is read from, its stack runs furthest
+const LEGACY_HEADER: usize = 215;
+
+/// 32768 words, addressed by byte like the ip is
+const MEMORY: usize = 0x10000;
+
 struct RecoveryError {
     details: String
 }
@@ -32,27 +50,45 @@ impl Error for RecoveryError {
     }
 }
 
+
 pub struct State {
     pub program: Vec<u8>,
     pub register:[u16;8],
     pub ip: usize,
     pub stack: Vec<u16>,
-    pub debug: bool,
+    pub frames: Vec<Frame>,
+    /// decoded instructions, anything writing program memory has to invalidate what it touches
+    pub cache: Cache,
 }
 
 impl State {
     pub fn new(program: Vec<u8>) -> State {
-        return State {
+        State {
             program,
             register: [0;8],
             ip: 0,
             stack: Vec::new(),
-            debug: false,
+            frames: Vec::new(),
+            cache: Cache::new(),
+        }
+    }
+
+    /// drop every frame whose return address is no longer on the stack
+    pub fn unwind(&mut self) {
+        while let Some(frame) = self.frames.last() {
+            if frame.depth <= self.stack.len() {
+                break;
+            }
+            self.frames.pop();
         }
     }
 
     pub fn recover(mut save: Vec<u8>) -> BoxResult<State> {
-        match save[0] {
+        let first = match save.first() {
+            Some(byte) => *byte,
+            None => return Err(RecoveryError::new(String::from("This file is empty"))),
+        };
+        match first {
             0x00..=0x15 => { // regular program?
                 return Ok(State::new(save));
             },
@@ -66,15 +102,21 @@ impl State {
                 return Err(RecoveryError::new(format!("This does not seem to be a valid file, invalid starting code {}", op)));
             }
         }
+        if save.len() < HEADER {
+            return Err(RecoveryError::new(format!("This save is {} bytes, too short for its {} byte header", save.len(), HEADER)));
+        }
         println!("recovering");
-        let mut header: Vec<u8> = save.drain(0..21).collect();
+        let mut header: Vec<u8> = save.drain(0..HEADER).collect();
 
 
         header.remove(0); // remove 0x17
 
         let sp = to_u16(header[0], header[1]) as usize;
         header.drain(0..2);
-        let mut stack: Vec<u8> = save.drain(0..(sp * 2)).collect();
+        if save.len() < sp * 2 {
+            return Err(RecoveryError::new(format!("This save claims {} stack slots but only has {} bytes left", sp, save.len())));
+        }
+        let stack: Vec<u8> = save.drain(0..(sp * 2)).collect();
         let mut state = State::new(save);
         state.ip = to_u16(header[0], header[1]) as usize;
         header.drain(0..2);
@@ -95,9 +137,8 @@ impl State {
     }
 
     pub fn save(state: &State) -> Vec<u8> {
-        let mut save:Vec<u8> = Vec::new();
+        let mut save:Vec<u8> = vec![0x17]; // if 23 is encountered, we know its a save file, 22 is legacy
 
-        save.push(0x17); // if 23 is encountered, we know its a save file, 22 is legacy
         save.push((state.stack.len() >> 8)  as u8);
         save.push(state.stack.len() as u8);
         save.push((state.ip >> 8)  as u8);
@@ -111,11 +152,158 @@ impl State {
             save.push(state.stack[i] as u8);
         }
         save.append(&mut state.program.clone());
-        return save;
+        save
+    }
+}
+
+/// the tools that watch the Synacor VM from inside `execute`, next to the debugger's state in `Meta::hooks`
+pub struct Hooks {
+    /// stop after every instruction with this opcode
+    pub break_op: Code,
+    pub coverage: Option<Coverage>,
+    pub smc: Option<Smc>,
+}
+
+impl Default for Hooks {
+    fn default() -> Hooks {
+        Hooks {
+            break_op: Code::Halt, // by default break on Halt
+            coverage: None,
+            smc: None,
+        }
+    }
+}
+
+impl Machine for State {
+    type Hooks = Hooks;
+
+    fn ip(&self) -> usize {
+        self.ip
+    }
+
+    fn set_ip(&mut self, ip: usize) {
+        self.ip = ip;
+    }
+
+    fn registers(&self) -> Vec<u64> {
+        self.register.iter().map(|value| *value as u64).collect()
+    }
+
+    fn set_register(&mut self, register: usize, value: u64) -> bool {
+        match (self.register.get_mut(register), u16::try_from(value)) {
+            (Some(slot), Ok(value)) => {
+                *slot = value;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn memory(&self) -> &[u8] {
+        &self.program
+    }
+
+    /// grows memory like wmem does and drops anything past the last word; coverage and `--smc` see one write per
+    /// word touched, made from wherever the machine stopped
+    fn write_memory(&mut self, hooks: &mut Hooks, address: usize, bytes: &[u8]) {
+        let end = address.saturating_add(bytes.len()).min(MEMORY);
+        if address >= end {
+            return;
+        }
+        // whole words, an odd address or length shares a word with bytes that stay
+        let first = address & !1;
+        let last = (end + 1) & !1;
+        let mut words: Vec<u8> = (first..last).map(|i| self.program.get(i).copied().unwrap_or(0)).collect();
+        words[address - first..end - first].copy_from_slice(&bytes[..end - address]);
+        for (n, word) in words.chunks_exact(2).enumerate() {
+            let at = first + n * 2;
+            if let Some(coverage) = &mut hooks.coverage {
+                coverage.write(at);
+            }
+            if let Some(smc) = &mut hooks.smc {
+                smc.write(self, self.ip, at, u16::from_le_bytes([word[0], word[1]]));
+            }
+        }
+        if self.program.len() < last {
+            self.program.resize(last, 0);
+        }
+        self.program[first..last].copy_from_slice(&words);
+        self.cache.invalidate_range(first, last - first);
+    }
+
+    fn stack(&self) -> Vec<u64> {
+        self.stack.iter().map(|value| *value as u64).collect()
+    }
+
+    fn set_stack(&mut self, slot: usize, value: u64) -> bool {
+        match (self.stack.get_mut(slot), u16::try_from(value)) {
+            (Some(slot), Ok(value)) => {
+                *slot = value;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    fn decode(&self, address: usize) -> (String, usize) {
+        let (text, words) = disassemble(&self.program, address);
+        (text, words * 2)
+    }
+
+    fn opcode(&self, address: usize) -> Option<u8> {
+        let instruction = Instruction::decode(&self.program, address);
+        match instruction.code() {
+            Code::Data => None,
+            _ => Some(instruction.op),
+        }
+    }
+
+    fn describe(&self, address: usize) -> &'static str {
+        Instruction::decode(&self.program, address).code().description()
+    }
+
+    fn mnemonic(&self, op: u8) -> &'static str {
+        lookup(op).mnemonic()
+    }
+
+    fn is_call(&self, address: usize) -> bool {
+        matches!(Instruction::decode(&self.program, address).code(), Code::Call)
+    }
+
+    /// return addresses are word addresses
+    fn return_address(&self, value: u64) -> usize {
+        value as usize * 2
+    }
+
+    fn break_on(hooks: &mut Hooks, op: u8) {
+        hooks.break_op = lookup(op);
+    }
+
+    fn break_op(hooks: &Hooks) -> String {
+        hooks.break_op.to_string()
+    }
+
+    fn step(&mut self, meta: &mut Meta) {
+        step(self, meta);
+    }
+
+    fn snapshot(&self) -> Vec<u8> {
+        State::save(self)
+    }
+
+    fn restore(snapshot: Vec<u8>) -> BoxResult<State> {
+        State::recover(snapshot)
     }
 }
 
 fn recover_legacy(program: Vec<u8>) -> BoxResult<State> {
+    if program.len() < LEGACY_HEADER {
+        return Err(RecoveryError::new(format!("This legacy save is {} bytes, too short for its {} byte header", program.len(), LEGACY_HEADER)));
+    }
     println!("legacy recovery");
     let mut ip = 1;
     let mut state = State::new(program.clone());
@@ -126,7 +314,7 @@ fn recover_legacy(program: Vec<u8>) -> BoxResult<State> {
         let value: u16 = higher << 8 | lower;
         state.register[i] = value;
     }
-    ip = ip + 16;
+    ip += 16;
     for i in 0..99 { // load the stack
         let n = i * 2;
         let higher = program[ip + n + 1] as u16;
@@ -134,11 +322,11 @@ fn recover_legacy(program: Vec<u8>) -> BoxResult<State> {
         let value: u16 = higher << 8 | lower;
         state.stack.push(value);
     }
-    ip = ip + 100;
+    ip += 100;
     let higher = program[ip] as u16;
     let lower = program[ip + 1] as u16;
     let value: u16 = higher << 8 | lower;
-    ip = ip + 2;
+    ip += 2;
     let sp = value as usize;
     for _i in sp..99 {
         state.stack.pop();
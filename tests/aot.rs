@@ -0,0 +1,85 @@
+//! translated programs have to leave the machine exactly where the interpreter would
+
+mod common;
+
+use common::{bytes, challenge, interpret, outcome, Outcome, FIB, LOOPS, PATCHED};
+use std::cell::RefCell;
+use std::fs;
+use std::rc::Rc;
+use synacor::aot::{translate, Runtime};
+use synacor::console::{Pipe, Piped};
+use synacor::debug::Reason;
+
+// regenerate with `cargo run -- aot tests/aot/<name>.bin --output tests/aot/<name>.rs`
+#[path = "aot/echo.rs"]
+mod echo;
+#[path = "aot/faults.rs"]
+mod faults;
+#[path = "aot/fib.rs"]
+mod fib;
+#[path = "aot/loops.rs"]
+mod loops;
+#[path = "aot/patched.rs"]
+mod patched;
+
+/// the same through a translated module
+fn compiled(mut rt: Runtime, run: fn(&mut Runtime), input: &str) -> Outcome {
+    let pipe = Rc::new(RefCell::new(Pipe::script(input.as_bytes())));
+    rt.io(Box::new(Piped(pipe.clone())));
+    run(&mut rt);
+    outcome(&rt.state, &rt.meta, &pipe)
+}
+
+/// the checked in module is what the translator makes of the image today, and runs like the interpreter
+fn compare(name: &str, image: &[u8], rt: Runtime, run: fn(&mut Runtime), input: &str) -> Outcome {
+    let source = fs::read_to_string(format!("{}/tests/aot/{}.rs", env!("CARGO_MANIFEST_DIR"), name)).unwrap();
+    assert_eq!(translate(image, &format!("{}.bin", name), None).unwrap(), source, "tests/aot/{}.rs is out of date", name);
+    let interpreted = interpret(image.to_vec(), input);
+    let translated = compiled(rt, run, input);
+    assert_eq!(interpreted, translated);
+    translated
+}
+
+#[test]
+fn loops() {
+    assert_eq!(loops::IMAGE, &bytes(&LOOPS)[..]);
+    let outcome = compare("loops", loops::IMAGE, loops::runtime().unwrap(), loops::run, "");
+    assert_eq!(outcome.output, b"X\n");
+}
+
+#[test]
+fn recursion() {
+    assert_eq!(fib::IMAGE, &bytes(&FIB)[..]);
+    let outcome = compare("fib", fib::IMAGE, fib::runtime().unwrap(), fib::run, "");
+    assert_eq!(outcome.register[0], 6765);
+}
+
+#[test]
+fn self_modified_blocks_fall_back() {
+    assert_eq!(patched::IMAGE, &bytes(&PATCHED)[..]);
+    let outcome = compare("patched", patched::IMAGE, patched::runtime().unwrap(), patched::run, "");
+    assert_eq!(outcome.output, b"AAB");
+    assert_eq!(outcome.register[0], 50);
+}
+
+#[test]
+fn input_runs_dry() {
+    let outcome = compare("echo", echo::IMAGE, echo::runtime().unwrap(), echo::run, "hello\n");
+    assert_eq!(outcome.output, b"hello\n");
+}
+
+#[test]
+fn faults_go_to_the_interpreter() {
+    // memory past the image, then a mod by a register holding zero inside a call
+    let outcome = compare("faults", faults::IMAGE, faults::runtime().unwrap(), faults::run, "");
+    assert_eq!(outcome.register[2], 7);
+    assert_eq!(outcome.program.len(), 60002);
+    assert_eq!(outcome.frames.len(), 1);
+    assert_eq!((outcome.ip, outcome.reason), (48, Some(Reason::DivideByZero)));
+}
+
+#[test]
+fn challenge_translates() {
+    let source = translate(&challenge(), "challenge.bin", None).unwrap();
+    assert!(source.contains("pub fn run(rt: &mut Runtime)"));
+}
@@ -0,0 +1,43 @@
+//! translated by `synacor aot` from echo.bin, do not edit
+
+#![allow(dead_code, clippy::all)]
+
+use synacor::aot::Runtime;
+use synacor::vm::BoxResult;
+
+/// the image the blocks were translated from
+pub const IMAGE: &[u8] = b"\
+\x14\x00\x00\x80\x13\x00\x00\x80\x06\x00\x00\x00\
+";
+
+/// word address and length in words of every translated block
+pub const BLOCKS: &[(usize, usize)] = &[
+    (0, 6),
+];
+
+/// the machine as the image starts it, on the terminal
+pub fn runtime() -> BoxResult<Runtime> {
+    Runtime::new(IMAGE, BLOCKS)
+}
+
+/// run until the guest halts or stops for the debugger
+pub fn run(rt: &mut Runtime) {
+    while !rt.meta.halt && !rt.meta.debugging {
+        match rt.block() {
+            Some(0) => {
+                // 0x0000: in r0
+                if !rt.input(0) {
+                    rt.meta.op_count += 1;
+                    rt.state.ip = 0;
+                    continue;
+                }
+                // 0x0004: out r0
+                rt.out(rt.state.register[0]);
+                // 0x0008: jmp 0
+                rt.meta.op_count += 3;
+                rt.state.ip = 0;
+            }
+            _ => rt.step(),
+        }
+    }
+}
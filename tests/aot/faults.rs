@@ -0,0 +1,65 @@
+//! translated by `synacor aot` from faults.bin, do not edit
+
+#![allow(dead_code, clippy::all)]
+
+use synacor::aot::Runtime;
+use synacor::vm::BoxResult;
+
+/// the image the blocks were translated from
+pub const IMAGE: &[u8] = b"\
+\x01\x00\x00\x80\x30\x75\x0f\x00\x01\x80\x00\x80\x10\x00\x00\x80\x07\x00\x0f\x00\x02\x80\x00\x80\
+\x11\x00\x14\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x09\x00\x04\x80\x02\x80\x01\x00\
+\x0b\x00\x03\x80\x02\x80\x01\x80\x12\x00\
+";
+
+/// word address and length in words of every translated block
+pub const BLOCKS: &[(usize, usize)] = &[
+    (0, 9),
+    (9, 5),
+    (20, 9),
+];
+
+/// the machine as the image starts it, on the terminal
+pub fn runtime() -> BoxResult<Runtime> {
+    Runtime::new(IMAGE, BLOCKS)
+}
+
+/// run until the guest halts or stops for the debugger
+pub fn run(rt: &mut Runtime) {
+    while !rt.meta.halt && !rt.meta.debugging {
+        match rt.block() {
+            Some(0) => {
+                // 0x0000: set r0 30000
+                rt.state.register[0] = 30000;
+                // 0x0006: rmem r1 r0
+                rt.state.register[1] = rt.read(rt.state.register[0]);
+                // 0x000C: wmem r0 7
+                rt.write(rt.state.register[0], 7);
+                rt.meta.op_count += 3;
+                rt.state.ip = 18;
+            }
+            Some(9) => {
+                // 0x0012: rmem r2 r0
+                rt.state.register[2] = rt.read(rt.state.register[0]);
+                // 0x0018: call 20
+                rt.meta.op_count += 2;
+                rt.call(12, 14, 40);
+            }
+            Some(20) => {
+                // 0x0028: add r4 r2 1
+                rt.state.register[4] = ((rt.state.register[2] as u32 + 1 as u32) % 32768) as u16;
+                // 0x0030: mod r3 r2 r1
+                if rt.state.register[1] == 0 {
+                    rt.meta.op_count += 1;
+                    rt.bail(24);
+                    continue;
+                }
+                rt.state.register[3] = rt.state.register[2] % rt.state.register[1] % 32768;
+                // 0x0038: ret
+                rt.meta.op_count += 3;
+                rt.ret(28);
+            }
+            _ => rt.step(),
+        }
+    }
+}
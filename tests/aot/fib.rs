@@ -0,0 +1,94 @@
+//! translated by `synacor aot` from fib.bin, do not edit
+
+#![allow(dead_code, clippy::all)]
+
+use synacor::aot::Runtime;
+use synacor::vm::BoxResult;
+
+/// the image the blocks were translated from
+pub const IMAGE: &[u8] = b"\
+\x01\x00\x00\x80\x14\x00\x11\x00\x07\x00\x00\x00\x15\x00\x05\x00\x01\x80\x00\x80\x01\x00\x07\x00\
+\x01\x80\x0f\x00\x12\x00\x02\x00\x00\x80\x09\x00\x00\x80\x00\x80\xff\x7f\x11\x00\x07\x00\x01\x00\
+\x02\x80\x00\x80\x03\x00\x00\x80\x02\x00\x02\x80\x09\x00\x00\x80\x00\x80\xfe\x7f\x11\x00\x07\x00\
+\x03\x00\x02\x80\x09\x00\x00\x80\x00\x80\x02\x80\x12\x00\
+";
+
+/// word address and length in words of every translated block
+pub const BLOCKS: &[(usize, usize)] = &[
+    (0, 5),
+    (7, 7),
+    (14, 1),
+    (15, 8),
+    (23, 13),
+    (36, 7),
+];
+
+/// the machine as the image starts it, on the terminal
+pub fn runtime() -> BoxResult<Runtime> {
+    Runtime::new(IMAGE, BLOCKS)
+}
+
+/// run until the guest halts or stops for the debugger
+pub fn run(rt: &mut Runtime) {
+    while !rt.meta.halt && !rt.meta.debugging {
+        match rt.block() {
+            Some(0) => {
+                // 0x0000: set r0 20
+                rt.state.register[0] = 20;
+                // 0x0006: call 7
+                rt.meta.op_count += 2;
+                rt.call(3, 5, 14);
+            }
+            Some(7) => {
+                // 0x000E: gt r1 r0 1
+                rt.state.register[1] = (rt.state.register[0] > 1) as u16;
+                // 0x0016: jt r1 15
+                rt.meta.op_count += 2;
+                rt.state.ip = if rt.state.register[1] != 0 { 30 } else { 28 };
+            }
+            Some(14) => {
+                // 0x001C: ret
+                rt.meta.op_count += 1;
+                rt.ret(14);
+            }
+            Some(15) => {
+                // 0x001E: push r0
+                rt.state.stack.push(rt.state.register[0]);
+                // 0x0022: add r0 r0 32767
+                rt.state.register[0] = ((rt.state.register[0] as u32 + 32767 as u32) % 32768) as u16;
+                // 0x002A: call 7
+                rt.meta.op_count += 3;
+                rt.call(21, 23, 14);
+            }
+            Some(23) => {
+                // 0x002E: set r2 r0
+                rt.state.register[2] = rt.state.register[0];
+                // 0x0034: pop r0
+                if let Some(value) = rt.state.stack.pop() {
+                    rt.state.register[0] = value;
+                    rt.state.unwind();
+                }
+                // 0x0038: push r2
+                rt.state.stack.push(rt.state.register[2]);
+                // 0x003C: add r0 r0 32766
+                rt.state.register[0] = ((rt.state.register[0] as u32 + 32766 as u32) % 32768) as u16;
+                // 0x0044: call 7
+                rt.meta.op_count += 5;
+                rt.call(34, 36, 14);
+            }
+            Some(36) => {
+                // 0x0048: pop r2
+                if let Some(value) = rt.state.stack.pop() {
+                    rt.state.register[2] = value;
+                    rt.state.unwind();
+                }
+                // 0x004C: add r0 r0 r2
+                rt.state.register[0] = ((rt.state.register[0] as u32 + rt.state.register[2] as u32) % 32768) as u16;
+                // 0x0054: ret
+                rt.meta.op_count += 3;
+                rt.ret(42);
+            }
+            _ => rt.step(),
+        }
+    }
+}
@@ -0,0 +1,72 @@
+//! translated by `synacor aot` from loops.bin, do not edit
+
+#![allow(dead_code, clippy::all)]
+
+use synacor::aot::Runtime;
+use synacor::vm::BoxResult;
+
+/// the image the blocks were translated from
+pub const IMAGE: &[u8] = b"\
+\x01\x00\x00\x80\x00\x00\x01\x00\x01\x80\x00\x00\x09\x00\x00\x80\x00\x80\x01\x00\x04\x00\x02\x80\
+\x00\x80\x30\x75\x08\x00\x02\x80\x06\x00\x09\x00\x01\x80\x01\x80\x01\x00\x01\x00\x00\x80\x00\x00\
+\x04\x00\x02\x80\x01\x80\x64\x00\x08\x00\x02\x80\x06\x00\x13\x00\x58\x00\x13\x00\x0a\x00\x00\x00\
+\x15\x00\
+";
+
+/// word address and length in words of every translated block
+pub const BLOCKS: &[(usize, usize)] = &[
+    (0, 6),
+    (6, 11),
+    (17, 14),
+    (31, 4),
+];
+
+/// the machine as the image starts it, on the terminal
+pub fn runtime() -> BoxResult<Runtime> {
+    Runtime::new(IMAGE, BLOCKS)
+}
+
+/// run until the guest halts or stops for the debugger
+pub fn run(rt: &mut Runtime) {
+    while !rt.meta.halt && !rt.meta.debugging {
+        match rt.block() {
+            Some(0) => {
+                // 0x0000: set r0 0
+                rt.state.register[0] = 0;
+                // 0x0006: set r1 0
+                rt.state.register[1] = 0;
+                rt.meta.op_count += 2;
+                rt.state.ip = 12;
+            }
+            Some(6) => {
+                // 0x000C: add r0 r0 1
+                rt.state.register[0] = ((rt.state.register[0] as u32 + 1 as u32) % 32768) as u16;
+                // 0x0014: eq r2 r0 30000
+                rt.state.register[2] = (rt.state.register[0] == 30000) as u16;
+                // 0x001C: jf r2 6
+                rt.meta.op_count += 3;
+                rt.state.ip = if rt.state.register[2] == 0 { 12 } else { 34 };
+            }
+            Some(17) => {
+                // 0x0022: add r1 r1 1
+                rt.state.register[1] = ((rt.state.register[1] as u32 + 1 as u32) % 32768) as u16;
+                // 0x002A: set r0 0
+                rt.state.register[0] = 0;
+                // 0x0030: eq r2 r1 100
+                rt.state.register[2] = (rt.state.register[1] == 100) as u16;
+                // 0x0038: jf r2 6
+                rt.meta.op_count += 4;
+                rt.state.ip = if rt.state.register[2] == 0 { 12 } else { 62 };
+            }
+            Some(31) => {
+                // 0x003E: out 'X'
+                rt.out(88);
+                // 0x0042: out '\n'
+                rt.out(10);
+                rt.meta.op_count += 2;
+                rt.state.ip = 70;
+            }
+            _ => rt.step(),
+        }
+    }
+}
@@ -0,0 +1,80 @@
+//! translated by `synacor aot` from patched.bin, do not edit
+
+#![allow(dead_code, clippy::all)]
+
+use synacor::aot::Runtime;
+use synacor::vm::BoxResult;
+
+/// the image the blocks were translated from
+pub const IMAGE: &[u8] = b"\
+\x01\x00\x00\x80\x00\x00\x09\x00\x00\x80\x00\x80\x01\x00\x04\x00\x01\x80\x00\x80\xc8\x00\x08\x00\
+\x01\x80\x03\x00\x13\x00\x41\x00\x07\x00\x03\x80\x1f\x00\x10\x00\x0a\x00\x32\x00\x01\x00\x03\x80\
+\x01\x00\x01\x00\x00\x80\x00\x00\x06\x00\x03\x00\x15\x00\x13\x00\x42\x00\x00\x00\
+";
+
+/// word address and length in words of every translated block
+pub const BLOCKS: &[(usize, usize)] = &[
+    (0, 3),
+    (3, 11),
+    (14, 5),
+    (19, 3),
+    (22, 8),
+    (31, 2),
+];
+
+/// the machine as the image starts it, on the terminal
+pub fn runtime() -> BoxResult<Runtime> {
+    Runtime::new(IMAGE, BLOCKS)
+}
+
+/// run until the guest halts or stops for the debugger
+pub fn run(rt: &mut Runtime) {
+    while !rt.meta.halt && !rt.meta.debugging {
+        match rt.block() {
+            Some(0) => {
+                // 0x0000: set r0 0
+                rt.state.register[0] = 0;
+                rt.meta.op_count += 1;
+                rt.state.ip = 6;
+            }
+            Some(3) => {
+                // 0x0006: add r0 r0 1
+                rt.state.register[0] = ((rt.state.register[0] as u32 + 1 as u32) % 32768) as u16;
+                // 0x000E: eq r1 r0 200
+                rt.state.register[1] = (rt.state.register[0] == 200) as u16;
+                // 0x0016: jf r1 3
+                rt.meta.op_count += 3;
+                rt.state.ip = if rt.state.register[1] == 0 { 6 } else { 28 };
+            }
+            Some(14) => {
+                // 0x001C: out 'A'
+                rt.out(65);
+                // 0x0020: jt r3 31
+                rt.meta.op_count += 2;
+                rt.state.ip = if rt.state.register[3] != 0 { 62 } else { 38 };
+            }
+            Some(19) => {
+                // 0x0026: wmem 10 50
+                rt.write(10, 50);
+                rt.meta.op_count += 1;
+                rt.state.ip = 44;
+            }
+            Some(22) => {
+                // 0x002C: set r3 1
+                rt.state.register[3] = 1;
+                // 0x0032: set r0 0
+                rt.state.register[0] = 0;
+                // 0x0038: jmp 3
+                rt.meta.op_count += 3;
+                rt.state.ip = 6;
+            }
+            Some(31) => {
+                // 0x003E: out 'B'
+                rt.out(66);
+                rt.meta.op_count += 1;
+                rt.state.ip = 66;
+            }
+            _ => rt.step(),
+        }
+    }
+}
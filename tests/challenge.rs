@@ -0,0 +1,89 @@
+//! the real challenge through its self-test and a scripted walk, against a checked in transcript of everything the
+//! guest printed and the instruction counts it reached its checkpoints at; `SYNACOR_BLESS=1 cargo test --test
+//! challenge` rewrites both after a change that is meant to alter them
+
+mod common;
+
+use common::challenge;
+use std::cell::RefCell;
+use std::fmt::Write as _;
+use std::fs;
+use std::rc::Rc;
+use synacor::console::{Pipe, Piped};
+use synacor::debug::Meta;
+use synacor::opcode::{self, Code};
+use synacor::peephole;
+use synacor::vm::State;
+
+/// printed once the self-test passes
+const SELF_TEST: &[u8] = b"self-test complete, all tests pass\n";
+
+/// more than the walk takes, a guest that stops reading input fails instead of spinning forever
+const LIMIT: usize = 5_000_000;
+
+fn path(name: &str) -> String {
+    format!("{}/tests/challenge/{}", env!("CARGO_MANIFEST_DIR"), name)
+}
+
+/// the guest output and one `count label` line per checkpoint
+fn play(fused: bool) -> (Vec<u8>, String) {
+    let input = fs::read_to_string(path("input.txt")).unwrap();
+    let mut commands = input.lines();
+    let mut state = State::recover(challenge()).unwrap();
+    let mut meta = Meta::new();
+    let pipe = Rc::new(RefCell::new(Pipe::script(input.as_bytes())));
+    meta.io = Box::new(Piped(pipe.clone()));
+
+    let mut checkpoints = String::new();
+    let mut tested = false;
+    let mut line_start = true;
+    let mut left = input.len();
+    while !meta.halt && !meta.debugging && meta.op_count < LIMIT {
+        // a command is reached when the guest reads its first byte
+        if line_start && matches!(opcode::parse(&state.program, &state.ip), Code::In) {
+            if let Some(command) = commands.next() {
+                let _ = writeln!(checkpoints, "{} > {}", meta.op_count, command);
+            }
+        }
+        if !fused || peephole::run(&mut state, &mut meta).is_none() {
+            opcode::step(&mut state, &mut meta);
+        }
+        let pipe = pipe.borrow();
+        if pipe.input.len() < left {
+            left = pipe.input.len();
+            line_start = input.as_bytes()[input.len() - left - 1] == b'\n';
+        }
+        if !tested && pipe.output.ends_with(SELF_TEST) {
+            tested = true;
+            let _ = writeln!(checkpoints, "{} self-test complete", meta.op_count);
+        }
+    }
+    assert!(meta.debugging, "the guest should end up waiting for more input");
+    let _ = writeln!(checkpoints, "{} out of input", meta.op_count);
+    let output = pipe.borrow().output.clone();
+    (output, checkpoints)
+}
+
+fn check(fused: bool) {
+    let (output, checkpoints) = play(fused);
+    if std::env::var_os("SYNACOR_BLESS").is_some() {
+        fs::write(path("transcript.txt"), &output).unwrap();
+        fs::write(path("checkpoints.txt"), &checkpoints).unwrap();
+    }
+    let transcript = fs::read(path("transcript.txt")).unwrap();
+    if output != transcript {
+        let line = output.split(|byte| *byte == b'\n').zip(transcript.split(|byte| *byte == b'\n')).take_while(|(a, b)| a == b).count();
+        panic!("the guest output differs from tests/challenge/transcript.txt from line {} on", line + 1);
+    }
+    assert_eq!(checkpoints, fs::read_to_string(path("checkpoints.txt")).unwrap(), "instruction counts moved");
+}
+
+#[test]
+fn transcript_stepping() {
+    check(false);
+}
+
+#[test]
+fn transcript_with_superinstructions() {
+    check(true);
+}
@@ -0,0 +1,103 @@
+//! programs and the run harness the back end tests share, each back end gets the first go at every instruction and
+//! the interpreter steps whatever it declines
+
+#![allow(dead_code)]
+
+use std::cell::RefCell;
+use std::fs;
+use std::rc::Rc;
+use synacor::console::{Pipe, Piped};
+use synacor::debug::{Meta, Reason};
+use synacor::machine::Frame;
+use synacor::opcode::{self, Code};
+use synacor::vm::State;
+
+pub const R0: u16 = 32768;
+pub const R1: u16 = 32769;
+pub const R2: u16 = 32770;
+pub const R3: u16 = 32771;
+
+/// count r0 to 30000 a hundred times, then out 'X'
+pub const LOOPS: [u16; 37] = [
+    1, R0, 0, 1, R1, 0, 9, R0, R0, 1, 4, R2, R0, 30000, 8, R2, 6, 9, R1, R1, 1, 1, R0, 0, 4, R2, R1, 100, 8, R2, 6, 19,
+    88, 19, 10, 0, 21,
+];
+
+/// r0 = fib(20) with push, pop, call and ret
+pub const FIB: [u16; 43] = [
+    1, R0, 20, 17, 7, 0, 21, 5, R1, R0, 1, 7, R1, 15, 18, 2, R0, 9, R0, R0, 32767, 17, 7, 1, R2, R0, 3, R0, 2, R2, 9,
+    R0, R0, 32766, 17, 7, 3, R2, 9, R0, R0, R2, 18,
+];
+
+/// count r0 to 200 with eq and jf, out 'A', then wmem patches the 200 under the compare to 50 and counts again
+pub const PATCHED: [u16; 34] = [
+    1, R0, 0, 9, R0, R0, 1, 4, R1, R0, 200, 8, R1, 3, 19, 65, 7, R3, 31, 16, 10, 50, 1, R3, 1, 1, R0, 0, 6, 3, 21, 19,
+    66, 0,
+];
+
+/// everything a back end has to leave exactly as the interpreter would
+#[derive(Debug, PartialEq)]
+pub struct Outcome {
+    pub output: Vec<u8>,
+    pub register: [u16; 8],
+    pub stack: Vec<u16>,
+    pub frames: Vec<Frame>,
+    pub program: Vec<u8>,
+    pub ip: usize,
+    pub instructions: usize,
+    pub reason: Option<Reason>,
+}
+
+pub fn outcome(state: &State, meta: &Meta, pipe: &Rc<RefCell<Pipe>>) -> Outcome {
+    Outcome {
+        output: pipe.borrow().output.clone(),
+        register: state.register,
+        stack: state.stack.clone(),
+        frames: state.frames.clone(),
+        program: state.program.clone(),
+        ip: state.ip,
+        instructions: meta.op_count,
+        reason: meta.reason,
+    }
+}
+
+pub fn bytes(words: &[u16]) -> Vec<u8> {
+    words.iter().flat_map(|word| word.to_le_bytes()).collect()
+}
+
+pub fn challenge() -> Vec<u8> {
+    fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/challenge.bin")).unwrap()
+}
+
+/// run until halt or the input runs dry, offering every instruction to fast first; hands back the machine too
+pub fn run<F>(program: Vec<u8>, input: &str, mut fast: F) -> (Outcome, State)
+where
+    F: FnMut(&mut State, &mut Meta) -> Option<Code>,
+{
+    let mut state = State::recover(program).unwrap();
+    let mut meta = Meta::new();
+    let pipe = Rc::new(RefCell::new(Pipe::script(input.as_bytes())));
+    meta.io = Box::new(Piped(pipe.clone()));
+    while !meta.halt && !meta.debugging {
+        if fast(&mut state, &mut meta).is_none() {
+            opcode::step(&mut state, &mut meta);
+        }
+    }
+    (outcome(&state, &meta, &pipe), state)
+}
+
+/// the plain interpreter, what every back end is held to
+pub fn interpret(program: Vec<u8>, input: &str) -> Outcome {
+    run(program, input, |_, _| None).0
+}
+
+/// runs both ways, checks they agree and hands back the fast run
+pub fn compare<F>(program: Vec<u8>, fast: F) -> (Outcome, State)
+where
+    F: FnMut(&mut State, &mut Meta) -> Option<Code>,
+{
+    let interpreted = interpret(program.clone(), "");
+    let (outcome, state) = run(program, "", fast);
+    assert_eq!(interpreted, outcome);
+    (outcome, state)
+}
@@ -0,0 +1,111 @@
+//! coverage recorded from real runs, saved, loaded, merged across runs and reported
+
+use std::env;
+use std::fs;
+use synacor::debug::coverage::{Coverage, EXECUTED, READ, WRITTEN};
+use synacor::debug::symbol::Symbols;
+use synacor::debug::Meta;
+use synacor::opcode;
+use synacor::vm::State;
+
+const R0: u16 = 32768;
+
+/// set r0 to the given flag; jt r0 to the out; halt; out 'A'; rmem r0 from 0; wmem 20 with r0; halt
+fn program(flag: u16) -> Vec<u8> {
+    let words = [1, R0, flag, 7, R0, 7, 0, 19, 65, 15, R0, 0, 16, 20, R0, 0];
+    words.iter().flat_map(|word| word.to_le_bytes()).collect()
+}
+
+fn record(program: Vec<u8>) -> Coverage {
+    let mut state = State::new(program.clone());
+    let mut meta: Meta = Meta::new();
+    meta.hooks.coverage = Some(Coverage::new(&program));
+    while !meta.halt {
+        opcode::step(&mut state, &mut meta);
+    }
+    meta.hooks.coverage.take().unwrap()
+}
+
+fn temporary(name: &str) -> String {
+    let path = env::temp_dir().join(format!("synacor-coverage-{}-{}", name, std::process::id()));
+    path.to_string_lossy().into_owned()
+}
+
+#[test]
+fn save_and_load_round_trip() {
+    let coverage = record(program(1));
+    let path = temporary("round-trip");
+    coverage.save(&path).unwrap();
+    let loaded = Coverage::load(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!((loaded.runs, loaded.program), (1, coverage.program));
+    for address in (0..64).step_by(2) {
+        assert_eq!(loaded.flags(address), coverage.flags(address), "{:#06x}", address);
+    }
+    assert_eq!(loaded.flags(0), EXECUTED | READ);
+    assert_eq!(loaded.flags(12), 0); // the halt the jt jumped over
+    assert_eq!(loaded.flags(40), WRITTEN);
+}
+
+#[test]
+fn runs_accumulate_into_one_file() {
+    let path = temporary("accumulate");
+    record(program(1)).accumulate(&path).unwrap();
+    record(program(1)).accumulate(&path).unwrap();
+    let total = Coverage::load(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert_eq!(total.runs, 2);
+    assert_eq!(total.flags(14), EXECUTED);
+}
+
+#[test]
+fn merging_ors_the_flags_and_adds_the_runs() {
+    let mut total = Coverage::empty();
+    // the run that jumps and the one that falls through to the halt share the same image
+    let jumped = record(program(1));
+    let mut fell = record(program(0));
+    fell.program = jumped.program;
+    total.merge(&jumped).unwrap();
+    total.merge(&fell).unwrap();
+    assert_eq!((total.runs, total.program), (2, jumped.program));
+    assert_eq!(total.flags(12), EXECUTED);
+    assert_eq!(total.flags(14), EXECUTED);
+}
+
+#[test]
+fn coverage_of_another_program_is_refused() {
+    let path = temporary("mismatch");
+    record(program(1)).accumulate(&path).unwrap();
+    assert!(record(program(0)).accumulate(&path).is_err());
+    let kept = Coverage::load(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert_eq!(kept.runs, 1);
+
+    // files written before the fingerprint existed merge with anything
+    let mut old = record(program(0));
+    old.program = None;
+    let mut total = record(program(1));
+    total.merge(&old).unwrap();
+    assert_eq!(total.runs, 2);
+}
+
+#[test]
+fn the_report_lists_branches_taken_one_way() {
+    let symbols = Symbols::new();
+    let jumped = record(program(1));
+    let report = jumped.report(&program(1), &symbols);
+    let section = &report[report.find("branches only ever taken one way").unwrap()..];
+    let lines: Vec<&str> = section.lines().skip(1).collect();
+    assert_eq!(lines.len(), 1, "{}", report);
+    assert!(lines[0].contains("never fell through to"), "{}", report);
+
+    let mut both = Coverage::empty();
+    let mut fell = record(program(0));
+    fell.program = jumped.program;
+    both.merge(&jumped).unwrap();
+    both.merge(&fell).unwrap();
+    let report = both.report(&program(1), &symbols);
+    assert!(report.contains("2 run(s)"));
+    assert!(!report.contains("never"), "{}", report);
+}
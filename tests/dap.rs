@@ -0,0 +1,248 @@
+//! drives `synacor --dap` the way an editor would, over a real socket
+
+use serde_json::{json, Value};
+use std::env;
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+
+/// in r0; call f; out 'A'; halt; f: out 'B'; noop; ret
+const PROGRAM: [u16; 11] = [20, 32768, 17, 7, 19, 65, 0, 19, 66, 21, 18];
+
+struct Editor {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    seq: u64,
+    events: Vec<Value>,
+}
+
+impl Editor {
+    fn receive(&mut self) -> Value {
+        let mut length = 0;
+        loop {
+            let mut line = String::new();
+            assert!(self.reader.read_line(&mut line).unwrap() > 0, "adapter hung up");
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            if let Some(value) = line.strip_prefix("Content-Length:") {
+                length = value.trim().parse().unwrap();
+            }
+        }
+        let mut body = vec![0u8; length];
+        self.reader.read_exact(&mut body).unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    fn request(&mut self, command: &str, arguments: Value) -> Value {
+        self.seq += 1;
+        let body = json!({ "seq": self.seq, "type": "request", "command": command, "arguments": arguments }).to_string();
+        write!(self.writer, "Content-Length: {}\r\n\r\n{}", body.len(), body).unwrap();
+        loop {
+            let message = self.receive();
+            if message["type"] == "response" && message["request_seq"] == json!(self.seq) {
+                assert_eq!(message["success"], json!(true), "{} failed: {}", command, message);
+                return message["body"].clone();
+            }
+            self.events.push(message);
+        }
+    }
+
+    /// wait for an event, keeping the ones that came before it
+    fn event(&mut self, name: &str) -> Value {
+        if let Some(i) = self.events.iter().position(|event| event["event"] == name) {
+            return self.events.remove(i)["body"].clone();
+        }
+        loop {
+            let message = self.receive();
+            if message["type"] == "event" && message["event"] == name {
+                return message["body"].clone();
+            }
+            self.events.push(message);
+        }
+    }
+
+    fn output(&mut self) -> String {
+        let mut text = String::new();
+        self.events.retain(|event| {
+            if event["event"] == "output" {
+                text.push_str(event["body"]["output"].as_str().unwrap());
+                return false;
+            }
+            true
+        });
+        text
+    }
+}
+
+fn program(name: &str) -> PathBuf {
+    let path = env::temp_dir().join(format!("synacor-dap-{}-{}.bin", name, std::process::id()));
+    let bytes: Vec<u8> = PROGRAM.iter().flat_map(|word| word.to_le_bytes()).collect();
+    fs::write(&path, bytes).unwrap();
+    path
+}
+
+fn adapter() -> (Child, Editor) {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_synacor"))
+        .args(["--no-init", "--dap", "0"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdout = BufReader::new(child.stdout.take().unwrap());
+    let mut line = String::new();
+    stdout.read_line(&mut line).unwrap();
+    let address = line.trim().strip_prefix("dap: listening on ").expect("no listening line").to_owned();
+    // keep draining so the adapter never blocks on a full pipe
+    std::thread::spawn(move || std::io::copy(&mut stdout, &mut std::io::sink()));
+
+    let stream = TcpStream::connect(address).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+    let editor = Editor {
+        reader: BufReader::new(stream.try_clone().unwrap()),
+        writer: stream,
+        seq: 0,
+        events: Vec::new(),
+    };
+    (child, editor)
+}
+
+#[test]
+fn session() {
+    let path = program("session");
+    let (mut child, mut editor) = adapter();
+
+    let capabilities = editor.request("initialize", json!({ "adapterID": "synacor", "linesStartAt1": true }));
+    assert_eq!(capabilities["supportsDisassembleRequest"], json!(true));
+    editor.event("initialized");
+
+    editor.request("launch", json!({ "program": path, "stopOnEntry": true, "input": "x\n" }));
+    // line 10 is word 9, the noop inside f
+    let breakpoints = editor.request("setBreakpoints", json!({ "source": { "sourceReference": 1 }, "breakpoints": [{ "line": 10 }] }));
+    assert_eq!(breakpoints["breakpoints"][0]["verified"], json!(true));
+    editor.request("configurationDone", json!({}));
+    assert_eq!(editor.event("stopped")["reason"], json!("entry"));
+
+    let threads = editor.request("threads", json!({}));
+    assert_eq!(threads["threads"].as_array().unwrap().len(), 1);
+
+    editor.request("continue", json!({ "threadId": 1 }));
+    assert_eq!(editor.event("stopped")["reason"], json!("breakpoint"));
+    assert_eq!(editor.output(), "B");
+
+    let trace = editor.request("stackTrace", json!({ "threadId": 1 }));
+    let frames = trace["stackFrames"].as_array().unwrap();
+    assert_eq!(frames.len(), 2);
+    assert_eq!(frames[0]["line"], json!(10));
+    assert_eq!(frames[0]["instructionPointerReference"], json!("0x0012"));
+    assert_eq!(frames[1]["name"], json!("<entry>"));
+    assert_eq!(frames[1]["line"], json!(3));
+
+    let scopes = editor.request("scopes", json!({ "frameId": 0 }));
+    let scopes = scopes["scopes"].as_array().unwrap();
+    let names: Vec<&str> = scopes.iter().map(|scope| scope["name"].as_str().unwrap()).collect();
+    assert_eq!(names, ["Registers", "Stack", "Memory"]);
+
+    let registers = editor.request("variables", json!({ "variablesReference": scopes[0]["variablesReference"] }));
+    assert_eq!(registers["variables"][0]["name"], json!("r0"));
+    assert_eq!(registers["variables"][0]["value"], json!("120"));
+    let stack = editor.request("variables", json!({ "variablesReference": scopes[1]["variablesReference"] }));
+    let stack = stack["variables"].as_array().unwrap();
+    assert_eq!(stack.len(), 1);
+    assert!(stack[0]["value"].as_str().unwrap().starts_with("4 (return to"));
+    let memory = editor.request("variables", json!({ "variablesReference": scopes[2]["variablesReference"] }));
+    assert_eq!(memory["variables"][0]["name"], json!("0x0012"));
+    assert_eq!(memory["variables"][0]["value"], json!("21"));
+
+    editor.request("setVariable", json!({ "variablesReference": scopes[0]["variablesReference"], "name": "r1", "value": "0x10" }));
+    assert_eq!(editor.request("evaluate", json!({ "expression": "r1", "context": "watch" }))["result"], json!("16"));
+
+    let listing = editor.request("disassemble", json!({ "memoryReference": "0x0000", "instructionCount": 5 }));
+    let instructions: Vec<&str> = listing["instructions"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|instruction| instruction["instruction"].as_str().unwrap())
+        .collect();
+    assert_eq!(instructions, ["in r0", "call 7", "out 'A'", "halt", "out 'B'"]);
+    let around = editor.request("disassemble", json!({ "memoryReference": "0x0012", "instructionOffset": -1, "instructionCount": 2 }));
+    assert_eq!(around["instructions"][0]["address"], json!("0x000e"));
+
+    editor.request("stepOut", json!({ "threadId": 1 }));
+    assert_eq!(editor.event("stopped")["reason"], json!("step"));
+    let trace = editor.request("stackTrace", json!({ "threadId": 1 }));
+    assert_eq!(trace["stackFrames"].as_array().unwrap().len(), 1);
+    assert_eq!(trace["stackFrames"][0]["line"], json!(5));
+
+    editor.request("next", json!({ "threadId": 1 }));
+    assert_eq!(editor.event("stopped")["reason"], json!("step"));
+    assert_eq!(editor.output(), "A");
+
+    editor.request("continue", json!({ "threadId": 1 }));
+    assert_eq!(editor.event("exited")["exitCode"], json!(0));
+    editor.event("terminated");
+    editor.request("disconnect", json!({}));
+
+    assert!(child.wait().unwrap().success());
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn input_from_the_debug_console() {
+    let path = program("input");
+    let (mut child, mut editor) = adapter();
+
+    editor.request("initialize", json!({ "adapterID": "synacor" }));
+    editor.request("launch", json!({ "program": path }));
+    editor.request("configurationDone", json!({}));
+    let stopped = editor.event("stopped");
+    assert_eq!(stopped["reason"], json!("pause"));
+    assert_eq!(stopped["instructionPointerReference"], json!("0x0000"));
+
+    editor.request("evaluate", json!({ "expression": "hi", "context": "repl" }));
+    editor.request("continue", json!({ "threadId": 1 }));
+    editor.event("exited");
+    assert_eq!(editor.output(), "BA");
+    editor.request("disconnect", json!({}));
+
+    assert!(child.wait().unwrap().success());
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn bad_messages_get_failed_responses() {
+    let path = program("bad");
+    let (mut child, mut editor) = adapter();
+    editor.request("initialize", json!({ "adapterID": "synacor" }));
+    editor.event("initialized");
+
+    write!(editor.writer, "Content-Length: 9\r\n\r\n{{ \"seq\": ").unwrap();
+    let response = editor.receive();
+    assert_eq!((&response["success"], &response["request_seq"]), (&json!(false), &json!(0)));
+    assert!(response["message"].as_str().unwrap().starts_with("not a JSON message"));
+
+    // the body is skipped without being held, the session goes on after it
+    let huge = 1 << 21;
+    write!(editor.writer, "Content-Length: {}\r\n\r\n", huge).unwrap();
+    editor.writer.write_all(&vec![b' '; huge]).unwrap();
+    let response = editor.receive();
+    assert_eq!(response["success"], json!(false));
+    assert!(response["message"].as_str().unwrap().contains("over the"));
+
+    editor.request("launch", json!({ "program": path, "stopOnEntry": true }));
+    editor.request("configurationDone", json!({}));
+    editor.event("stopped");
+    let listing = editor.request(
+        "disassemble",
+        json!({ "memoryReference": "0x0012", "instructionOffset": -1000000, "instructionCount": 1u64 << 40 }),
+    );
+    assert_eq!(listing["instructions"].as_array().unwrap().len(), 4096);
+    editor.request("disconnect", json!({}));
+
+    assert!(child.wait().unwrap().success());
+    fs::remove_file(path).unwrap();
+}
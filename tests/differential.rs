@@ -0,0 +1,263 @@
+//! random programs run on the crate and on the plain interpreter in `tests/reference`, which have to agree on
+//! registers, stack, memory and output, stepping one instruction at a time and on each faster back end
+
+mod reference;
+
+use quickcheck::{Arbitrary, Gen, QuickCheck, TestResult};
+use reference::Status;
+use std::cell::RefCell;
+use std::rc::Rc;
+use synacor::console::{Pipe, Piped};
+use synacor::debug::Meta;
+#[cfg(feature = "jit")]
+use synacor::jit::Jit;
+use synacor::opcode::{self, Code};
+use synacor::peephole;
+use synacor::vm::State;
+
+/// instructions each case runs at most
+const STEPS: usize = 400;
+
+/// cases per property
+const CASES: u64 = 300;
+
+/// operand counts of opcodes 0 to 21, from the spec
+const OPERANDS: [usize; 22] = [0, 2, 1, 1, 3, 3, 1, 2, 2, 3, 3, 3, 3, 3, 2, 2, 2, 1, 0, 1, 1, 0];
+
+/// a program of whole instructions plus where the machine starts out
+#[derive(Debug, Clone)]
+struct Case {
+    instructions: Vec<Vec<u16>>,
+    registers: [u16; 8],
+    stack: Vec<u16>,
+    input: Vec<u8>,
+}
+
+impl Case {
+    fn program(&self) -> Vec<u16> {
+        self.instructions.concat()
+    }
+}
+
+fn below(g: &mut Gen, n: u16) -> u16 {
+    u16::arbitrary(g) % n
+}
+
+/// mostly registers, now and then a literal or a word past the registers
+fn operand(g: &mut Gen, small: u16) -> u16 {
+    match below(g, 10) {
+        0..=3 => 32768 + below(g, 8),
+        4..=7 => below(g, small),
+        8 => below(g, 32768),
+        _ => 32776 + below(g, 32760),
+    }
+}
+
+/// addresses stay inside the program or just past it, so jumps loop and memory ops hit code
+fn address(g: &mut Gen, len: u16) -> u16 {
+    match below(g, 4) {
+        0 => 32768 + below(g, 8),
+        _ => below(g, len + 8),
+    }
+}
+
+fn destination(g: &mut Gen) -> u16 {
+    match below(g, 16) {
+        0 => 32776 + below(g, 100),
+        _ => 32768 + below(g, 8),
+    }
+}
+
+impl Arbitrary for Case {
+    fn arbitrary(g: &mut Gen) -> Case {
+        let count = 1 + below(g, 40) as usize;
+        // word addresses are only known once the instructions are, a rough bound does
+        let len = count as u16 * 3;
+        let instructions = (0..count)
+            .map(|_| {
+                let op = match below(g, 100) {
+                    0 => 0,
+                    1 => 22 + below(g, 100),
+                    _ => 1 + below(g, 21),
+                };
+                let mut words = vec![op];
+                if let Some(operands) = OPERANDS.get(op as usize) {
+                    for n in 0..*operands {
+                        words.push(match (op, n) {
+                            (1 | 3 | 4 | 5 | 9..=15 | 20, 0) => destination(g),
+                            (6 | 17, 0) | (7 | 8 | 15, 1) | (16, 0) => address(g, len),
+                            (19, 0) => 32 + below(g, 95),
+                            _ => operand(g, 64),
+                        });
+                    }
+                }
+                words
+            })
+            .collect();
+        let mut registers = [0; 8];
+        for register in registers.iter_mut() {
+            *register = below(g, 32768);
+        }
+        Case {
+            instructions,
+            registers,
+            stack: (0..below(g, 6)).map(|_| below(g, len)).collect(),
+            input: (0..below(g, 8)).map(|_| b'a' + below(g, 26) as u8).collect(),
+        }
+    }
+
+    /// fewer instructions first, then less input
+    fn shrink(&self) -> Box<dyn Iterator<Item = Case>> {
+        let mut smaller = Vec::new();
+        for n in (0..self.instructions.len()).rev() {
+            if self.instructions.len() > 1 {
+                let mut case = self.clone();
+                case.instructions.remove(n);
+                smaller.push(case);
+            }
+        }
+        if !self.input.is_empty() {
+            let mut case = self.clone();
+            case.input.pop();
+            smaller.push(case);
+        }
+        Box::new(smaller.into_iter())
+    }
+}
+
+struct Real {
+    state: State,
+    meta: Meta,
+    pipe: Rc<RefCell<Pipe>>,
+}
+
+fn machines(case: &Case) -> (Real, reference::Machine) {
+    let program = case.program();
+    let mut expected = reference::Machine::new(&program);
+    expected.registers = case.registers;
+    expected.stack = case.stack.clone();
+    expected.input = case.input.iter().copied().collect();
+
+    let mut state = State::new(expected.memory.iter().flat_map(|word| word.to_le_bytes()).collect());
+    state.register = case.registers;
+    state.stack = case.stack.clone();
+    let mut meta = Meta::new();
+    let pipe = Rc::new(RefCell::new(Pipe::script(&case.input)));
+    meta.io = Box::new(Piped(pipe.clone()));
+    (Real { state, meta, pipe }, expected)
+}
+
+/// everything but memory, cheap enough to check after every instruction
+fn registers_stack_output(real: &Real, expected: &reference::Machine) -> Result<(), String> {
+    let actual = (real.state.ip, real.state.register, &real.state.stack, &real.pipe.borrow().output);
+    let wanted = (expected.ip * 2, expected.registers, &expected.stack, &expected.output);
+    if actual != wanted {
+        return Err(format!("after {} instructions\n  real {:?}\n  reference {:?}", expected.steps, actual, wanted));
+    }
+    if real.meta.op_count != expected.steps {
+        return Err(format!("real counted {} instructions, reference {}", real.meta.op_count, expected.steps));
+    }
+    Ok(())
+}
+
+fn memory(real: &Real, expected: &reference::Machine) -> Result<(), String> {
+    for (address, word) in expected.memory.iter().enumerate() {
+        let actual = u16::from_le_bytes([real.state.program[address * 2], real.state.program[address * 2 + 1]]);
+        if actual != *word {
+            return Err(format!("memory at {} is {}, reference has {}", address, actual, word));
+        }
+    }
+    Ok(())
+}
+
+/// whether the real machine stopped the way the reference did
+fn stopped(real: &Real, status: Status) -> Result<(), String> {
+    let wanted = match status {
+        Status::Running => (false, false),
+        Status::Halted => (true, false),
+        Status::Waiting | Status::Unknown | Status::DivideByZero => (false, true),
+    };
+    match (real.meta.halt, real.meta.debugging) == wanted {
+        true => Ok(()),
+        false => Err(format!(
+            "reference {:?}, real halt {} debugging {}",
+            status, real.meta.halt, real.meta.debugging
+        )),
+    }
+}
+
+fn verdict(result: Result<(), String>) -> TestResult {
+    match result {
+        Ok(()) => TestResult::passed(),
+        Err(message) => TestResult::error(message),
+    }
+}
+
+/// one instruction on each side, compared after every one
+fn stepped(case: Case) -> TestResult {
+    let (mut real, mut expected) = machines(&case);
+    let result = (|| {
+        for _ in 0..STEPS {
+            let status = expected.step();
+            opcode::step(&mut real.state, &mut real.meta);
+            registers_stack_output(&real, &expected)?;
+            stopped(&real, status)?;
+            if status != Status::Running {
+                break;
+            }
+        }
+        memory(&real, &expected)
+    })();
+    verdict(result)
+}
+
+/// fast gets the first go at every instruction and the interpreter steps whatever it declines, the reference catches
+/// up to however many instructions the crate got through
+fn ran<F>(case: Case, mut fast: F) -> TestResult
+where
+    F: FnMut(&mut State, &mut Meta) -> Option<Code>,
+{
+    let (mut real, mut expected) = machines(&case);
+    while !real.meta.halt && !real.meta.debugging && real.meta.op_count < STEPS {
+        if fast(&mut real.state, &mut real.meta).is_none() {
+            opcode::step(&mut real.state, &mut real.meta);
+        }
+    }
+    let mut status = Status::Running;
+    while expected.steps < real.meta.op_count && status == Status::Running {
+        status = expected.step();
+    }
+    verdict(
+        registers_stack_output(&real, &expected)
+            .and_then(|_| stopped(&real, status))
+            .and_then(|_| memory(&real, &expected)),
+    )
+}
+
+#[test]
+fn stepping_matches_the_reference() {
+    QuickCheck::new().tests(CASES).quickcheck(stepped as fn(Case) -> TestResult);
+}
+
+fn fused(case: Case) -> TestResult {
+    ran(case, peephole::run)
+}
+
+/// every block compiled the first time it is reached, so each instruction the jit takes runs native
+#[cfg(feature = "jit")]
+fn compiled(case: Case) -> TestResult {
+    let mut jit = Jit::new().unwrap();
+    jit.hot = 1;
+    ran(case, |state, meta| jit.run(state, meta))
+}
+
+#[test]
+fn superinstructions_match_the_reference() {
+    QuickCheck::new().tests(CASES).quickcheck(fused as fn(Case) -> TestResult);
+}
+
+#[cfg(feature = "jit")]
+#[test]
+fn the_jit_matches_the_reference() {
+    QuickCheck::new().tests(CASES).quickcheck(compiled as fn(Case) -> TestResult);
+}
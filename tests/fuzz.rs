@@ -0,0 +1,98 @@
+//! inputs that used to panic the paths the targets in `fuzz/` exercise, each has to fail cleanly now
+
+use synacor::console::Pipe;
+use synacor::debug::Meta;
+use synacor::opcode::{self, parse, Code};
+use synacor::vm::State;
+
+const R0: u16 = 32768;
+
+fn bytes(words: &[u16]) -> Vec<u8> {
+    words.iter().flat_map(|word| word.to_le_bytes()).collect()
+}
+
+/// run until halt, the debugger or a hundred instructions
+fn run(state: &mut State) -> Meta {
+    let mut meta = Meta::new();
+    meta.io = Box::new(Pipe::default());
+    while !meta.halt && !meta.debugging && meta.op_count < 100 {
+        opcode::step(state, &mut meta);
+    }
+    meta
+}
+
+#[test]
+fn empty_files_do_not_load() {
+    assert!(State::recover(Vec::new()).is_err());
+}
+
+#[test]
+fn saves_shorter_than_their_header_do_not_load() {
+    assert!(State::recover(vec![0x17]).is_err());
+    assert!(State::recover(vec![0x17; 20]).is_err());
+}
+
+#[test]
+fn saves_with_a_stack_past_the_end_do_not_load() {
+    let mut save = vec![0x17, 0x01, 0x00];
+    save.resize(21, 0);
+    save.extend([0, 0, 0]);
+    assert!(State::recover(save).is_err());
+}
+
+#[test]
+fn short_legacy_saves_do_not_load() {
+    assert!(State::recover(vec![0x16]).is_err());
+    assert!(State::recover(vec![0x16; 214]).is_err());
+    assert!(State::recover(vec![0x16; 215]).is_ok());
+}
+
+#[test]
+fn saves_round_trip() {
+    let mut state = State::new(bytes(&[21, 21, 0]));
+    state.ip = 2;
+    state.register[3] = 17;
+    state.stack = vec![1, 2, 3];
+    let loaded = State::recover(State::save(&state)).unwrap();
+    assert_eq!((loaded.ip, loaded.register, loaded.stack), (2, state.register, state.stack));
+    assert_eq!(loaded.program, state.program);
+}
+
+#[test]
+fn parse_reads_past_the_end_as_zero() {
+    assert_eq!(parse(&[], &5), Code::Halt);
+    assert_eq!(parse(&[9], &0), Code::Add);
+    assert_eq!(parse(&[19, 0], &1), Code::Halt);
+}
+
+#[test]
+fn an_ip_past_the_end_halts() {
+    let mut save = State::save(&State::new(bytes(&[21])));
+    save[3] = 0x10;
+    let mut state = State::recover(save).unwrap();
+    assert!(run(&mut state).halt);
+}
+
+#[test]
+fn rmem_past_the_image_reads_zero() {
+    let mut state = State::new(bytes(&[1, R0, 7, 15, R0, 30000]));
+    run(&mut state);
+    assert_eq!(state.register[0], 0);
+}
+
+#[test]
+fn wmem_past_the_image_grows_it() {
+    let mut state = State::new(bytes(&[16, 32767, 42, 15, R0, 32767]));
+    run(&mut state);
+    assert_eq!(state.program.len(), 65536);
+    assert_eq!(state.register[0], 42);
+}
+
+#[test]
+fn mod_by_zero_stops_for_the_debugger() {
+    let mut state = State::new(bytes(&[1, R0, 9, 11, R0, R0, 32769]));
+    let meta = run(&mut state);
+    assert!(meta.debugging);
+    assert!(!meta.halt);
+    assert_eq!((state.ip, state.register[0]), (6, 9));
+}
@@ -0,0 +1,134 @@
+//! drives `synacor --gdb` the way gdb would, over a real socket
+
+use std::env;
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::Duration;
+
+/// noop; noop; out 'A'; halt
+const PROGRAM: [u16; 5] = [21, 21, 19, 65, 0];
+
+struct Gdb {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+fn checksum(data: &str) -> u8 {
+    data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte))
+}
+
+impl Gdb {
+    fn send(&mut self, data: &str, sum: u8) {
+        write!(self.writer, "${}#{:02x}", data, sum).unwrap();
+    }
+
+    fn ack(&mut self) -> u8 {
+        let mut byte = [0u8; 1];
+        self.reader.read_exact(&mut byte).unwrap();
+        byte[0]
+    }
+
+    /// the next reply, acknowledged after its checksum is checked
+    fn reply(&mut self) -> String {
+        let mut skipped = Vec::new();
+        self.reader.read_until(b'$', &mut skipped).unwrap();
+        let mut data = Vec::new();
+        self.reader.read_until(b'#', &mut data).unwrap();
+        assert_eq!(data.pop(), Some(b'#'), "stub hung up");
+        let mut sum = [0u8; 2];
+        self.reader.read_exact(&mut sum).unwrap();
+        let data = String::from_utf8(data).unwrap();
+        assert_eq!(std::str::from_utf8(&sum).unwrap(), format!("{:02x}", checksum(&data)));
+        self.writer.write_all(b"+").unwrap();
+        data
+    }
+
+    fn request(&mut self, data: &str) -> String {
+        self.send(data, checksum(data));
+        assert_eq!(self.ack(), b'+', "{} was not acknowledged", data);
+        self.reply()
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[test]
+fn session() {
+    let id = std::process::id();
+    let path = env::temp_dir().join(format!("synacor-gdb-{}.bin", id));
+    let coverage = env::temp_dir().join(format!("synacor-gdb-{}.coverage", id));
+    fs::write(&path, PROGRAM.iter().flat_map(|word| word.to_le_bytes()).collect::<Vec<u8>>()).unwrap();
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_synacor"))
+        .args(["--no-init", "--smc", "--gdb", "0", "--coverage"])
+        .args([&coverage, &path])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdout = BufReader::new(child.stdout.take().unwrap());
+    let mut line = String::new();
+    stdout.read_line(&mut line).unwrap();
+    let address = line.trim().strip_prefix("gdb: waiting for a connection on ").expect("no waiting line").to_owned();
+    // keep draining so the stub never blocks on a full pipe, the rest is checked at the end
+    let rest = thread::spawn(move || {
+        let mut text = String::new();
+        stdout.read_to_string(&mut text).unwrap();
+        text
+    });
+
+    let stream = TcpStream::connect(address).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+    let mut gdb = Gdb {
+        reader: BufReader::new(stream.try_clone().unwrap()),
+        writer: stream,
+    };
+
+    // a bad checksum is refused and the packet sent again
+    gdb.send("?", checksum("?").wrapping_add(1));
+    assert_eq!(gdb.ack(), b'-');
+    assert_eq!(gdb.request("?"), "S05");
+
+    // r0 to r7 then pc, every one a little endian u16
+    assert_eq!(gdb.request("g"), "0".repeat(36));
+    let mut registers = [0u8; 18];
+    registers[..2].copy_from_slice(&0x1234u16.to_le_bytes());
+    registers[14..16].copy_from_slice(&7u16.to_le_bytes());
+    assert_eq!(gdb.request(&format!("G{}", hex(&registers))), "OK");
+    assert_eq!(gdb.request("g"), hex(&registers));
+
+    assert_eq!(gdb.request("m0,4"), "15001500");
+    assert_eq!(gdb.request("s"), "S05");
+    assert_eq!(&gdb.request("g")[32..], "0200");
+
+    // rewrite the noop that already ran, coverage and --smc see it like a wmem; the same word again changes nothing
+    assert_eq!(gdb.request("M0,2:1500"), "OK");
+    assert_eq!(gdb.request("M0,2:0000"), "OK");
+    assert_eq!(gdb.request("m0,2"), "0000");
+
+    // anything that does not parse or runs off memory is an error, and the stub carries on
+    assert_eq!(gdb.request("mzz,2"), "E01");
+    assert_eq!(gdb.request("M0,4:12"), "E01");
+    assert_eq!(gdb.request("Mffff,2:0000"), "E01");
+    assert_eq!(gdb.request("Gzz"), "E01");
+
+    assert_eq!(gdb.request("Z0,4,2"), "OK");
+    assert_eq!(gdb.request("c"), "T05swbreak:;");
+    assert_eq!(&gdb.request("g")[32..], "0400");
+    assert_eq!(gdb.request("z0,4,2"), "OK");
+    assert_eq!(gdb.request("c"), "W00");
+    gdb.send("k", checksum("k"));
+
+    assert!(child.wait().unwrap().success());
+    let output = rest.join().unwrap();
+    assert!(output.contains("1 writes into executed or decoded code"), "{}", output);
+    let recorded = fs::read_to_string(&coverage).unwrap();
+    assert!(recorded.lines().any(|line| line == "0x0000 xw"), "{}", recorded);
+    fs::remove_file(path).unwrap();
+    fs::remove_file(coverage).unwrap();
+}
@@ -0,0 +1,47 @@
+//! the jit has to leave the machine exactly where the interpreter would
+
+#![cfg(feature = "jit")]
+
+mod common;
+
+use common::{bytes, challenge, compare, run, FIB, LOOPS, R0, R1, R3};
+use synacor::jit::Jit;
+
+/// a hot loop adding 1 to r0 until 200, then wmem patches the 1 to a 2 and the loop runs again
+const PATCHED_LOOP: [u16; 33] = [
+    1, R0, 0, 9, R0, R0, 1, 4, R1, R0, 200, 8, R1, 3, 19, 65, 16, 6, 2, 7, R3, 30, 1, R3, 1, 1, R0, 0, 6, 3, 19, 66, 0,
+];
+
+/// runs with and without the jit, checks they agree and hands back the jit to look at
+fn compiled(program: Vec<u8>) -> Jit {
+    let mut jit = Jit::new().unwrap();
+    compare(program, |state, meta| jit.run(state, meta));
+    jit
+}
+
+#[test]
+fn loops() {
+    let jit = compiled(bytes(&LOOPS));
+    assert!(jit.compiled > 0);
+}
+
+#[test]
+fn recursion() {
+    let mut jit = Jit::new().unwrap();
+    let (outcome, _) = run(bytes(&FIB), "", |state, meta| jit.run(state, meta));
+    assert_eq!(outcome.register[0], 6765);
+    let jit = compiled(bytes(&FIB));
+    assert!(jit.compiled > 0);
+}
+
+#[test]
+fn self_modified_blocks_fall_back() {
+    let jit = compiled(bytes(&PATCHED_LOOP));
+    assert_eq!(jit.discarded, 1);
+}
+
+#[test]
+fn challenge_self_test() {
+    let jit = compiled(challenge());
+    assert!(jit.compiled > 0);
+}
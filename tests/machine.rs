@@ -0,0 +1,330 @@
+//! the debugger, backtraces, the profiler, the trace recorder and snapshots driving an architecture that is not
+//! Synacor
+
+use std::cell::RefCell;
+use std::convert::TryFrom;
+use std::fs;
+use std::io;
+use std::rc::Rc;
+use synacor::debug::debugger::script;
+use synacor::debug::profile::Profile;
+use synacor::debug::symbol::Symbols;
+use synacor::debug::{backtrace, Resume};
+use synacor::machine::{Frame, Machine};
+use synacor::observer::Recorder;
+use synacor::vm::BoxResult;
+
+type Meta = synacor::debug::Meta<Tiny>;
+
+/// one accumulator and a return stack, opcodes are 0 halt, 1 inc, 2 call <address>, 3 ret
+struct Tiny {
+    ip: usize,
+    acc: u8,
+    memory: Vec<u8>,
+    stack: Vec<u8>,
+    frames: Vec<Frame>,
+}
+
+impl Tiny {
+    fn new(memory: Vec<u8>) -> Tiny {
+        Tiny {
+            ip: 0,
+            acc: 0,
+            memory,
+            stack: Vec::new(),
+            frames: Vec::new(),
+        }
+    }
+}
+
+/// all Tiny lets the debugger hook into is the opcode `bp` breaks on
+#[derive(Default)]
+struct Hooks {
+    break_op: Option<u8>,
+}
+
+impl Machine for Tiny {
+    type Hooks = Hooks;
+
+    fn ip(&self) -> usize {
+        self.ip
+    }
+
+    fn set_ip(&mut self, ip: usize) {
+        self.ip = ip;
+    }
+
+    fn registers(&self) -> Vec<u64> {
+        vec![self.acc as u64]
+    }
+
+    fn set_register(&mut self, register: usize, value: u64) -> bool {
+        match (register, u8::try_from(value)) {
+            (0, Ok(value)) => {
+                self.acc = value;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    fn write_memory(&mut self, _hooks: &mut Hooks, address: usize, bytes: &[u8]) {
+        self.memory[address..address + bytes.len()].copy_from_slice(bytes);
+    }
+
+    fn stack(&self) -> Vec<u64> {
+        self.stack.iter().map(|value| *value as u64).collect()
+    }
+
+    fn set_stack(&mut self, slot: usize, value: u64) -> bool {
+        match (self.stack.get_mut(slot), u8::try_from(value)) {
+            (Some(slot), Ok(value)) => {
+                *slot = value;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    fn decode(&self, address: usize) -> (String, usize) {
+        match self.memory.get(address) {
+            Some(2) => (format!("call {}", self.memory[address + 1]), 2),
+            Some(op) => (String::from(self.mnemonic(*op)), 1),
+            None => (String::from("?"), 1),
+        }
+    }
+
+    fn mnemonic(&self, op: u8) -> &'static str {
+        match op {
+            0 => "halt",
+            1 => "inc",
+            2 => "call",
+            3 => "ret",
+            _ => "data",
+        }
+    }
+
+    fn is_call(&self, address: usize) -> bool {
+        self.memory.get(address) == Some(&2)
+    }
+
+    fn break_on(hooks: &mut Hooks, op: u8) {
+        hooks.break_op = Some(op);
+    }
+
+    fn break_op(hooks: &Hooks) -> String {
+        format!("{:?}", hooks.break_op)
+    }
+
+    fn step(&mut self, meta: &mut Meta) {
+        meta.op_count += 1;
+        let mut observer = meta.observer.take();
+        if let Some(observer) = &mut observer {
+            observer.on_fetch(self, self.ip);
+        }
+        let op = self.memory[self.ip];
+        match op {
+            1 => {
+                self.acc = self.acc.wrapping_add(1);
+                self.ip += 1;
+                if let Some(observer) = &mut observer {
+                    observer.on_register_write(0, self.acc as u64);
+                }
+            }
+            2 => {
+                let callee = self.memory[self.ip + 1] as usize;
+                self.stack.push(self.ip as u8 + 2);
+                self.frames.push(Frame {
+                    caller: self.ip,
+                    callee,
+                    depth: self.stack.len(),
+                });
+                if let Some(observer) = &mut observer {
+                    observer.on_stack_push(self.ip as u64 + 2, self.stack.len());
+                    observer.on_jump(self.ip, callee);
+                }
+                self.ip = callee;
+            }
+            3 => {
+                let to = self.stack.pop().unwrap() as usize;
+                self.frames.pop();
+                if let Some(observer) = &mut observer {
+                    observer.on_stack_pop(Some(to as u64), self.stack.len());
+                    observer.on_jump(self.ip, to);
+                }
+                self.ip = to;
+            }
+            _ => meta.halt = true,
+        }
+        meta.observer = observer;
+        if meta.hooks.break_op == Some(op) {
+            meta.debugging = true;
+        }
+    }
+
+    fn snapshot(&self) -> Vec<u8> {
+        let mut bytes = vec![self.ip as u8, self.acc];
+        bytes.extend(&self.memory);
+        bytes
+    }
+
+    fn restore(snapshot: Vec<u8>) -> BoxResult<Tiny> {
+        let mut tiny = Tiny::new(snapshot[2..].to_vec());
+        tiny.ip = snapshot[0] as usize;
+        tiny.acc = snapshot[1];
+        Ok(tiny)
+    }
+}
+
+/// main calls 5 twice, which increments and calls 8, which just returns
+const PROGRAM: [u8; 10] = [2, 5, 2, 5, 0, 1, 2, 8, 3, 1];
+
+fn with(script_lines: &[&str]) -> Meta {
+    let mut meta = Meta::new();
+    meta.pending.extend(script_lines.iter().map(|line| String::from(*line)));
+    meta
+}
+
+/// step until halt or until meta says the debugger should open
+fn run(tiny: &mut Tiny, meta: &mut Meta) {
+    while !meta.halt {
+        tiny.step(meta);
+        if meta.stop(tiny) {
+            break;
+        }
+    }
+}
+
+#[test]
+fn registers_and_stack_go_through_the_trait() {
+    let mut tiny = Tiny::new(PROGRAM.to_vec());
+    let mut meta = with(&["register 0 41", "register 3 1", "register 0 300"]);
+    script(&mut tiny, &mut meta).unwrap();
+    assert_eq!(tiny.acc, 41);
+
+    tiny.step(&mut meta);
+    let mut meta = with(&["stack 0", "stack 9"]);
+    script(&mut tiny, &mut meta).unwrap();
+    assert_eq!(tiny.stack, vec![2]);
+}
+
+#[test]
+fn a_macro_that_uses_itself_stops_expanding() {
+    let mut tiny = Tiny::new(PROGRAM.to_vec());
+    let mut meta = with(&["define spin", "spin", "end", "spin", "register 0 7"]);
+    script(&mut tiny, &mut meta).unwrap();
+    assert_eq!(tiny.acc, 7);
+    assert!(meta.pending.is_empty());
+}
+
+#[test]
+fn next_steps_over_a_call() {
+    let mut tiny = Tiny::new(PROGRAM.to_vec());
+    let mut meta = with(&["next"]);
+    script(&mut tiny, &mut meta).unwrap();
+    assert_eq!(meta.resume, Resume::Return(0));
+    run(&mut tiny, &mut meta);
+    assert_eq!((tiny.ip, tiny.acc, meta.op_count), (2, 1, 5));
+}
+
+#[test]
+fn backtrace_follows_the_frames() {
+    let mut tiny = Tiny::new(PROGRAM.to_vec());
+    let mut meta = Meta::new();
+    for _ in 0..3 {
+        tiny.step(&mut meta);
+    }
+    assert_eq!(tiny.ip, 8);
+    let traces = backtrace(&tiny);
+    let functions: Vec<Option<usize>> = traces.iter().map(|trace| trace.function).collect();
+    let pcs: Vec<usize> = traces.iter().map(|trace| trace.pc).collect();
+    assert_eq!(functions, vec![Some(8), Some(5), None]);
+    assert_eq!(pcs, vec![8, 6, 0]);
+}
+
+#[test]
+fn profile_reads_any_machine() {
+    let mut tiny = Tiny::new(PROGRAM.to_vec());
+    let mut meta = Meta::new();
+    let mut profile = Profile::new();
+    while !meta.halt {
+        profile.record(&tiny);
+        tiny.step(&mut meta);
+    }
+    let symbols = Symbols::new();
+    let report = profile.report(&tiny, &symbols);
+    assert!(report.starts_with("11 instructions\n"));
+    assert!(report.contains("call 8"));
+    assert_eq!(profile.folded(&symbols), "entry 3\nentry;0x0005 6\nentry;0x0005;0x0008 2\n");
+}
+
+#[test]
+fn bp_goes_through_the_machine_hooks() {
+    let mut tiny = Tiny::new(PROGRAM.to_vec());
+    let mut meta = with(&["bp 3"]);
+    script(&mut tiny, &mut meta).unwrap();
+    assert_eq!(meta.hooks.break_op, Some(3));
+    while !meta.debugging {
+        tiny.step(&mut meta);
+    }
+    assert_eq!((tiny.ip, meta.op_count), (8, 4));
+}
+
+/// a writer the test keeps a handle on after handing it to the recorder
+#[derive(Clone, Default)]
+struct Shared(Rc<RefCell<Vec<u8>>>);
+
+impl io::Write for Shared {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(bytes);
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn the_trace_recorder_reads_any_machine() {
+    let mut tiny = Tiny::new(PROGRAM.to_vec());
+    let mut meta = Meta::new();
+    let out = Shared::default();
+    meta.observer = Some(Box::new(Recorder::new(Box::new(out.clone()))));
+    for _ in 0..4 {
+        tiny.step(&mut meta);
+    }
+    meta.observer = None; // the last line goes out when the recorder is dropped
+    let trace = String::from_utf8(out.0.borrow().clone()).unwrap();
+    let lines: Vec<&str> = trace.lines().map(|line| line.trim_end()).collect();
+    assert_eq!(
+        lines,
+        vec![
+            "0x0000: call 5                   ; push 2 -> 0x0005",
+            "0x0005: inc                      ; r0=1",
+            "0x0006: call 8                   ; push 8 -> 0x0008",
+            "0x0008: ret                      ; pop 8 -> 0x0008",
+        ]
+    );
+}
+
+#[test]
+fn save_writes_the_machine_snapshot() {
+    let path = std::env::temp_dir().join(format!("synacor-machine-{}", std::process::id()));
+    let mut tiny = Tiny::new(PROGRAM.to_vec());
+    tiny.acc = 7;
+    tiny.ip = 5;
+    let mut meta = with(&[&format!("save {}", path.display())]);
+    script(&mut tiny, &mut meta).unwrap();
+    let restored = Tiny::restore(fs::read(&path).unwrap()).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert_eq!((restored.ip, restored.acc, restored.memory), (5, 7, PROGRAM.to_vec()));
+}
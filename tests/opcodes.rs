@@ -0,0 +1,405 @@
+//! every opcode against the architecture spec in `arch-spec`, guest io through a pipe so no terminal is needed; every
+//! back end runs each program and has to end up exactly where the interpreter does
+
+use std::cell::RefCell;
+use std::fmt::Debug;
+use std::rc::Rc;
+use synacor::console::{Pipe, Piped};
+use synacor::debug::profile::Profile;
+use synacor::debug::symbol::Symbols;
+use synacor::debug::{Meta, Reason};
+#[cfg(feature = "jit")]
+use synacor::jit::Jit;
+use synacor::opcode::{self, lookup, Code, Operand};
+use synacor::peephole;
+use synacor::vm::State;
+
+const R0: u16 = 32768;
+const R1: u16 = 32769;
+const R2: u16 = 32770;
+const R7: u16 = 32775;
+
+/// stops runaway programs, nothing here needs more
+const LIMIT: usize = 1000;
+
+/// words of memory every program gets, the rest past its code is zero
+const MEMORY: usize = 64;
+
+/// what gets the first go at every instruction, the interpreter steps whatever it declines
+#[derive(Debug, Clone, Copy)]
+enum Back {
+    Interpreter,
+    Superinstructions,
+    /// compiling every block the first time it is reached, so each instruction runs native
+    #[cfg(feature = "jit")]
+    Jit,
+}
+
+/// a back end's go at the instruction at ip, None to have the interpreter step it
+type Fast = Box<dyn FnMut(&mut State, &mut Meta) -> Option<Code>>;
+
+/// held to the interpreter on every program
+const FAST: &[Back] = &[
+    Back::Superinstructions,
+    #[cfg(feature = "jit")]
+    Back::Jit,
+];
+
+struct Run {
+    state: State,
+    output: Vec<u8>,
+    halted: bool,
+    /// stopped for the debugger, waiting on input or at an unknown opcode
+    debugging: bool,
+    reason: Option<Reason>,
+    instructions: usize,
+}
+
+impl Run {
+    fn word(&self, address: usize) -> u16 {
+        u16::from_le_bytes([self.state.program[address * 2], self.state.program[address * 2 + 1]])
+    }
+
+    /// everything a back end has to agree with the interpreter on
+    fn seen(&self) -> impl PartialEq + Debug + '_ {
+        let state = &self.state;
+        let stopped = (self.halted, self.debugging, self.reason, self.instructions);
+        (&state.program, state.register, &state.stack, state.ip, &self.output, stopped)
+    }
+}
+
+/// run from address 0 with the registers preset on one back end, until halt, the debugger or LIMIT instructions
+fn run_on(back: Back, words: &[u16], registers: [u16; 8], input: &[u8]) -> Run {
+    let mut memory = words.to_vec();
+    memory.resize(MEMORY.max(words.len()), 0);
+    let mut state = State::new(memory.iter().flat_map(|word| word.to_le_bytes()).collect());
+    state.register = registers;
+    let mut meta = Meta::new();
+    let pipe = Rc::new(RefCell::new(Pipe::script(input)));
+    meta.io = Box::new(Piped(pipe.clone()));
+    let mut fast: Fast = match back {
+        Back::Interpreter => Box::new(|_, _| None),
+        Back::Superinstructions => Box::new(peephole::run),
+        #[cfg(feature = "jit")]
+        Back::Jit => {
+            let mut jit = Jit::new().unwrap();
+            jit.hot = 1;
+            Box::new(move |state, meta| jit.run(state, meta))
+        }
+    };
+    while !meta.halt && !meta.debugging && meta.op_count < LIMIT {
+        if fast(&mut state, &mut meta).is_none() {
+            opcode::step(&mut state, &mut meta);
+        }
+    }
+    let output = pipe.borrow().output.clone();
+    Run {
+        state,
+        output,
+        halted: meta.halt,
+        debugging: meta.debugging,
+        reason: meta.reason,
+        instructions: meta.op_count,
+    }
+}
+
+/// the interpreter's run, once every other back end has been checked against it
+fn run_with(words: &[u16], registers: [u16; 8], input: &[u8]) -> Run {
+    let interpreted = run_on(Back::Interpreter, words, registers, input);
+    for back in FAST {
+        assert_eq!(run_on(*back, words, registers, input).seen(), interpreted.seen(), "{:?}", back);
+    }
+    interpreted
+}
+
+fn run(words: &[u16]) -> Run {
+    run_with(words, [0; 8], b"")
+}
+
+#[test]
+fn operand_counts_follow_the_spec() {
+    let spec: [(&str, usize); 22] = [
+        ("halt", 0),
+        ("set", 2),
+        ("push", 1),
+        ("pop", 1),
+        ("eq", 3),
+        ("gt", 3),
+        ("jmp", 1),
+        ("jt", 2),
+        ("jf", 2),
+        ("add", 3),
+        ("mult", 3),
+        ("mod", 3),
+        ("and", 3),
+        ("or", 3),
+        ("not", 2),
+        ("rmem", 2),
+        ("wmem", 2),
+        ("call", 1),
+        ("ret", 0),
+        ("out", 1),
+        ("in", 1),
+        ("noop", 0),
+    ];
+    for (op, (mnemonic, operands)) in spec.iter().enumerate() {
+        let code = lookup(op as u8);
+        assert_eq!(code.mnemonic(), *mnemonic);
+        assert_eq!(code.len(), *operands, "{}", mnemonic);
+        assert_eq!(code.operands().len(), *operands, "{}", mnemonic);
+    }
+    assert_eq!(lookup(22).mnemonic(), "data");
+}
+
+#[test]
+fn destinations_are_registers() {
+    for op in [1, 3, 4, 5, 9, 10, 11, 12, 13, 14, 15, 20] {
+        assert_eq!(lookup(op).operands()[0], Operand::Register, "{}", lookup(op).mnemonic());
+    }
+}
+
+#[test]
+fn spec_sample_program() {
+    // store into r0 the sum of 4 and r1, then output r0
+    let result = run_with(&[9, R0, R1, 4, 19, R0], [0, 61, 0, 0, 0, 0, 0, 0], b"");
+    assert_eq!(result.output, b"A");
+    assert_eq!(result.state.register[0], 65);
+    assert!(result.halted);
+    assert_eq!(result.instructions, 3);
+}
+
+#[test]
+fn halt_stops_before_anything_else() {
+    let result = run(&[0, 19, 65]);
+    assert!(result.halted);
+    assert!(result.output.is_empty());
+    assert_eq!((result.state.ip, result.instructions), (0, 1));
+}
+
+#[test]
+fn set_takes_literals_and_registers() {
+    let result = run(&[1, R0, 1234, 1, R7, R0]);
+    assert_eq!(result.state.register[0], 1234);
+    assert_eq!(result.state.register[7], 1234);
+}
+
+#[test]
+fn push_and_pop_are_last_in_first_out() {
+    let result = run_with(&[2, 10, 2, R1, 3, R0, 3, R2], [0, 20, 0, 0, 0, 0, 0, 0], b"");
+    assert_eq!(&result.state.register[..3], &[20, 20, 10]);
+    assert!(result.state.stack.is_empty());
+}
+
+#[test]
+fn pop_on_an_empty_stack_leaves_the_register_alone() {
+    let result = run_with(&[3, R0, 19, 66], [7, 0, 0, 0, 0, 0, 0, 0], b"");
+    assert_eq!(result.state.register[0], 7);
+    assert_eq!(result.output, b"B");
+    assert!(result.halted);
+}
+
+#[test]
+fn eq_and_gt_write_one_or_zero() {
+    let result = run_with(
+        &[4, R0, 5, 5, 4, R1, R7, 6, 5, R2, R7, 5, 5, 32771, 5, R7],
+        [0, 0, 0, 0, 0, 0, 0, 6],
+        b"",
+    );
+    assert_eq!(&result.state.register[..4], &[1, 1, 1, 0]);
+}
+
+#[test]
+fn jmp_skips_ahead() {
+    let result = run(&[6, 4, 19, 65, 19, 66]);
+    assert_eq!(result.output, b"B");
+}
+
+#[test]
+fn jt_and_jf_branch_on_nonzero_and_zero() {
+    // jt taken, jt not taken, jf taken, jf not taken; A and C are skipped
+    let program = [
+        7, 1, 5, 19, 65, //
+        7, R0, 9, 19, 66, //
+        8, R0, 15, 19, 67, //
+        8, 32767, 19, 19, 68,
+    ];
+    assert_eq!(run(&program).output, b"BD");
+}
+
+#[test]
+fn jumps_through_registers() {
+    let result = run_with(&[6, R0, 19, 65, 19, 66], [4, 0, 0, 0, 0, 0, 0, 0], b"");
+    assert_eq!(result.output, b"B");
+}
+
+#[test]
+fn add_wraps_around() {
+    let result = run_with(&[9, R0, 32758, 15, 9, R1, R1, R1], [0, 32767, 0, 0, 0, 0, 0, 0], b"");
+    assert_eq!(result.state.register[0], 5);
+    assert_eq!(result.state.register[1], 32766);
+}
+
+#[test]
+fn mult_wraps_around() {
+    let result = run_with(&[10, R0, 32767, 32767, 10, R1, R1, 3], [0, 20000, 0, 0, 0, 0, 0, 0], b"");
+    assert_eq!(result.state.register[0], 1);
+    assert_eq!(result.state.register[1], (20000 * 3) % 32768);
+}
+
+#[test]
+fn mod_by_zero_stops_for_the_debugger() {
+    let result = run_with(&[19, 65, 11, R0, 5, R1, 19, 66], [9, 0, 0, 0, 0, 0, 0, 0], b"");
+    assert_eq!(result.output, b"A");
+    assert_eq!(result.reason, Some(Reason::DivideByZero));
+    assert_eq!(result.state.register[0], 9);
+    assert_eq!(result.state.ip, 4);
+}
+
+#[test]
+fn mod_is_the_remainder() {
+    let result = run_with(&[11, R0, 32767, 10, 11, R1, R1, R2], [0, 17, 5, 0, 0, 0, 0, 0], b"");
+    assert_eq!(result.state.register[0], 7);
+    assert_eq!(result.state.register[1], 2);
+}
+
+#[test]
+fn and_and_or_are_bitwise() {
+    let result = run(&[12, R0, 0b1100, 0b1010, 13, R1, 0b1100, 0b1010, 13, R2, 16384, 16383]);
+    assert_eq!(&result.state.register[..3], &[0b1000, 0b1110, 32767]);
+}
+
+#[test]
+fn not_keeps_fifteen_bits() {
+    let result = run_with(&[14, R0, 0, 14, R1, 32767, 14, R2, R7], [0, 0, 0, 0, 0, 0, 0, 0b101], b"");
+    assert_eq!(&result.state.register[..3], &[32767, 0, 32762]);
+}
+
+#[test]
+fn rmem_reads_a_word() {
+    let result = run_with(&[15, R0, 7, 15, R1, R7, 0, 12345, 0, 0, 4321], [0, 0, 0, 0, 0, 0, 0, 10], b"");
+    assert_eq!(result.state.register[0], 12345);
+    assert_eq!(result.state.register[1], 4321);
+}
+
+#[test]
+fn rmem_copies_the_stored_word_without_reading_registers_through_it() {
+    let result = run_with(&[15, R0, 4, 0, R1], [0, 55, 0, 0, 0, 0, 0, 0], b"");
+    assert_eq!(result.state.register[0], R1);
+}
+
+#[test]
+fn wmem_writes_a_word() {
+    let result = run_with(&[16, 20, 999, 16, R7, R0], [31000, 0, 0, 0, 0, 0, 0, 21], b"");
+    assert_eq!(result.word(20), 999);
+    assert_eq!(result.word(21), 31000);
+}
+
+#[test]
+fn wmem_over_code_runs_the_new_code() {
+    // the operand of the out at 3 is patched before it is reached
+    let result = run(&[16, 4, 66, 19, 65]);
+    assert_eq!(result.output, b"B");
+}
+
+#[test]
+fn call_pushes_the_next_address_and_ret_pops_it() {
+    // call 6, out A on return, halt; 6 outputs B and returns
+    let result = run(&[17, 6, 19, 65, 0, 0, 19, 66, 18]);
+    assert_eq!(result.output, b"BA");
+    assert!(result.state.stack.is_empty());
+    assert!(result.halted);
+
+    let result = run(&[17, 3, 0, 0]);
+    assert_eq!(result.state.stack, vec![2]);
+}
+
+#[test]
+fn call_through_a_register() {
+    let result = run_with(&[17, R0, 0, 19, 66, 18], [3, 0, 0, 0, 0, 0, 0, 0], b"");
+    assert_eq!(result.output, b"B");
+}
+
+#[test]
+fn ret_on_an_empty_stack_halts() {
+    let result = run(&[18, 19, 65]);
+    assert!(result.halted);
+    assert!(result.output.is_empty());
+}
+
+#[test]
+fn ret_jumps_to_a_pushed_address() {
+    let result = run(&[2, 5, 18, 19, 65, 19, 66]);
+    assert_eq!(result.output, b"B");
+}
+
+#[test]
+fn out_writes_literals_and_registers() {
+    let result = run_with(&[19, 72, 19, R0, 19, 10], [105, 0, 0, 0, 0, 0, 0, 0], b"");
+    assert_eq!(result.output, b"Hi\n");
+}
+
+#[test]
+fn in_reads_a_character_at_a_time() {
+    let result = run_with(&[20, R0, 20, R1, 20, R2], [0; 8], b"ok\n");
+    assert_eq!(&result.state.register[..3], &[b'o' as u16, b'k' as u16, b'\n' as u16]);
+    assert!(result.halted);
+}
+
+#[test]
+fn in_without_input_waits_in_place() {
+    let result = run(&[20, R0, 19, 65]);
+    assert!(result.debugging);
+    assert_eq!(result.reason, Some(Reason::Input));
+    assert!(!result.halted);
+    assert_eq!(result.state.ip, 0);
+    assert!(result.output.is_empty());
+}
+
+#[test]
+fn noop_moves_on() {
+    let result = run(&[21, 21, 19, 65]);
+    assert_eq!(result.output, b"A");
+    assert_eq!((result.state.ip, result.instructions), (8, 4));
+}
+
+#[test]
+fn unknown_opcodes_stop_for_the_debugger() {
+    let result = run(&[19, 65, 22, 19, 66]);
+    assert_eq!(result.output, b"A");
+    assert!(result.debugging);
+    assert_eq!(result.reason, Some(Reason::UnknownOpcode));
+    assert!(!result.halted);
+    assert_eq!(result.state.ip, 4);
+}
+
+#[test]
+fn the_profiler_does_not_count_data_as_opcodes() {
+    // 0x0113 reads as out by its low byte, but an opcode is a whole word
+    let mut state = State::new([21u16, 0x0113].iter().flat_map(|word| word.to_le_bytes()).collect());
+    let mut meta = Meta::new();
+    let mut profile = Profile::new();
+    while !meta.debugging {
+        profile.record(&state);
+        opcode::step(&mut state, &mut meta);
+    }
+    let report = profile.report(&state, &Symbols::new());
+    let opcodes = &report[report.find("\nopcodes\n").unwrap()..report.find("\ntop functions").unwrap()];
+    assert!(opcodes.contains("noop"));
+    assert!(!opcodes.contains("out"));
+}
+
+#[test]
+fn operands_past_the_registers_wrap_to_literals() {
+    // 32776 and up are invalid, the machine reads them modulo 32768
+    let result = run(&[9, R0, 32776, 1, 19, 32833]);
+    assert_eq!(result.state.register[0], 9);
+    assert_eq!(result.output, b"A");
+}
+
+#[test]
+fn writes_to_a_register_that_does_not_exist_are_dropped() {
+    let result = run(&[1, 32776, 5, 9, 40000, 1, 1, 19, 65]);
+    assert_eq!(result.state.register, [0; 8]);
+    assert_eq!(result.output, b"A");
+    assert!(result.halted);
+}
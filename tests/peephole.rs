@@ -0,0 +1,19 @@
+//! superinstructions have to leave the machine exactly where stepping one instruction at a time would
+
+mod common;
+
+use common::{bytes, challenge, compare, PATCHED};
+use synacor::peephole;
+
+#[test]
+fn writes_under_a_superinstruction_deoptimize_it() {
+    let (outcome, state) = compare(bytes(&PATCHED), peephole::run);
+    assert_eq!(outcome.output, b"AAB");
+    assert_eq!(outcome.register[0], 50);
+    assert_eq!(state.cache.deoptimized, 1);
+}
+
+#[test]
+fn challenge_self_test() {
+    compare(challenge(), peephole::run);
+}
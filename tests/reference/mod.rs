@@ -0,0 +1,178 @@
+//! a deliberately plain Synacor interpreter straight from `arch-spec`, word addressed, no caching, no fusing,
+//! nothing shared with the crate; the crate's own choices where the spec is silent are copied on purpose
+
+use std::collections::VecDeque;
+
+/// words of memory, 15-bit addresses
+pub const MEMORY: usize = 32768;
+
+/// what a step left the machine doing
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Status {
+    Running,
+    /// halt, or ret on an empty stack
+    Halted,
+    /// in with no input left, ip stays on the in
+    Waiting,
+    /// an opcode past 21, ip stays on it
+    Unknown,
+    /// mod by zero, ip stays on it
+    DivideByZero,
+}
+
+pub struct Machine {
+    pub memory: Vec<u16>,
+    pub registers: [u16; 8],
+    pub stack: Vec<u16>,
+    /// word address
+    pub ip: usize,
+    pub input: VecDeque<u8>,
+    pub output: Vec<u8>,
+    pub steps: usize,
+}
+
+impl Machine {
+    pub fn new(program: &[u16]) -> Machine {
+        let mut memory = program.to_vec();
+        memory.resize(MEMORY, 0);
+        Machine {
+            memory,
+            registers: [0; 8],
+            stack: Vec::new(),
+            ip: 0,
+            input: VecDeque::new(),
+            output: Vec::new(),
+            steps: 0,
+        }
+    }
+
+    fn word(&self, offset: usize) -> u16 {
+        self.memory.get(self.ip + offset).copied().unwrap_or(0)
+    }
+
+    /// an operand's value, words past the registers are taken modulo 32768 like the crate does
+    fn value(&self, raw: u16) -> u16 {
+        match raw {
+            32768..=32775 => self.registers[raw as usize - 32768],
+            _ => raw % 32768,
+        }
+    }
+
+    /// a destination register, writes to anything past r7 are dropped like the crate does
+    fn set(&mut self, raw: u16, value: u16) {
+        let index = (raw % 32768) as usize;
+        if index < 8 {
+            self.registers[index] = value;
+        }
+    }
+
+    /// run one instruction
+    pub fn step(&mut self) -> Status {
+        let op = self.word(0);
+        let a = self.word(1);
+        let b = self.word(2);
+        let c = self.word(3);
+        // the crate counts halts, waits and faults as instructions too
+        self.steps += 1;
+        if op == 11 && self.value(c) == 0 {
+            return Status::DivideByZero;
+        }
+        let at = self.ip;
+        let next = |operands: usize| at + 1 + operands;
+        match op {
+            0 => return Status::Halted,
+            1 => {
+                let b = self.value(b);
+                self.set(a, b);
+                self.ip = next(2);
+            }
+            2 => {
+                self.stack.push(self.value(a));
+                self.ip = next(1);
+            }
+            3 => {
+                // the spec calls an empty stack an error, the crate carries on without writing
+                if let Some(top) = self.stack.pop() {
+                    self.set(a, top);
+                }
+                self.ip = next(1);
+            }
+            4 => {
+                let result = self.value(b) == self.value(c);
+                self.set(a, result as u16);
+                self.ip = next(3);
+            }
+            5 => {
+                let result = self.value(b) > self.value(c);
+                self.set(a, result as u16);
+                self.ip = next(3);
+            }
+            6 => self.ip = self.value(a) as usize,
+            7 => self.ip = if self.value(a) != 0 { self.value(b) as usize } else { next(2) },
+            8 => self.ip = if self.value(a) == 0 { self.value(b) as usize } else { next(2) },
+            9 => {
+                let sum = (self.value(b) as u32 + self.value(c) as u32) % 32768;
+                self.set(a, sum as u16);
+                self.ip = next(3);
+            }
+            10 => {
+                let product = (self.value(b) as u32 * self.value(c) as u32) % 32768;
+                self.set(a, product as u16);
+                self.ip = next(3);
+            }
+            11 => {
+                let remainder = self.value(b) % self.value(c) % 32768;
+                self.set(a, remainder);
+                self.ip = next(3);
+            }
+            12 => {
+                let and = (self.value(b) & self.value(c)) % 32768;
+                self.set(a, and);
+                self.ip = next(3);
+            }
+            13 => {
+                let or = (self.value(b) | self.value(c)) % 32768;
+                self.set(a, or);
+                self.ip = next(3);
+            }
+            14 => {
+                let not = !self.value(b) & 0x7FFF;
+                self.set(a, not);
+                self.ip = next(2);
+            }
+            15 => {
+                // a register can hold any word rmem found, addresses keep to the 15-bit space
+                let word = self.memory[self.value(b) as usize % MEMORY];
+                self.set(a, word);
+                self.ip = next(2);
+            }
+            16 => {
+                let address = self.value(a) as usize % MEMORY;
+                self.memory[address] = self.value(b);
+                self.ip = next(2);
+            }
+            17 => {
+                self.stack.push(next(1) as u16);
+                self.ip = self.value(a) as usize;
+            }
+            18 => match self.stack.pop() {
+                Some(address) => self.ip = address as usize,
+                None => return Status::Halted,
+            },
+            19 => {
+                self.output.push(self.value(a) as u8);
+                self.ip = next(1);
+            }
+            20 => match self.input.pop_front() {
+                Some(byte) => {
+                    self.set(a, byte as u16);
+                    self.ip = next(1);
+                }
+                None => return Status::Waiting,
+            },
+            21 => self.ip = next(0),
+            _ => return Status::Unknown,
+        }
+        Status::Running
+    }
+}
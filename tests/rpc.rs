@@ -0,0 +1,148 @@
+//! drives `synacor --rpc` the way a control client would, over a real unix socket
+
+use serde_json::{json, Value};
+use std::env;
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::Duration;
+
+/// jmp 0 forever; out 'B'; noop; out 'A'; out '\n'; halt
+const PROGRAM: [u16; 10] = [6, 0, 19, 66, 21, 19, 65, 19, 10, 0];
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_PARAMS: i64 = -32602;
+
+struct Client {
+    reader: BufReader<UnixStream>,
+    writer: UnixStream,
+    id: u64,
+    notifications: Vec<Value>,
+}
+
+impl Client {
+    fn send(&mut self, line: &str) {
+        writeln!(self.writer, "{}", line).unwrap();
+    }
+
+    fn receive(&mut self) -> Value {
+        let mut line = String::new();
+        assert!(self.reader.read_line(&mut line).unwrap() > 0, "server hung up");
+        serde_json::from_str(&line).unwrap()
+    }
+
+    /// the next response, keeping the notifications that came before it
+    fn response(&mut self) -> Value {
+        loop {
+            let message = self.receive();
+            if message.get("id").is_some() {
+                return message;
+            }
+            self.notifications.push(message);
+        }
+    }
+
+    fn call(&mut self, method: &str, params: Value) -> Result<Value, Value> {
+        self.id += 1;
+        self.send(&json!({ "jsonrpc": "2.0", "id": self.id, "method": method, "params": params }).to_string());
+        let response = self.response();
+        assert_eq!(response["id"], json!(self.id));
+        match response.get("error") {
+            Some(error) => Err(error.clone()),
+            None => Ok(response["result"].clone()),
+        }
+    }
+
+    /// wait for a notification, keeping the ones that came before it
+    fn notification(&mut self, method: &str) -> Value {
+        if let Some(i) = self.notifications.iter().position(|message| message["method"] == method) {
+            return self.notifications.remove(i)["params"].clone();
+        }
+        loop {
+            let message = self.receive();
+            if message["method"] == method {
+                return message["params"].clone();
+            }
+            self.notifications.push(message);
+        }
+    }
+}
+
+fn code(error: Value) -> i64 {
+    error["code"].as_i64().unwrap()
+}
+
+#[test]
+fn session() {
+    let id = std::process::id();
+    let path = env::temp_dir().join(format!("synacor-rpc-{}.bin", id));
+    let socket = env::temp_dir().join(format!("synacor-rpc-{}.sock", id));
+    fs::write(&path, PROGRAM.iter().flat_map(|word| word.to_le_bytes()).collect::<Vec<u8>>()).unwrap();
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_synacor"))
+        .args(["--no-init", "--smc", "--rpc"])
+        .args([&socket, &path])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdout = BufReader::new(child.stdout.take().unwrap());
+    let mut line = String::new();
+    stdout.read_line(&mut line).unwrap();
+    assert!(line.starts_with("rpc: listening on "), "{}", line);
+    // keep draining so the server never blocks on a full pipe, the rest is checked at the end
+    let rest = thread::spawn(move || {
+        let mut text = String::new();
+        stdout.read_to_string(&mut text).unwrap();
+        text
+    });
+
+    let stream = UnixStream::connect(&socket).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+    let mut client = Client {
+        reader: BufReader::new(stream.try_clone().unwrap()),
+        writer: stream,
+        id: 0,
+        notifications: Vec::new(),
+    };
+
+    // garbage gets a parse error with a null id, a request without an id gets nothing back
+    client.send("{ not json");
+    let response = client.response();
+    assert_eq!(response["id"], Value::Null);
+    assert_eq!(code(response["error"].clone()), PARSE_ERROR);
+    client.send(r#"{ "jsonrpc": "2.0", "method": "pause" }"#);
+    assert_eq!(client.notification("stopped")["reason"], json!("pause"));
+    assert_eq!(client.call("status", json!({})).unwrap()["paused"], json!(true));
+
+    // a write off the end or with a word that does not fit is refused whole
+    let error = client.call("writeMemory", json!({ "address": 0xfffe, "words": [1, 2] })).unwrap_err();
+    assert_eq!(code(error), INVALID_PARAMS);
+    let error = client.call("writeMemory", json!({ "address": 4, "words": [1, 70000] })).unwrap_err();
+    assert_eq!(code(error), INVALID_PARAMS);
+    let memory = client.call("readMemory", json!({ "address": 4, "count": 2 })).unwrap();
+    assert_eq!(memory["words"], json!([19, 66]));
+
+    // point the loop at the out, the jmp it already decoded has to go
+    client.call("writeMemory", json!({ "address": 2, "words": [2] })).unwrap();
+    client.call("setBreakpoint", json!({ "address": 10 })).unwrap();
+    let error = client.call("step", json!({ "count": 1u64 << 40 })).unwrap_err();
+    assert_eq!(code(error), INVALID_PARAMS);
+    assert_eq!(client.call("step", json!({ "count": 100 })).unwrap()["ip"], json!(10));
+    let stopped = client.notification("stopped");
+    assert_eq!((&stopped["reason"], &stopped["ip"]), (&json!("breakpoint"), &json!(10)));
+    assert_eq!(client.notification("output")["text"], json!("B"));
+
+    client.call("clearBreakpoint", json!({ "address": 10 })).unwrap();
+    client.call("resume", json!({})).unwrap();
+    assert_eq!(client.notification("output")["text"], json!("A\n"));
+    assert_eq!(client.notification("halted")["ip"], json!(18));
+
+    assert!(child.wait().unwrap().success());
+    let output = rest.join().unwrap();
+    assert!(output.contains("1 writes into executed or decoded code"), "{}", output);
+    assert!(!socket.exists());
+    fs::remove_file(path).unwrap();
+}
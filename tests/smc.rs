@@ -0,0 +1,91 @@
+//! `--smc` on real programs: which wmem writes count as rewriting code and how the report groups them
+
+use std::cell::RefCell;
+use std::rc::Rc;
+use synacor::console::{Pipe, Piped};
+use synacor::debug::smc::Smc;
+use synacor::debug::symbol::Symbols;
+use synacor::debug::{Meta, Reason};
+use synacor::opcode;
+use synacor::vm::State;
+
+fn machine(words: &[u16]) -> (State, Meta) {
+    let state = State::new(words.iter().flat_map(|word| word.to_le_bytes()).collect());
+    let mut meta: Meta = Meta::new();
+    meta.io = Box::new(Piped(Rc::new(RefCell::new(Pipe::default()))));
+    let mut smc = Smc::new();
+    smc.log = false;
+    meta.hooks.smc = Some(smc);
+    (state, meta)
+}
+
+fn run(state: &mut State, meta: &mut Meta) {
+    while !meta.halt && !meta.debugging {
+        opcode::step(state, meta);
+    }
+}
+
+fn smc(meta: &Meta) -> &Smc {
+    meta.hooks.smc.as_ref().unwrap()
+}
+
+#[test]
+fn a_write_into_executed_code_is_reported() {
+    // out 'A'; wmem 1 66 patches the out that already ran; halt
+    let (mut state, mut meta) = machine(&[19, 65, 16, 1, 66, 0]);
+    run(&mut state, &mut meta);
+    let modifications = &smc(&meta).modifications;
+    assert_eq!(modifications.len(), 1);
+    let modification = &modifications[0];
+    assert_eq!((modification.writer, modification.address, modification.instruction), (4, 2, 0));
+    assert!(modification.executed);
+    assert_eq!((modification.old.as_str(), modification.new.as_str()), ("out 'A'", "out 'B'"));
+}
+
+#[test]
+fn stop_opens_the_debugger_after_the_write() {
+    let (mut state, mut meta) = machine(&[19, 65, 16, 1, 66, 0]);
+    meta.hooks.smc.as_mut().unwrap().stop = true;
+    run(&mut state, &mut meta);
+    assert!(!meta.halt);
+    assert_eq!(meta.reason, Some(Reason::CodeWrite));
+    assert_eq!(state.ip, 10);
+}
+
+#[test]
+fn a_write_into_decoded_code_that_never_ran_is_reported() {
+    // wmem 6 66 patches the operand of the out at word 5; halt; noop; out 'A'
+    let (mut state, mut meta) = machine(&[16, 6, 66, 0, 21, 19, 65]);
+    let program = state.program.clone();
+    state.cache.fetch(&program, 10);
+    assert_eq!(state.cache.covering(12), Some(10));
+    run(&mut state, &mut meta);
+    let modifications = &smc(&meta).modifications;
+    assert_eq!(modifications.len(), 1);
+    assert_eq!((modifications[0].address, modifications[0].instruction), (12, 10));
+    assert!(!modifications[0].executed);
+    assert_eq!(modifications[0].new, "out 'B'");
+}
+
+#[test]
+fn writes_into_data_or_of_the_same_word_are_not_reported() {
+    // wmem 20 5 into zeros past the code; wmem 5 20 rewrites its own operand with what is already there; halt
+    let (mut state, mut meta) = machine(&[16, 20, 5, 16, 5, 20, 0]);
+    run(&mut state, &mut meta);
+    assert!(meta.halt);
+    assert!(smc(&meta).modifications.is_empty());
+    assert_eq!(smc(&meta).report(&Symbols::new()), "no writes into executed or decoded code\n");
+}
+
+#[test]
+fn the_report_groups_neighbouring_words_into_regions() {
+    // four noops, then wmem 0 1; wmem 1 1; wmem 1 2; wmem 3 1; halt
+    let (mut state, mut meta) = machine(&[21, 21, 21, 21, 16, 0, 1, 16, 1, 1, 16, 1, 2, 16, 3, 1, 0]);
+    run(&mut state, &mut meta);
+    assert_eq!(
+        smc(&meta).report(&Symbols::new()),
+        "4 writes into executed or decoded code, 2 region(s)\n\
+         \x20 0x0000..=0x0002 (2 words, 3 writes) by 0x0008, 0x000e, 0x0014\n\
+         \x20 0x0006..=0x0006 (1 words, 1 writes) by 0x001a\n"
+    );
+}
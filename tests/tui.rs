@@ -0,0 +1,133 @@
+//! the full screen view driven by keys and drawn on ratatui's test backend, no terminal needed
+
+use ratatui::backend::TestBackend;
+use ratatui::buffer::Buffer;
+use ratatui::crossterm::event::{KeyCode, KeyEvent};
+use ratatui::style::Color;
+use ratatui::Terminal;
+use std::cell::RefCell;
+use std::rc::Rc;
+use synacor::console::{Pipe, Piped};
+use synacor::debug::{Meta, Resume};
+use synacor::tui::Screen;
+use synacor::vm::State;
+
+const R0: u16 = 32768;
+
+/// set r0 5; out 'A'; halt
+const PROGRAM: [u16; 6] = [1, R0, 5, 19, 65, 0];
+
+struct View {
+    state: State,
+    meta: Meta,
+    pipe: Rc<RefCell<Pipe>>,
+    screen: Screen,
+    terminal: Terminal<TestBackend>,
+}
+
+impl View {
+    fn new() -> View {
+        let state = State::new(PROGRAM.iter().flat_map(|word| word.to_le_bytes()).collect());
+        let mut meta = Meta::new();
+        let pipe = Rc::new(RefCell::new(Pipe::default()));
+        meta.io = Box::new(Piped(pipe.clone()));
+        let screen = Screen::new(&state, pipe.clone());
+        View {
+            state,
+            meta,
+            pipe,
+            screen,
+            terminal: Terminal::new(TestBackend::new(120, 30)).unwrap(),
+        }
+    }
+
+    fn keys(&mut self, codes: &[KeyCode]) {
+        for code in codes {
+            self.screen.key(&mut self.state, &mut self.meta, KeyEvent::from(*code));
+        }
+    }
+
+    fn typed(&mut self, text: &str) {
+        let codes: Vec<KeyCode> = text.chars().map(KeyCode::Char).collect();
+        self.keys(&codes);
+    }
+
+    fn draw(&mut self) -> &Buffer {
+        let View { terminal, screen, state, meta, .. } = self;
+        terminal.draw(|frame| screen.draw(frame, state, meta)).unwrap();
+        terminal.backend().buffer()
+    }
+}
+
+/// the row and column where text starts on screen
+fn find(buffer: &Buffer, text: &str) -> Option<(u16, u16)> {
+    (0..buffer.area.height).find_map(|y| {
+        let row: String = (0..buffer.area.width).map(|x| buffer[(x, y)].symbol()).collect();
+        // every cell here is one column wide, so a char index is a column
+        let column = row.find(text)?;
+        Some((row[..column].chars().count() as u16, y))
+    })
+}
+
+#[test]
+fn registers_changed_since_the_last_resume_are_highlighted() {
+    let mut view = View::new();
+    view.keys(&[KeyCode::Char('s')]);
+    assert_eq!(view.meta.resume, Resume::Step(1));
+    let View { screen, state, meta, .. } = &mut view;
+    screen.run(state, meta);
+    assert_eq!((view.state.ip, view.state.register[0]), (6, 5));
+
+    let buffer = view.draw().clone();
+    let (x, y) = find(&buffer, "r0 =     5").expect("no r0 line");
+    assert_eq!(buffer[(x, y)].fg, Color::Yellow);
+    let (x, y) = find(&buffer, "r1 =     0").expect("no r1 line");
+    assert_ne!(buffer[(x, y)].fg, Color::Yellow);
+
+    // the next resume takes the current values as the baseline
+    view.keys(&[KeyCode::Char('s')]);
+    let buffer = view.draw().clone();
+    let (x, y) = find(&buffer, "r0 =     5").unwrap();
+    assert_ne!(buffer[(x, y)].fg, Color::Yellow);
+}
+
+#[test]
+fn keys_toggle_breakpoints_at_the_cursor() {
+    let mut view = View::new();
+    view.keys(&[KeyCode::Down, KeyCode::Char('b')]);
+    assert_eq!(view.meta.breakpoints, vec![6]);
+    assert!(find(view.draw(), "breakpoint at").is_some());
+    view.keys(&[KeyCode::Char('b')]);
+    assert!(view.meta.breakpoints.is_empty());
+    assert!(find(view.draw(), "cleared breakpoint at").is_some());
+}
+
+#[test]
+fn goto_moves_the_memory_pane() {
+    let mut view = View::new();
+    view.keys(&[KeyCode::Char('g')]);
+    view.typed("0x41");
+    assert!(find(view.draw(), "goto address or symbol: 0x41_").is_some());
+    view.keys(&[KeyCode::Enter]);
+    assert!(find(view.draw(), "0x0040: ").is_some());
+
+    view.keys(&[KeyCode::Char('g')]);
+    view.typed("nowhere");
+    view.keys(&[KeyCode::Enter]);
+    assert!(find(view.draw(), "not an address or symbol: nowhere").is_some());
+}
+
+#[test]
+fn typed_input_reaches_the_guest_and_esc_drops_it() {
+    let mut view = View::new();
+    view.keys(&[KeyCode::Char('i')]);
+    view.typed("look");
+    view.keys(&[KeyCode::Backspace, KeyCode::Enter]);
+    assert_eq!(view.pipe.borrow().input.iter().copied().collect::<Vec<u8>>(), b"loo\n");
+    assert!(find(view.draw(), "loo").is_some());
+
+    view.keys(&[KeyCode::Char('i')]);
+    view.typed("never");
+    view.keys(&[KeyCode::Esc]);
+    assert_eq!(view.pipe.borrow().input.len(), 4);
+}